@@ -1,13 +1,20 @@
-use crate::instruction::{Instruction, LabeledInstruction, MemoryAccessMode, Reference};
-use dot_writer::{Attributes, Color, DotWriter, Style};
+use crate::instruction::{
+    FenceScope, Instruction, LabeledInstruction, MemoryAccessMode, Reference,
+};
+use dot_writer::{Attributes, Color, DotWriter, Scope, Shape, Style};
 use rand::Rng;
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Display;
 use std::rc::Rc;
 
 type PropagateId = String;
 
+/// Id of a node, as given by `InstructionNode::id`. Unique among the nodes in
+/// the graph at the same time.
+pub type NodeId = String;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Propagate {
     pub associated_write: LabeledInstruction,
@@ -51,16 +58,11 @@ impl NodeType {
     pub fn to_dot(&self) -> String {
         match self {
             Self::Instruction(instruction) => {
-                format!(
-                    "T{}Xinstr{}",
-                    instruction.thread_id.to_string(),
-                    instruction.line_index.to_string()
-                )
+                format!("T{}Xinstr{}", instruction.thread_id, instruction.line_index)
             }
             Self::Propagate(propagate) => format!(
                 "T{}Xprop{}",
-                propagate.associated_write.thread_id.to_string(),
-                propagate.associated_write.line_index.to_string()
+                propagate.associated_write.thread_id, propagate.associated_write.line_index
             ),
         }
     }
@@ -71,17 +73,24 @@ impl NodeType {
             Self::Propagate(propagate) => propagate.associated_write.label.clone(),
         }
     }
+
+    /// Location the node writes: the instruction's write location, or the
+    /// location a propagate commits to.
+    pub fn write_location(&self) -> Option<&Reference> {
+        match self {
+            Self::Instruction(instruction) => instruction.instruction.write_location(),
+            Self::Propagate(propagate) => Some(&propagate.to_location),
+        }
+    }
 }
 
 impl Display for NodeType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            NodeType::Instruction(instruction) => write!(f, "{}", instruction.to_string()),
-            NodeType::Propagate(propagate) => write!(
-                f,
-                "Propagate for write ({})",
-                propagate.associated_write.to_string()
-            ),
+            NodeType::Instruction(instruction) => write!(f, "{}", instruction),
+            NodeType::Propagate(propagate) => {
+                write!(f, "Propagate for write ({})", propagate.associated_write)
+            }
         }
     }
 }
@@ -91,6 +100,10 @@ pub struct InstructionNode {
     pub instruction: NodeType,
     pub depends_on: Vec<Rc<RefCell<InstructionNode>>>,
     pub depends_on_me: Vec<Rc<RefCell<InstructionNode>>>,
+    /// Tells apart the nodes of one instruction in the graph at the same
+    /// time, e.g. a loop iteration's store and the still pending propagate
+    /// of the previous iteration's one. The first node is instance 0.
+    pub instance: usize,
 }
 
 impl InstructionNode {
@@ -99,6 +112,16 @@ impl InstructionNode {
             instruction: NodeType::Instruction(instruction),
             depends_on: Vec::new(),
             depends_on_me: Vec::new(),
+            instance: 0,
+        }))
+    }
+
+    pub(crate) fn detached(instruction: NodeType) -> Rc<RefCell<InstructionNode>> {
+        Rc::new(RefCell::new(Self {
+            instruction,
+            depends_on: Vec::new(),
+            depends_on_me: Vec::new(),
+            instance: 0,
         }))
     }
 
@@ -110,16 +133,26 @@ impl InstructionNode {
             instruction: NodeType::Propagate(Propagate::new(write, to_location)),
             depends_on: Vec::new(),
             depends_on_me: Vec::new(),
+            instance: 0,
         }))
     }
 
+    /// Id of the instruction, suffixed with `@<instance>` for every instance
+    /// after the first.
+    pub fn id(&self) -> String {
+        match self.instance {
+            0 => self.instruction.id(),
+            instance => format!("{}@{}", self.instruction.id(), instance),
+        }
+    }
+
     pub fn add_dependency(from: Rc<RefCell<InstructionNode>>, to: Rc<RefCell<InstructionNode>>) {
         if from
             .borrow()
             .depends_on
             .clone()
             .into_iter()
-            .any(|x| x.borrow().instruction.id() == to.borrow().instruction.id())
+            .any(|x| x.borrow().id() == to.borrow().id())
         {
             return;
         }
@@ -129,47 +162,478 @@ impl InstructionNode {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub enum GraphError {
+    NodeNotFound(String),
+    NodeHasDependencies(String),
+    /// A propagate the removed node can't have: one for a location it doesn't
+    /// write, or a second one for the same location.
+    UnexpectedPropagate(String),
+    /// Ids of the nodes on a cycle, see `DependencyGraph::detect_cycle`.
+    Cycle(Vec<String>),
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::NodeNotFound(id) => write!(f, "Node {} is not in the graph", id),
+            GraphError::NodeHasDependencies(id) => {
+                write!(f, "Node {} still has pending dependencies", id)
+            }
+            GraphError::UnexpectedPropagate(id) => {
+                write!(f, "Unexpected propagate {}", id)
+            }
+            GraphError::Cycle(ids) => write!(f, "Cyclic dependency graph: {}", ids.join(" -> ")),
+        }
+    }
+}
+
+fn is_release(am: MemoryAccessMode) -> bool {
+    matches!(
+        am,
+        MemoryAccessMode::Rel | MemoryAccessMode::RelAcq | MemoryAccessMode::SeqCst
+    )
+}
+
+/// A message passing idiom: a store of the flag in one thread, a load of it
+/// in another, and the data the storing thread writes before the flag and
+/// the loading thread reads after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MpReport {
+    pub store: String,
+    pub load: String,
+    pub flag: String,
+    pub data: Vec<String>,
+    /// Whether the edges order every data write before the flag store and
+    /// every data read after the flag load, so a load that sees the flag
+    /// also sees the data.
+    pub synchronized: bool,
+}
+
+/// What `DependencyGraph::to_adjacency` tells about a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    pub instruction: NodeType,
+    pub instance: usize,
+}
+
+/// Transitive closure of the `depends_on` edges of a graph, computed once for
+/// repeated queries. Nodes are named by `InstructionNode::id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HappensBefore {
+    predecessors: HashMap<String, HashSet<String>>,
+}
+
+impl HappensBefore {
+    /// Whether `a` happens before `b`: `b` waits for `a`, directly or through
+    /// other nodes.
+    pub fn happens_before(&self, a: &str, b: &str) -> bool {
+        self.predecessors
+            .get(b)
+            .is_some_and(|predecessors| predecessors.contains(a))
+    }
+}
+
 pub struct DependencyGraph {
-    pub nodes: Vec<Rc<RefCell<InstructionNode>>>,
+    nodes: Vec<Rc<RefCell<InstructionNode>>>,
+    /// Number of nodes added before each node, which orders `nodes`.
+    added: HashMap<*const RefCell<InstructionNode>, usize>,
+    /// The leaves as of the last removal and the nodes added since, which
+    /// may have got dependencies, by `added`. Every leaf is among them.
+    leaves: BTreeMap<usize, Rc<RefCell<InstructionNode>>>,
+    additions: usize,
+    /// Instance the next node of each instruction gets and the number of
+    /// its nodes pending, by `NodeType::id`. An instruction with no node
+    /// pending has no entry, so its next node is instance 0 again.
+    instances: HashMap<String, (usize, usize)>,
+}
+
+impl Default for DependencyGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for DependencyGraph {
+    /// Deep copy of the graph: every node is reallocated and the edges are
+    /// rewired to the copies, so the clone shares no `RefCell` with `self`.
+    fn clone(&self) -> Self {
+        let copies: Vec<Rc<RefCell<InstructionNode>>> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let copy = InstructionNode::detached(node.borrow().instruction.clone());
+                copy.borrow_mut().instance = node.borrow().instance;
+                copy
+            })
+            .collect();
+        let index_of: HashMap<*const RefCell<InstructionNode>, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (Rc::as_ptr(node), index))
+            .collect();
+        let remap = |edges: &Vec<Rc<RefCell<InstructionNode>>>| {
+            edges
+                .iter()
+                .filter_map(|edge| index_of.get(&Rc::as_ptr(edge)))
+                .map(|index| copies[*index].clone())
+                .collect::<Vec<_>>()
+        };
+        for (node, copy) in self.nodes.iter().zip(copies.iter()) {
+            let mut copy = copy.borrow_mut();
+            copy.depends_on = remap(&node.borrow().depends_on);
+            copy.depends_on_me = remap(&node.borrow().depends_on_me);
+        }
+        let mut graph = Self::from_nodes(copies);
+        graph.instances = self.instances.clone();
+        graph
+    }
+}
+
+/// Sample edges of the `dot` legend: whether the waiting node and the
+/// awaited one are propagates, and what the edge means.
+const LEGEND_EDGES: [(bool, bool, &str); 3] = [
+    (
+        false,
+        false,
+        "waits for an earlier instruction of its thread",
+    ),
+    (false, true, "waits for the write to reach memory"),
+    (
+        true,
+        false,
+        "leaves the store buffer after an earlier write",
+    ),
+];
+
+/// Style of an edge from a node to one it waits for, in `dot`, by whether
+/// each is a propagate. Edges between instructions keep the solid default.
+fn dot_edge_style(from_propagate: bool, to_propagate: bool) -> Option<Style> {
+    match (from_propagate, to_propagate) {
+        (true, _) => Some(Style::Dotted),
+        (false, true) => Some(Style::Dashed),
+        (false, false) => None,
+    }
+}
+
+/// Writes the legend cluster of `DependencyGraph::to_dot`: a sample of both
+/// node kinds, then a sample of every edge kind.
+fn dot_legend(digraph: &mut Scope) {
+    let mut cluster = digraph.cluster();
+    cluster.set_label("Legend");
+    cluster.set_style(Style::Dashed);
+    cluster
+        .node_attributes()
+        .set_style(Style::Filled)
+        .set_color(Color::LightGrey);
+    fn sample_node(cluster: &mut Scope, id: &str, is_propagate: bool) {
+        let mut node = cluster.node_named(id);
+        match is_propagate {
+            true => node.set_label("propagate").set_shape(Shape::Rectangle),
+            false => node.set_label("instruction"),
+        };
+    }
+    sample_node(&mut cluster, "legend_instruction", false);
+    sample_node(&mut cluster, "legend_propagate", true);
+    for (index, (from_propagate, to_propagate, meaning)) in LEGEND_EDGES.into_iter().enumerate() {
+        let from = format!("legend_edge{}_from", index);
+        let to = format!("legend_edge{}_to", index);
+        sample_node(&mut cluster, &from, from_propagate);
+        sample_node(&mut cluster, &to, to_propagate);
+        let mut edge = cluster.edge(from, to).attributes();
+        edge.set_label(meaning);
+        if let Some(style) = dot_edge_style(from_propagate, to_propagate) {
+            edge.set_style(style);
+        }
+    }
+}
+
+/// Instruction of an instruction node.
+fn instruction_of(node: &Rc<RefCell<InstructionNode>>) -> Instruction {
+    match &node.borrow().instruction {
+        NodeType::Instruction(instruction) => instruction.instruction.clone(),
+        NodeType::Propagate(_) => unreachable!("Propagates are never ordered by line"),
+    }
+}
+
+/// The instruction nodes of one thread ordered by line, with the positions
+/// of the ones accessing each memory location.
+struct ThreadNodes {
+    nodes: Vec<Rc<RefCell<InstructionNode>>>,
+    lines: Vec<usize>,
+    by_location: HashMap<Reference, Vec<usize>>,
+}
+
+impl ThreadNodes {
+    fn new(mut nodes: Vec<Rc<RefCell<InstructionNode>>>) -> Self {
+        let line = |node: &Rc<RefCell<InstructionNode>>| match &node.borrow().instruction {
+            NodeType::Instruction(instruction) => instruction.line_index,
+            NodeType::Propagate(_) => unreachable!("Propagates are never ordered by line"),
+        };
+        nodes.sort_by_key(line);
+        let lines = nodes.iter().map(line).collect();
+        let mut by_location: HashMap<Reference, Vec<usize>> = HashMap::new();
+        for (position, node) in nodes.iter().enumerate() {
+            let instruction = instruction_of(node);
+            let locations = instruction.read_locations();
+            let locations = locations.into_iter().chain(instruction.write_locations());
+            for location in locations.collect::<HashSet<_>>() {
+                by_location
+                    .entry(location.clone())
+                    .or_default()
+                    .push(position);
+            }
+        }
+        Self {
+            nodes,
+            lines,
+            by_location,
+        }
+    }
+
+    /// Number of nodes on lines before `line`.
+    fn before(&self, line: usize) -> usize {
+        self.lines.partition_point(|other| *other < line)
+    }
+}
+
+/// Where the nodes a node is ordered against are looked up: the earlier and
+/// later instructions of its thread.
+enum Lookup<'a> {
+    /// Searched for in the whole graph.
+    Graph(&'a DependencyGraph),
+    /// Indexed once for all nodes of the thread.
+    Thread(&'a ThreadNodes),
+    /// Only the given node, for the edges it adds to the existing nodes.
+    Node(&'a Rc<RefCell<InstructionNode>>),
+}
+
+impl Lookup<'_> {
+    fn earlier(&self, instruction: &LabeledInstruction) -> Vec<Rc<RefCell<InstructionNode>>> {
+        match self {
+            Lookup::Graph(graph) => graph.same_thread(instruction, Ordering::Less),
+            Lookup::Thread(thread) => {
+                thread.nodes[..thread.before(instruction.line_index)].to_vec()
+            }
+            Lookup::Node(node) => Self::node_if(node, instruction, Ordering::Less),
+        }
+    }
+
+    fn later(&self, instruction: &LabeledInstruction) -> Vec<Rc<RefCell<InstructionNode>>> {
+        match self {
+            Lookup::Graph(graph) => graph.same_thread(instruction, Ordering::Greater),
+            Lookup::Thread(thread) => {
+                thread.nodes[thread.before(instruction.line_index + 1)..].to_vec()
+            }
+            Lookup::Node(node) => Self::node_if(node, instruction, Ordering::Greater),
+        }
+    }
+
+    /// `node` if it is an instruction of the thread of `instruction` on a
+    /// line ordered as `ordering` against it.
+    fn node_if(
+        node: &Rc<RefCell<InstructionNode>>,
+        instruction: &LabeledInstruction,
+        ordering: Ordering,
+    ) -> Vec<Rc<RefCell<InstructionNode>>> {
+        match &node.borrow().instruction {
+            NodeType::Instruction(other)
+                if other.thread_id == instruction.thread_id
+                    && other.line_index.cmp(&instruction.line_index) == ordering =>
+            {
+                vec![node.clone()]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The earlier instructions, or at least those of them accessing a
+    /// location `instruction` accesses.
+    fn earlier_accessing(
+        &self,
+        instruction: &LabeledInstruction,
+    ) -> Vec<Rc<RefCell<InstructionNode>>> {
+        let thread = match self {
+            Lookup::Graph(_) | Lookup::Node(_) => return self.earlier(instruction),
+            Lookup::Thread(thread) => thread,
+        };
+        let before = thread.before(instruction.line_index);
+        let locations = instruction.instruction.read_locations();
+        let locations = locations
+            .into_iter()
+            .chain(instruction.instruction.write_locations());
+        let mut positions = locations
+            .filter_map(|location| thread.by_location.get(location))
+            .flatten()
+            .copied()
+            .filter(|position| *position < before)
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+            .into_iter()
+            .map(|position| thread.nodes[position].clone())
+            .collect()
+    }
+}
+
+/// Whether both instructions access the same address and at least one of
+/// them writes it.
+fn conflict(first: &Instruction, second: &Instruction) -> bool {
+    fn writes_read(write: &Instruction, other: &Instruction) -> bool {
+        write.write_locations().into_iter().any(|addr| {
+            other.read_locations().contains(&addr) || other.write_locations().contains(&addr)
+        })
+    }
+    writes_read(first, second) || writes_read(second, first)
 }
 
 impl DependencyGraph {
     pub fn new() -> Self {
-        Self { nodes: Vec::new() }
+        Self::from_nodes(Vec::new())
     }
 
-    pub fn add_some_node(&mut self, node: NodeType) {
-        let node = Rc::new(RefCell::new(InstructionNode {
-            instruction: node,
-            depends_on: Vec::new(),
-            depends_on_me: Vec::new(),
-        }));
+    /// A graph of the given nodes, with the edges they already have. Nodes
+    /// keep their instance.
+    pub fn from_nodes(nodes: Vec<Rc<RefCell<InstructionNode>>>) -> Self {
+        let added = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (Rc::as_ptr(node), index))
+            .collect();
+        let leaves = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.borrow().depends_on.is_empty())
+            .map(|(index, node)| (index, node.clone()))
+            .collect();
+        let mut instances = HashMap::new();
+        for node in &nodes {
+            let node = node.borrow();
+            let (next, pending) = instances.entry(node.instruction.id()).or_insert((0, 0));
+            *next = (*next).max(node.instance + 1);
+            *pending += 1;
+        }
+        Self {
+            additions: nodes.len(),
+            nodes,
+            added,
+            leaves,
+            instances,
+        }
+    }
+
+    /// Adds the node as the next instance of its instruction. Instances are
+    /// counted from the last time no node of the instruction was pending, so
+    /// a node never takes the instance of one still pending, and a line run
+    /// again after a jump keeps its id.
+    fn push(&mut self, node: Rc<RefCell<InstructionNode>>) {
+        let (next, pending) = self
+            .instances
+            .entry(node.borrow().instruction.id())
+            .or_insert((0, 0));
+        node.borrow_mut().instance = *next;
+        *next += 1;
+        *pending += 1;
+        self.added.insert(Rc::as_ptr(&node), self.additions);
+        self.leaves.insert(self.additions, node.clone());
+        self.additions += 1;
         self.nodes.push(node);
     }
 
+    /// Pending nodes, in the order they were added.
+    pub fn nodes(&self) -> &[Rc<RefCell<InstructionNode>>] {
+        &self.nodes
+    }
+
+    pub fn add_some_node(&mut self, node: NodeType) {
+        self.push(InstructionNode::detached(node));
+    }
+
+    /// Adds a propagate of `write`. Propagates of one write pending at the
+    /// same time, e.g. of consecutive loop iterations, are told apart by
+    /// their instance, so their ids never collide.
     pub fn add_propagate(
         &mut self,
         write: LabeledInstruction,
         to_location: Reference,
     ) -> Rc<RefCell<InstructionNode>> {
         let node = InstructionNode::new_propagate(write, to_location);
-        self.nodes.push(node.clone());
+        self.push(node.clone());
         node
     }
 
     pub fn add_node(&mut self, instruction: LabeledInstruction) -> Rc<RefCell<InstructionNode>> {
         let node = InstructionNode::new(instruction);
-        self.nodes.push(node.clone());
+        self.push(node.clone());
         node
     }
 
+    /// Adds the edges of every instruction node. The instructions of each
+    /// thread are indexed by line and by accessed location up front, so the
+    /// nodes a node is ordered against are looked up instead of searched
+    /// for in the whole graph: O(n log n + e) for n nodes and e edges, where
+    /// searching per node was O(n * (n + e)).
     pub fn build_dependencies(&mut self) {
-        let node_count = self.nodes.len();
-        for index in 0..node_count {
-            self.add_dependencies(index);
+        let mut threads: HashMap<usize, Vec<Rc<RefCell<InstructionNode>>>> = HashMap::new();
+        for node in &self.nodes {
+            if let NodeType::Instruction(instruction) = &node.borrow().instruction {
+                threads
+                    .entry(instruction.thread_id)
+                    .or_default()
+                    .push(node.clone());
+            }
+        }
+        let threads: HashMap<usize, ThreadNodes> = threads
+            .into_iter()
+            .map(|(thread_id, nodes)| (thread_id, ThreadNodes::new(nodes)))
+            .collect();
+        for node in &self.nodes {
+            let thread_id = node.borrow().instruction.thread_id();
+            if let Some(thread) = threads.get(&thread_id) {
+                self.add_node_dependencies(node.clone(), &Lookup::Thread(thread));
+            }
+        }
+    }
+
+    /// Ids of the nodes on some cycle of `depends_on` edges, in edge order,
+    /// or `None` if the graph is acyclic. A cycle leaves its nodes out of
+    /// `get_leaves` forever, so the execution would look finished early.
+    pub fn detect_cycle(&self) -> Option<Vec<String>> {
+        fn visit(
+            node: &Rc<RefCell<InstructionNode>>,
+            finished: &mut HashSet<*const RefCell<InstructionNode>>,
+            path: &mut Vec<Rc<RefCell<InstructionNode>>>,
+        ) -> Option<Vec<String>> {
+            if let Some(start) = path.iter().position(|n| Rc::ptr_eq(n, node)) {
+                return Some(path[start..].iter().map(|n| n.borrow().id()).collect());
+            }
+            if finished.contains(&Rc::as_ptr(node)) {
+                return None;
+            }
+            path.push(node.clone());
+            for dependency in &node.borrow().depends_on {
+                if let Some(cycle) = visit(dependency, finished, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            finished.insert(Rc::as_ptr(node));
+            None
         }
+
+        let mut finished = HashSet::new();
+        self.nodes
+            .iter()
+            .find_map(|node| visit(node, &mut finished, &mut Vec::new()))
     }
 
+    /// Pushes onto `result` the nodes accepted by `predicate` among `node`
+    /// and the nodes it waits for, in depth-first preorder, skipping the ids
+    /// in `visited`. Uses an explicit stack, so long chains of dependencies
+    /// can't overflow the call stack.
     pub fn dfs_filter_aux(
         &self,
         node: &Rc<RefCell<InstructionNode>>,
@@ -177,16 +641,16 @@ impl DependencyGraph {
         result: &mut Vec<Rc<RefCell<InstructionNode>>>,
         predicate: &impl Fn(&NodeType) -> bool,
     ) {
-        // Check id
-        if visited.contains(&node.borrow().instruction.id()) {
-            return;
-        }
-        visited.insert(node.borrow().instruction.id());
-        if predicate(&node.borrow().instruction) {
-            result.push(node.clone());
-        }
-        for dependency in &node.borrow().depends_on {
-            self.dfs_filter_aux(dependency, visited, result, predicate);
+        let mut stack = vec![node.clone()];
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node.borrow().id()) {
+                continue;
+            }
+            if predicate(&node.borrow().instruction) {
+                result.push(node.clone());
+            }
+            // Reversed, so the first dependency is visited first
+            stack.extend(node.borrow().depends_on.iter().rev().cloned());
         }
     }
 
@@ -202,49 +666,97 @@ impl DependencyGraph {
         result
     }
 
-    fn add_rel_deps(&self, cur_node: &mut Rc<RefCell<InstructionNode>>) {
+    /// Instruction nodes of the thread of `instruction` whose line compares
+    /// to its line as `ordering`, in depth-first order.
+    fn same_thread(
+        &self,
+        instruction: &LabeledInstruction,
+        ordering: Ordering,
+    ) -> Vec<Rc<RefCell<InstructionNode>>> {
+        self.dfs_filter(|other_node| match other_node {
+            NodeType::Instruction(other_instr) => {
+                other_instr.thread_id == instruction.thread_id
+                    && other_instr.line_index.cmp(&instruction.line_index) == ordering
+            }
+            NodeType::Propagate(_) => false,
+        })
+    }
+
+    /// Release ordering: `cur_node` waits for the earlier instructions of its
+    /// thread accepted by `filter`.
+    fn add_rel_deps(
+        &self,
+        cur_node: &mut Rc<RefCell<InstructionNode>>,
+        lookup: &Lookup,
+        filter: impl Fn(&Instruction) -> bool,
+    ) {
         let instr: NodeType = cur_node.borrow().instruction.clone();
-        match instr {
-            NodeType::Instruction(cur_instr) => {
-                let depended_nodes = self.dfs_filter(|other_node| {
-                    if let NodeType::Instruction(other_instr) = other_node {
-                        cur_instr.thread_id == other_instr.thread_id
-                            && cur_instr.line_index < other_instr.line_index
-                    } else {
-                        false
-                    }
-                });
-                for depended_node in depended_nodes {
-                    InstructionNode::add_dependency(depended_node.clone(), cur_node.clone());
+        if let NodeType::Instruction(cur_instr) = instr {
+            for depended_node in lookup.earlier(&cur_instr) {
+                if filter(&instruction_of(&depended_node)) {
+                    InstructionNode::add_dependency(cur_node.clone(), depended_node);
                 }
             }
-            _ => {}
         }
     }
 
-    fn add_acq_deps(&self, cur_node: &mut Rc<RefCell<InstructionNode>>) {
+    /// Acquire ordering: the later instructions of the thread accepted by
+    /// `filter` wait for `cur_node`.
+    fn add_acq_deps(
+        &self,
+        cur_node: &mut Rc<RefCell<InstructionNode>>,
+        lookup: &Lookup,
+        filter: impl Fn(&Instruction) -> bool,
+    ) {
         let instr: NodeType = cur_node.borrow().instruction.clone();
 
-        match instr {
-            NodeType::Instruction(cur_instr) => {
-                let dependant_nodes = self.dfs_filter(|other_node| {
-                    if let NodeType::Instruction(other_instr) = other_node {
-                        cur_instr.thread_id == other_instr.thread_id
-                            && cur_instr.line_index > other_instr.line_index
-                    } else {
-                        false
-                    }
-                });
-                for dependant_node in dependant_nodes {
-                    InstructionNode::add_dependency(cur_node.clone(), dependant_node.clone());
+        if let NodeType::Instruction(cur_instr) = instr {
+            for dependant_node in lookup.later(&cur_instr) {
+                if filter(&instruction_of(&dependant_node)) {
+                    InstructionNode::add_dependency(dependant_node, cur_node.clone());
                 }
             }
-            _ => {}
         }
     }
 
+    /// Adds the edges of the node at `node_index`, searching the graph for
+    /// the nodes it is ordered against.
     pub fn add_dependencies(&mut self, node_index: usize) {
-        let mut node = self.nodes[node_index].clone();
+        let node = self.nodes[node_index].clone();
+        self.add_node_dependencies(node, &Lookup::Graph(self));
+    }
+
+    /// Adds an instruction node along with its edges to the nodes already in
+    /// the graph, in both directions, without rebuilding the others' edges.
+    /// The graph ends up as `build_dependencies` would leave it, in
+    /// O(k log k + e) for the k nodes of its thread, so re-adding the
+    /// instructions a jump goes back over doesn't redo the whole graph each
+    /// time.
+    pub fn add_node_with_deps(
+        &mut self,
+        instruction: LabeledInstruction,
+    ) -> Rc<RefCell<InstructionNode>> {
+        let thread_id = instruction.thread_id;
+        let node = self.add_node(instruction);
+        let thread = self
+            .nodes
+            .iter()
+            .filter(|other| {
+                matches!(&other.borrow().instruction,
+                    NodeType::Instruction(other) if other.thread_id == thread_id)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for other in &thread {
+            if !Rc::ptr_eq(other, &node) {
+                self.add_node_dependencies(other.clone(), &Lookup::Node(&node));
+            }
+        }
+        self.add_node_dependencies(node.clone(), &Lookup::Thread(&ThreadNodes::new(thread)));
+        node
+    }
+
+    fn add_node_dependencies(&self, mut node: Rc<RefCell<InstructionNode>>, lookup: &Lookup) {
         fn get_access_mode_seq_cst(
             instruction: &Instruction,
             prev_am: MemoryAccessMode,
@@ -264,7 +776,10 @@ impl DependencyGraph {
                         prev_am
                     }
                 }
-                Instruction::Cas(_, am, _, _, _) | Instruction::Fai(_, am, _, _) => {
+                Instruction::Cas(_, am, _, _, _)
+                | Instruction::Cas2(_, am, _, _, _)
+                | Instruction::Fai(_, am, _, _)
+                | Instruction::FetchMinMax(_, _, am, _, _) => {
                     if *am == MemoryAccessMode::SeqCst {
                         MemoryAccessMode::RelAcq
                     } else {
@@ -275,116 +790,490 @@ impl DependencyGraph {
             }
         }
         let c_node: NodeType = node.borrow().instruction.clone();
-        match c_node {
-            NodeType::Instruction(instruction) => match instruction.instruction {
+        if let NodeType::Instruction(instruction) = c_node {
+            // Store forwarding: a load reads the thread's latest buffered
+            // write to its address, so it must follow the earlier stores to
+            // that address, and stores to one address enter the buffer, and
+            // so propagate, in program order.
+            for other in lookup.earlier_accessing(&instruction) {
+                if conflict(&instruction.instruction, &instruction_of(&other)) {
+                    InstructionNode::add_dependency(node.clone(), other);
+                }
+            }
+            match instruction.instruction {
+                // A release fence orders all earlier instructions before the
+                // later writes, an acquire fence orders the earlier reads
+                // before all later instructions. Both scopes order the
+                // instructions, only global fences also wait for propagates.
+                Instruction::Fence(am, _) => match am {
+                    MemoryAccessMode::Rel => {
+                        self.add_rel_deps(&mut node, lookup, |_| true);
+                        self.add_acq_deps(&mut node, lookup, |other| {
+                            other.write_location().is_some()
+                        });
+                    }
+                    MemoryAccessMode::Acq => {
+                        self.add_rel_deps(&mut node, lookup, |other| {
+                            other.read_location().is_some()
+                        });
+                        self.add_acq_deps(&mut node, lookup, |_| true);
+                    }
+                    MemoryAccessMode::RelAcq | MemoryAccessMode::SeqCst => {
+                        self.add_rel_deps(&mut node, lookup, |_| true);
+                        self.add_acq_deps(&mut node, lookup, |_| true);
+                    }
+                    MemoryAccessMode::Rlx => {}
+                },
+                // Same as a fence, restricted to accesses to `addr`.
+                Instruction::FenceAddr(am, addr) => {
+                    let reads = |other: &Instruction| other.read_locations().contains(&&addr);
+                    let writes = |other: &Instruction| other.write_locations().contains(&&addr);
+                    let accesses = |other: &Instruction| reads(other) || writes(other);
+                    match am {
+                        MemoryAccessMode::Rel => {
+                            self.add_rel_deps(&mut node, lookup, accesses);
+                            self.add_acq_deps(&mut node, lookup, writes);
+                        }
+                        MemoryAccessMode::Acq => {
+                            self.add_rel_deps(&mut node, lookup, reads);
+                            self.add_acq_deps(&mut node, lookup, accesses);
+                        }
+                        MemoryAccessMode::RelAcq | MemoryAccessMode::SeqCst => {
+                            self.add_rel_deps(&mut node, lookup, accesses);
+                            self.add_acq_deps(&mut node, lookup, accesses);
+                        }
+                        MemoryAccessMode::Rlx => {}
+                    }
+                }
                 Instruction::Load(am, _, _)
                 | Instruction::Store(am, _, _)
                 | Instruction::Cas(_, am, _, _, _)
+                | Instruction::Cas2(_, am, _, _, _)
                 | Instruction::Fai(_, am, _, _)
-                | Instruction::Fence(am) => {
+                | Instruction::FetchMinMax(_, _, am, _, _) => {
+                    // Accesses keep the edges they have always had: a
+                    // release access orders the later instructions after it,
+                    // an acquire access waits for the earlier ones.
                     let modified_am = get_access_mode_seq_cst(&instruction.instruction, am);
                     match modified_am {
                         MemoryAccessMode::Rel => {
-                            self.add_rel_deps(&mut node);
+                            self.add_acq_deps(&mut node, lookup, |_| true);
                         }
                         MemoryAccessMode::Acq => {
-                            self.add_acq_deps(&mut node);
+                            self.add_rel_deps(&mut node, lookup, |_| true);
                         }
                         MemoryAccessMode::RelAcq => {
-                            self.add_rel_deps(&mut node);
-                            self.add_acq_deps(&mut node);
+                            self.add_rel_deps(&mut node, lookup, |_| true);
+                            self.add_acq_deps(&mut node, lookup, |_| true);
                         }
                         _ => {}
                     }
                 }
                 _ => {}
-            },
-            _ => {}
+            }
         }
     }
 
-    pub fn get_leaves(&self) -> Vec<Rc<RefCell<InstructionNode>>> {
+    /// Pairs of ids of conflicting accesses (same address, different threads,
+    /// at least one write) that nothing in the graph synchronizes. There are
+    /// no edges between threads, so a pair counts as synchronized only when
+    /// both accesses are ordered with the rest of their thread, by a fence or
+    /// by release/acquire semantics.
+    pub fn potential_races(&self) -> Vec<(String, String)> {
+        let accesses = self
+            .nodes
+            .iter()
+            .filter_map(|node| {
+                let node = node.borrow();
+                match &node.instruction {
+                    NodeType::Instruction(instruction) => {
+                        // Edges between accesses to one address only keep
+                        // the thread's own program order, see store forwarding.
+                        let ordered =
+                            node.depends_on
+                                .iter()
+                                .chain(&node.depends_on_me)
+                                .any(|other| match &RefCell::borrow(other).instruction {
+                                    NodeType::Instruction(other) => {
+                                        !conflict(&instruction.instruction, &other.instruction)
+                                    }
+                                    NodeType::Propagate(_) => true,
+                                });
+                        Some((instruction.clone(), ordered))
+                    }
+                    NodeType::Propagate(_) => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut races = Vec::new();
+        for (index, (first, first_ordered)) in accesses.iter().enumerate() {
+            for (second, second_ordered) in &accesses[index + 1..] {
+                if first.thread_id != second.thread_id
+                    && !(*first_ordered && *second_ordered)
+                    && conflict(&first.instruction, &second.instruction)
+                {
+                    races.push((first.id(), second.id()));
+                }
+            }
+        }
+        races.sort();
+        races
+    }
+
+    /// Message passing idioms among the pending instructions: every store and
+    /// load of one address in different threads, where the storing thread
+    /// writes data before the store that the loading thread reads after the
+    /// load. Ids are `<thread>-<line>`.
+    pub fn message_passing(&self) -> Vec<MpReport> {
+        let instructions = self
+            .nodes
+            .iter()
+            .filter_map(|node| match &node.borrow().instruction {
+                NodeType::Instruction(instruction) => Some((instruction.clone(), node.clone())),
+                NodeType::Propagate(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mut reports = Vec::new();
+        for (store, store_node) in &instructions {
+            let Some(flag) = store.instruction.write_location() else {
+                continue;
+            };
+            for (load, load_node) in &instructions {
+                if load.thread_id == store.thread_id
+                    || load.instruction.read_location() != Some(flag)
+                {
+                    continue;
+                }
+                let mut data = BTreeSet::new();
+                let mut synchronized = true;
+                for (write, write_node) in &instructions {
+                    let addr = match write.instruction.write_location() {
+                        Some(addr) if addr != flag => addr,
+                        _ => continue,
+                    };
+                    if write.thread_id != store.thread_id || write.line_index > store.line_index {
+                        continue;
+                    }
+                    let reads = instructions
+                        .iter()
+                        .filter(|(read, _)| {
+                            read.thread_id == load.thread_id
+                                && read.line_index > load.line_index
+                                && read.instruction.read_location() == Some(addr)
+                        })
+                        .collect::<Vec<_>>();
+                    if reads.is_empty() {
+                        continue;
+                    }
+                    data.insert(addr.to_string());
+                    synchronized &= self.depends_transitively(store_node, write_node)
+                        && reads
+                            .iter()
+                            .all(|(_, read_node)| self.depends_transitively(read_node, load_node));
+                }
+                if !data.is_empty() {
+                    reports.push(MpReport {
+                        store: store.id(),
+                        load: load.id(),
+                        flag: flag.to_string(),
+                        data: data.into_iter().collect(),
+                        synchronized,
+                    });
+                }
+            }
+        }
+        reports
+    }
+
+    /// Whether `node` waits for `on`, directly or through other nodes.
+    fn depends_transitively(
+        &self,
+        node: &Rc<RefCell<InstructionNode>>,
+        on: &Rc<RefCell<InstructionNode>>,
+    ) -> bool {
+        let mut ancestors = Vec::new();
+        self.dfs_filter_aux(node, &mut HashSet::new(), &mut ancestors, &|_| true);
+        ancestors.iter().any(|ancestor| Rc::ptr_eq(ancestor, on))
+    }
+
+    /// The happens-before order of the pending nodes: the transitive closure of
+    /// `depends_on`. Nodes on a cycle get the predecessors found before the
+    /// cycle closes.
+    pub fn happens_before_relation(&self) -> HappensBefore {
+        type Closure = HashMap<*const RefCell<InstructionNode>, HashSet<String>>;
+        fn visit(node: &Rc<RefCell<InstructionNode>>, closure: &mut Closure) -> HashSet<String> {
+            if let Some(predecessors) = closure.get(&Rc::as_ptr(node)) {
+                return predecessors.clone();
+            }
+            closure.insert(Rc::as_ptr(node), HashSet::new());
+            let mut predecessors = HashSet::new();
+            for dependency in &node.borrow().depends_on {
+                predecessors.insert(dependency.borrow().id());
+                predecessors.extend(visit(dependency, closure));
+            }
+            closure.insert(Rc::as_ptr(node), predecessors.clone());
+            predecessors
+        }
+
+        let mut closure = Closure::new();
+        let predecessors = self
+            .nodes
+            .iter()
+            .map(|node| (node.borrow().id(), visit(node, &mut closure)))
+            .collect();
+        HappensBefore { predecessors }
+    }
+
+    /// Finds the pending node of the instruction at the given thread and line.
+    pub fn find_instruction(
+        &self,
+        thread_id: usize,
+        line_index: usize,
+    ) -> Option<Rc<RefCell<InstructionNode>>> {
         self.nodes
             .iter()
+            .find(|node| match &node.borrow().instruction {
+                NodeType::Instruction(instruction) => {
+                    instruction.thread_id == thread_id && instruction.line_index == line_index
+                }
+                NodeType::Propagate(_) => false,
+            })
+            .cloned()
+    }
+
+    /// Nodes without dependencies, in graph order. Candidates are kept by
+    /// the order nodes were added: a node is one when it's added and again
+    /// when a removal takes its last dependency, and stops being one when
+    /// it's removed. Only the candidates still without dependencies are
+    /// returned.
+    pub fn get_leaves(&self) -> Vec<Rc<RefCell<InstructionNode>>> {
+        self.leaves
+            .values()
             .filter(|node| node.borrow().depends_on.is_empty())
             .cloned()
             .collect()
     }
 
-    pub fn remove_node(
-        &mut self,
-        node: Rc<RefCell<InstructionNode>>,
-        propagate: Option<(LabeledInstruction, Reference)>,
-        pso: bool,
-    ) {
-        // Node has no outgoing edges
+    /// Only leaves of the graph, i.e. nodes without pending dependencies, may
+    /// be removed.
+    pub fn check_removable(&self, node: &Rc<RefCell<InstructionNode>>) -> Result<(), GraphError> {
+        if !self.nodes.iter().any(|n| Rc::ptr_eq(n, node)) {
+            return Err(GraphError::NodeNotFound(node.borrow().id()));
+        }
         if !node.borrow().depends_on.is_empty() {
-            panic!("Cannot remove node with dependencies");
+            return Err(GraphError::NodeHasDependencies(node.borrow().id()));
         }
+        Ok(())
+    }
 
-        // Remove node incoming edges
-        for dependency in &node.borrow().depends_on_me {
+    /// Each write of the node gets at most one propagate, so that e.g. a
+    /// store has exactly one.
+    fn check_propagates(
+        node: &Rc<RefCell<InstructionNode>>,
+        propagates: &[(LabeledInstruction, Reference)],
+    ) -> Result<(), GraphError> {
+        let node = node.borrow();
+        for (index, (write, to_location)) in propagates.iter().enumerate() {
+            let written = matches!(&node.instruction, NodeType::Instruction(instruction)
+                if instruction == write
+                    && instruction.instruction.write_locations().contains(&to_location));
+            let repeated = propagates[..index]
+                .iter()
+                .any(|(_, location)| location == to_location);
+            if !written || repeated {
+                let propagate = Propagate::new(write.clone(), to_location.clone());
+                return Err(GraphError::UnexpectedPropagate(format!(
+                    "{} to {}",
+                    propagate.id(),
+                    to_location
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a node that won't execute, e.g. a line a jump skips, with
+    /// its edges in both directions. The nodes waiting only for it become
+    /// leaves.
+    pub fn discard_node(&mut self, node: Rc<RefCell<InstructionNode>>) {
+        for dependency in &node.borrow().depends_on {
             dependency
                 .borrow_mut()
-                .depends_on
+                .depends_on_me
                 .retain(|n| !Rc::ptr_eq(n, &node));
         }
+        for dependant in &node.borrow().depends_on_me {
+            let mut dependant_node = dependant.borrow_mut();
+            dependant_node.depends_on.retain(|n| !Rc::ptr_eq(n, &node));
+            if dependant_node.depends_on.is_empty() {
+                if let Some(added) = self.added.get(&Rc::as_ptr(dependant)) {
+                    self.leaves.insert(*added, dependant.clone());
+                }
+            }
+        }
+        node.borrow_mut().depends_on.clear();
+        node.borrow_mut().depends_on_me.clear();
+        self.forget(&node);
+    }
+
+    /// Takes the node out of the graph, leaving its edges to the caller.
+    fn forget(&mut self, node: &Rc<RefCell<InstructionNode>>) {
+        if let Some(added) = self.added.remove(&Rc::as_ptr(node)) {
+            self.leaves.remove(&added);
+        }
+        self.nodes.retain(|n| !Rc::ptr_eq(n, node));
+        let id = node.borrow().instruction.id();
+        if let Some((_, pending)) = self.instances.get_mut(&id) {
+            *pending -= 1;
+            if *pending == 0 {
+                self.instances.remove(&id);
+            }
+        }
+    }
+
+    /// Removes an executed node, adding a propagate for each write it left
+    /// in the store buffer, in buffer order.
+    pub fn remove_node(
+        &mut self,
+        node: Rc<RefCell<InstructionNode>>,
+        propagates: Vec<(LabeledInstruction, Reference)>,
+        pso: bool,
+    ) -> Result<(), GraphError> {
+        self.check_removable(&node)?;
+        Self::check_propagates(&node, &propagates)?;
+
+        // Remove node incoming edges, the last one of a node makes it a leaf
+        for dependency in &node.borrow().depends_on_me {
+            let mut dependant = dependency.borrow_mut();
+            dependant.depends_on.retain(|n| !Rc::ptr_eq(n, &node));
+            if dependant.depends_on.is_empty() {
+                if let Some(added) = self.added.get(&Rc::as_ptr(dependency)) {
+                    self.leaves.insert(*added, dependency.clone());
+                }
+            }
+        }
 
         // Remove node from graph
-        self.nodes.retain(|n| !Rc::ptr_eq(n, &node));
+        self.forget(&node);
 
-        if let Some((instr, to_loc)) = propagate {
-            // println!("Propagating {:?}", instr);
+        let mut previous: Option<Rc<RefCell<InstructionNode>>> = None;
+        for (instr, to_loc) in propagates {
             let propagate_node = self.add_propagate(instr.clone(), to_loc.clone());
 
-            // Add dependencies from fences
-            let dependant_nodes = self.dfs_filter(|other_node| {
-                if let NodeType::Instruction(LabeledInstruction {
-                    instruction: Instruction::Fence(_),
-                    ..
-                }) = other_node
-                {
-                    if let NodeType::Instruction(other_instr) = other_node {
-                        other_instr.thread_id == instr.thread_id
-                    } else {
-                        false
+            // Writes of a `cas2` share a line, so order them explicitly when
+            // they share a buffer.
+            if let Some(previous) = previous.take().filter(|_| !pso) {
+                InstructionNode::add_dependency(propagate_node.clone(), previous);
+            }
+
+            // Global fences with release semantics (on the written address,
+            // for address fences) wait for the write to leave the buffer, as do
+            // later read-modify-writes with release semantics.
+            let dependant_nodes = self.dfs_filter(|other_node| match other_node {
+                NodeType::Instruction(other_instr) if other_instr.thread_id == instr.thread_id => {
+                    match other_instr.instruction {
+                        Instruction::Fence(am, FenceScope::Global) => is_release(am),
+                        Instruction::FenceAddr(am, ref addr) => is_release(am) && *addr == to_loc,
+                        Instruction::Cas(_, am, _, _, _)
+                        | Instruction::Cas2(_, am, _, _, _)
+                        | Instruction::Fai(_, am, _, _)
+                        | Instruction::FetchMinMax(_, _, am, _, _) => {
+                            is_release(am) && other_instr.line_index > instr.line_index
+                        }
+                        _ => false,
                     }
-                } else {
-                    false
                 }
+                _ => false,
             });
 
             for dependant_node in dependant_nodes {
                 InstructionNode::add_dependency(dependant_node.clone(), propagate_node.clone());
             }
 
-            // Add dependencies to other propagates
+            // Writes of one thread leave the buffer in program order (per
+            // address under PSO): the new propagate waits for earlier writes,
+            // issued or not, and later propagates wait for it.
+            let same_buffer = |other_node: &NodeType| {
+                let (thread_id, other_locs) = match other_node {
+                    NodeType::Propagate(Propagate {
+                        to_location,
+                        associated_write,
+                    }) => (associated_write.thread_id, vec![to_location]),
+                    NodeType::Instruction(other_instr) => (
+                        other_instr.thread_id,
+                        other_instr.instruction.write_locations(),
+                    ),
+                };
+                !other_locs.is_empty()
+                    && thread_id == instr.thread_id
+                    && (!pso || other_locs.contains(&&to_loc))
+            };
+            let line_index = |node: &NodeType| match node {
+                NodeType::Propagate(propagate) => propagate.associated_write.line_index,
+                NodeType::Instruction(instruction) => instruction.line_index,
+            };
+
             let depended_nodes = self.dfs_filter(|other_node| {
-                if let NodeType::Propagate(Propagate {
-                    to_location: other_loc,
-                    associated_write: labeled_instr,
-                }) = other_node
-                {
-                    if pso {
-                        (*other_loc) == to_loc
-                            && labeled_instr.thread_id == instr.thread_id
-                            && labeled_instr.line_index != instr.line_index
-                    } else {
-                        labeled_instr.thread_id == instr.thread_id
-                            && labeled_instr.line_index != instr.line_index
-                    }
-                } else {
-                    false
-                }
+                same_buffer(other_node) && line_index(other_node) < instr.line_index
             });
-
             for depended_node in depended_nodes {
                 InstructionNode::add_dependency(propagate_node.clone(), depended_node.clone());
             }
+
+            let dependant_nodes = self.dfs_filter(|other_node| {
+                matches!(other_node, NodeType::Propagate(_))
+                    && same_buffer(other_node)
+                    && line_index(other_node) > instr.line_index
+            });
+            for dependant_node in dependant_nodes {
+                InstructionNode::add_dependency(dependant_node.clone(), propagate_node.clone());
+            }
+            previous = Some(propagate_node);
         }
+
+        // The propagates and the nodes now waiting for them may have
+        // dependencies
+        self.leaves
+            .retain(|_, leaf| leaf.borrow().depends_on.is_empty());
+        Ok(())
     }
 
-    pub fn to_dot(&self) -> String {
+    /// Unique id of every node. Several nodes may share a thread and line (a
+    /// write propagated twice, or instructions re-added by a jump), so ids
+    /// also carry the node's position in the graph.
+    fn node_ids(&self) -> HashMap<*const RefCell<InstructionNode>, String> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let id = format!("{}N{}", node.borrow().instruction.to_dot(), index);
+                (Rc::as_ptr(node), id)
+            })
+            .collect()
+    }
+
+    /// Every node in graph order, with the ids of the nodes it waits for in
+    /// the order the edges were added.
+    pub fn to_adjacency(&self) -> Vec<(NodeInfo, Vec<NodeId>)> {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let node = node.borrow();
+                let info = NodeInfo {
+                    id: node.id(),
+                    instruction: node.instruction.clone(),
+                    instance: node.instance,
+                };
+                let depends_on = node.depends_on.iter().map(|d| d.borrow().id()).collect();
+                (info, depends_on)
+            })
+            .collect()
+    }
+
+    /// Renders the graph in the `dot` format with one cluster per thread, in
+    /// thread order. With `legend`, a separate cluster shows a sample of
+    /// every node and edge kind with its meaning.
+    pub fn to_dot(&self, legend: bool) -> String {
         fn get_color() -> Color {
             let mut rng = rand::thread_rng();
             match rng.gen_range(0..=4) {
@@ -395,19 +1284,23 @@ impl DependencyGraph {
                 _ => Color::Black,
             }
         }
+        let node_ids = self.node_ids();
+        let dot_id = |node: &Rc<RefCell<InstructionNode>>| node_ids[&Rc::as_ptr(node)].clone();
+        let is_propagate = |node: &NodeType| matches!(node, NodeType::Propagate(_));
         let mut output_bytes = Vec::new();
         {
             let mut writer = DotWriter::from(&mut output_bytes);
             writer.set_pretty_print(false);
             let mut digraph = writer.digraph();
 
-            let mut threads: HashSet<usize> = HashSet::new();
+            // The nodes of each thread, in graph order
+            let mut threads: BTreeMap<usize, Vec<&Rc<RefCell<InstructionNode>>>> = BTreeMap::new();
             for node in &self.nodes {
                 let thread_id = node.borrow().instruction.thread_id();
-                threads.insert(thread_id);
+                threads.entry(thread_id).or_default().push(node);
             }
 
-            for thread_id in threads {
+            for (thread_id, nodes) in threads {
                 let mut cluster = digraph.cluster();
                 cluster.set_color(get_color());
                 cluster
@@ -415,18 +1308,75 @@ impl DependencyGraph {
                     .set_style(Style::Filled)
                     .set_color(Color::LightGrey);
                 cluster.set_label(format!("Thread #{}", thread_id).as_str());
-                for node in &self.nodes {
-                    if node.borrow().instruction.thread_id() == thread_id {
-                        let node_label = node.borrow().instruction.to_dot();
-                        cluster.node_named(node_label.as_str());
-                        for dependency in &node.borrow().depends_on {
-                            let dependency_label = dependency.borrow().instruction.to_dot();
-                            cluster.edge(node_label.as_str(), dependency_label.as_str());
+                for node in nodes {
+                    let node_id = dot_id(node);
+                    // The id stays the node name, the label shows the
+                    // instruction text
+                    let instruction = &node.borrow().instruction;
+                    {
+                        let mut dot_node = cluster.node_named(node_id.as_str());
+                        dot_node.set_label(&instruction.to_string());
+                        if is_propagate(instruction) {
+                            dot_node.set_shape(Shape::Rectangle);
+                        }
+                    }
+                    for dependency in &node.borrow().depends_on {
+                        let edge = cluster.edge(node_id.as_str(), dot_id(dependency).as_str());
+                        if let Some(style) = dot_edge_style(
+                            is_propagate(instruction),
+                            is_propagate(&dependency.borrow().instruction),
+                        ) {
+                            edge.attributes().set_style(style);
                         }
                     }
                 }
             }
+
+            if legend {
+                dot_legend(&mut digraph);
+            }
         }
         String::from_utf8(output_bytes).unwrap()
     }
+
+    /// Renders the graph as a Mermaid flowchart with one subgraph per thread,
+    /// using the same node ids and edges as `to_dot`.
+    pub fn to_mermaid(&self) -> String {
+        let node_ids = self.node_ids();
+        let mermaid_id = |node: &Rc<RefCell<InstructionNode>>| &node_ids[&Rc::as_ptr(node)];
+        let threads: BTreeSet<usize> = self
+            .nodes
+            .iter()
+            .map(|node| node.borrow().instruction.thread_id())
+            .collect();
+
+        let mut output = String::from("flowchart TD\n");
+        for thread_id in threads {
+            output.push_str(&format!(
+                "    subgraph T{}[\"Thread #{}\"]\n",
+                thread_id, thread_id
+            ));
+            for node in &self.nodes {
+                let node_ref = node.borrow();
+                if node_ref.instruction.thread_id() == thread_id {
+                    output.push_str(&format!(
+                        "        {}[\"{}\"]\n",
+                        mermaid_id(node),
+                        node_ref.instruction.to_dot()
+                    ));
+                }
+            }
+            output.push_str("    end\n");
+        }
+        for node in &self.nodes {
+            for dependency in &node.borrow().depends_on {
+                output.push_str(&format!(
+                    "    {} --> {}\n",
+                    mermaid_id(node),
+                    mermaid_id(dependency)
+                ));
+            }
+        }
+        output
+    }
 }
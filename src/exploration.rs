@@ -0,0 +1,306 @@
+//! Exhaustive exploration of executions, shared by the engines. Every
+//! explorer clones the engine at each step and executes one option per clone,
+//! so the engines only have to implement `Explore`.
+
+use crate::instruction::{ArithMode, SubMode};
+use crate::thread_subsystem::{ExecutionError, FinalState};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::thread;
+
+/// Settings of an engine that snapshots don't record, so that an engine
+/// restored from a snapshot explores like the one that took it.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Settings {
+    pub arith_mode: ArithMode,
+    pub sub_mode: Option<SubMode>,
+    pub mem_default: i64,
+    pub eager_propagate: bool,
+}
+
+/// What an explorer found, with the executions it gave up because a step
+/// failed at runtime. Those have no final state, `errors` counts them by
+/// error message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exploration<T> {
+    pub found: T,
+    pub errors: BTreeMap<String, usize>,
+    /// Number of states explored, each time one is reached. A state skipped
+    /// as already explored isn't counted.
+    pub visited: usize,
+}
+
+impl<T> Exploration<T> {
+    /// Number of executions that failed at runtime.
+    pub fn failed(&self) -> usize {
+        self.errors.values().sum()
+    }
+
+    fn fail(&mut self, error: ExecutionError) {
+        *self.errors.entry(error.to_string()).or_insert(0) += 1;
+    }
+
+    fn add_errors(&mut self, errors: BTreeMap<String, usize>) {
+        for (error, count) in errors {
+            *self.errors.entry(error).or_insert(0) += count;
+        }
+    }
+}
+
+impl Exploration<HashMap<FinalState, usize>> {
+    /// Adds what `other` found, failed and visited.
+    fn add(&mut self, other: Self) {
+        for (state, count) in other.found {
+            *self.found.entry(state).or_insert(0) += count;
+        }
+        self.add_errors(other.errors);
+        self.visited += other.visited;
+    }
+}
+
+/// What the explorers need of an engine. The options of a step are taken by
+/// index, so that each clone executes its own copy of the option.
+pub(crate) trait Explore: Clone {
+    /// Number of options of the next step, zero once the execution is over.
+    fn options_count(&self) -> usize;
+
+    /// Executes the option at `index` of the next step.
+    fn exec_option(&mut self, index: usize) -> Result<(), ExecutionError>;
+
+    fn final_state(&self) -> FinalState;
+
+    /// See `TSO::state_fingerprint`.
+    fn state_fingerprint(&self) -> String;
+
+    fn to_snapshot(&self) -> String;
+
+    fn settings(&self) -> Settings;
+
+    /// The engine `to_snapshot` saved in `snapshot`, with `settings`.
+    fn restore(snapshot: &str, settings: Settings) -> Self;
+}
+
+/// See `TSO::explore_all`.
+pub(crate) fn explore_all<E: Explore>(
+    engine: &E,
+    step_limit: usize,
+) -> Exploration<HashSet<FinalState>> {
+    let mut outcomes = Exploration::default();
+    explore_distinct(engine, step_limit, &mut HashMap::new(), &mut outcomes);
+    outcomes
+}
+
+/// Explores like `explore_aux`, skipping the states in `visited`, which maps
+/// state fingerprints to the steps left when they were explored.
+fn explore_distinct<E: Explore>(
+    engine: &E,
+    steps_left: usize,
+    visited: &mut HashMap<String, usize>,
+    outcomes: &mut Exploration<HashSet<FinalState>>,
+) {
+    let fingerprint = engine.state_fingerprint();
+    if visited
+        .get(&fingerprint)
+        .is_some_and(|explored| *explored >= steps_left)
+    {
+        return;
+    }
+    visited.insert(fingerprint, steps_left);
+    outcomes.visited += 1;
+    let options_count = engine.options_count();
+    if options_count == 0 {
+        outcomes.found.insert(engine.final_state());
+        return;
+    }
+    if steps_left == 0 {
+        return;
+    }
+    for index in 0..options_count {
+        let mut next = engine.clone();
+        if let Err(err) = next.exec_option(index) {
+            outcomes.fail(err);
+            continue;
+        }
+        stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
+            explore_distinct(&next, steps_left - 1, visited, outcomes)
+        });
+    }
+}
+
+/// See `TSO::explore_counts`.
+pub(crate) fn explore_counts<E: Explore>(
+    engine: &E,
+    step_limit: usize,
+) -> Exploration<HashMap<FinalState, usize>> {
+    explore_aux(engine, step_limit, &mut HashMap::new())
+}
+
+/// Counts the executions from `engine`. `counted` maps a state fingerprint
+/// and the steps left to what was counted from there, which an execution
+/// reaching the same state with as many steps left adds again without
+/// exploring it.
+fn explore_aux<E: Explore>(
+    engine: &E,
+    steps_left: usize,
+    counted: &mut HashMap<(String, usize), Exploration<HashMap<FinalState, usize>>>,
+) -> Exploration<HashMap<FinalState, usize>> {
+    let key = (engine.state_fingerprint(), steps_left);
+    if let Some(outcomes) = counted.get(&key) {
+        return Exploration {
+            visited: 0,
+            ..outcomes.clone()
+        };
+    }
+    let mut outcomes = Exploration::<HashMap<_, _>> {
+        visited: 1,
+        ..Default::default()
+    };
+    let options_count = engine.options_count();
+    if options_count == 0 {
+        outcomes.found.insert(engine.final_state(), 1);
+    } else if steps_left > 0 {
+        for index in 0..options_count {
+            let mut next = engine.clone();
+            if let Err(err) = next.exec_option(index) {
+                outcomes.fail(err);
+                continue;
+            }
+            outcomes.add(stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
+                explore_aux(&next, steps_left - 1, counted)
+            }));
+        }
+    }
+    counted.insert(key, outcomes.clone());
+    outcomes
+}
+
+/// See `TSO::explore_counts_parallel`.
+pub(crate) fn explore_counts_parallel<E: Explore>(
+    engine: &E,
+    step_limit: usize,
+    jobs: usize,
+) -> Exploration<HashMap<FinalState, usize>> {
+    if jobs <= 1 {
+        return explore_counts(engine, step_limit);
+    }
+    let mut outcomes = Exploration::<HashMap<_, _>>::default();
+    let mut frontier = vec![engine.clone()];
+    let mut steps_left = step_limit;
+    while !frontier.is_empty() && frontier.len() < jobs && steps_left > 0 {
+        let mut next_frontier = Vec::new();
+        for system in frontier {
+            outcomes.visited += 1;
+            let options_count = system.options_count();
+            if options_count == 0 {
+                *outcomes.found.entry(system.final_state()).or_insert(0) += 1;
+            }
+            for index in 0..options_count {
+                let mut next = system.clone();
+                match next.exec_option(index) {
+                    Ok(()) => next_frontier.push(next),
+                    Err(err) => outcomes.fail(err),
+                }
+            }
+        }
+        frontier = next_frontier;
+        steps_left -= 1;
+    }
+
+    let snapshots = frontier.iter().map(E::to_snapshot).collect();
+    let settings = engine.settings();
+    explore_snapshots(snapshots, jobs, &mut outcomes, |snapshot| {
+        explore_counts(&E::restore(snapshot, settings), steps_left)
+    });
+    outcomes
+}
+
+/// Explores the executions saved in `snapshots` on at most `jobs` worker
+/// threads, each taking the next snapshot left once done with its last, and
+/// adds the final states, errors and visited states `explore` counts to
+/// `outcomes`.
+fn explore_snapshots(
+    snapshots: Vec<String>,
+    jobs: usize,
+    outcomes: &mut Exploration<HashMap<FinalState, usize>>,
+    explore: impl Fn(&str) -> Exploration<HashMap<FinalState, usize>> + Sync,
+) {
+    let jobs = jobs.min(snapshots.len());
+    let queue = Mutex::new(snapshots);
+    let worker = || {
+        let mut found = Exploration::<HashMap<_, _>>::default();
+        loop {
+            let next = queue.lock().unwrap().pop();
+            let Some(snapshot) = next else {
+                return found;
+            };
+            found.add(explore(&snapshot));
+        }
+    };
+    thread::scope(|scope| {
+        let workers = (0..jobs).map(|_| scope.spawn(worker)).collect::<Vec<_>>();
+        for worker in workers {
+            let found = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            outcomes.add(found);
+        }
+    });
+}
+
+/// See `TSO::find_final_state`.
+pub(crate) fn find_final_state<E: Explore>(
+    engine: &E,
+    step_limit: usize,
+    predicate: &dyn Fn(&FinalState) -> bool,
+) -> Exploration<Option<FinalState>> {
+    let mut outcome = Exploration::default();
+    find_aux(
+        engine,
+        step_limit,
+        predicate,
+        &mut HashMap::new(),
+        &mut outcome,
+    );
+    outcome
+}
+
+/// Explores until `outcome` has found a final state, skipping the states in
+/// `visited` like `explore_distinct`.
+fn find_aux<E: Explore>(
+    engine: &E,
+    steps_left: usize,
+    predicate: &dyn Fn(&FinalState) -> bool,
+    visited: &mut HashMap<String, usize>,
+    outcome: &mut Exploration<Option<FinalState>>,
+) {
+    let fingerprint = engine.state_fingerprint();
+    if visited
+        .get(&fingerprint)
+        .is_some_and(|explored| *explored >= steps_left)
+    {
+        return;
+    }
+    visited.insert(fingerprint, steps_left);
+    outcome.visited += 1;
+    let options_count = engine.options_count();
+    if options_count == 0 {
+        outcome.found = Some(engine.final_state()).filter(predicate);
+        return;
+    }
+    if steps_left == 0 {
+        return;
+    }
+    for index in 0..options_count {
+        let mut next = engine.clone();
+        if let Err(err) = next.exec_option(index) {
+            outcome.fail(err);
+            continue;
+        }
+        stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
+            find_aux(&next, steps_left - 1, predicate, visited, outcome)
+        });
+        if outcome.found.is_some() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! `arbitrary` support for fuzzing. Generated instructions are structurally
+//! valid: operands have the kinds the engines execute (registers where a
+//! value is read or written, memory locations where memory is accessed), and
+//! names come from small pools so that generated programs share registers,
+//! locations and labels. Every generated instruction parses back from its
+//! `to_source` form.
+
+use crate::instruction::{
+    ArithCommand, CmpCommand, FenceScope, Instruction, LabeledInstruction, MemoryAccessMode,
+    MinMax, Reference,
+};
+use crate::symbol::SymbolId;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+const REGISTERS: &[&str] = &["r0", "r1", "r2", "r3", "r4", "r5"];
+const LOCATIONS: &[&str] = &["x", "y", "z", "w"];
+const LABELS: &[&str] = &["L0", "L1", "L2", "L3"];
+
+fn register(u: &mut Unstructured) -> Result<Reference> {
+    Ok(Reference::Register(SymbolId::intern(u.choose(REGISTERS)?)))
+}
+
+fn registers(u: &mut Unstructured) -> Result<[Reference; 2]> {
+    Ok([register(u)?, register(u)?])
+}
+
+fn location(u: &mut Unstructured) -> Result<Reference> {
+    Ok(Reference::Memory(SymbolId::intern(u.choose(LOCATIONS)?)))
+}
+
+fn label(u: &mut Unstructured) -> Result<String> {
+    Ok(u.choose(LABELS)?.to_string())
+}
+
+fn mode(u: &mut Unstructured) -> Result<MemoryAccessMode> {
+    u.choose(&[
+        MemoryAccessMode::SeqCst,
+        MemoryAccessMode::Rel,
+        MemoryAccessMode::Acq,
+        MemoryAccessMode::RelAcq,
+        MemoryAccessMode::Rlx,
+    ])
+    .copied()
+}
+
+impl<'a> Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let instruction = match u.int_in_range(0..=13)? {
+            // Immediates are written without a sign
+            0 => Instruction::AssignConst(register(u)?, u.int_in_range(0..=i64::MAX)?),
+            1 => {
+                let op = *u.choose(&[
+                    ArithCommand::Add,
+                    ArithCommand::Sub,
+                    ArithCommand::Mul,
+                    ArithCommand::Div,
+                ])?;
+                Instruction::AssignOperation(register(u)?, register(u)?, op, register(u)?)
+            }
+            2 => Instruction::ConditionalJump(register(u)?, label(u)?),
+            3 => {
+                let cmp = *u.choose(&[
+                    CmpCommand::Lt,
+                    CmpCommand::Le,
+                    CmpCommand::Gt,
+                    CmpCommand::Ge,
+                    CmpCommand::Eq,
+                    CmpCommand::Ne,
+                ])?;
+                Instruction::CondJumpCmp(register(u)?, cmp, register(u)?, label(u)?)
+            }
+            4 => Instruction::Load(mode(u)?, location(u)?, register(u)?),
+            5 => Instruction::Store(mode(u)?, register(u)?, location(u)?),
+            6 => Instruction::Cas(
+                register(u)?,
+                mode(u)?,
+                location(u)?,
+                register(u)?,
+                register(u)?,
+            ),
+            7 => Instruction::Cas2(
+                registers(u)?,
+                mode(u)?,
+                [location(u)?, location(u)?],
+                registers(u)?,
+                registers(u)?,
+            ),
+            8 => Instruction::Fai(register(u)?, mode(u)?, location(u)?, register(u)?),
+            9 => {
+                let scope = *u.choose(&[FenceScope::Global, FenceScope::Local])?;
+                Instruction::Fence(mode(u)?, scope)
+            }
+            10 => Instruction::FenceAddr(mode(u)?, location(u)?),
+            11 => match u.arbitrary()? {
+                true => Instruction::Print(register(u)?),
+                false => Instruction::Print(location(u)?),
+            },
+            12 => {
+                let op = *u.choose(&[MinMax::Min, MinMax::Max])?;
+                Instruction::FetchMinMax(register(u)?, op, mode(u)?, location(u)?, register(u)?)
+            }
+            _ => Instruction::Nop,
+        };
+        Ok(instruction)
+    }
+}
+
+/// A labeled instruction of thread 0 at line 0, labeled from the same pool
+/// jumps go to.
+impl<'a> Arbitrary<'a> for LabeledInstruction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let label = match u.arbitrary()? {
+            true => Some(label(u)?),
+            false => None,
+        };
+        Ok(LabeledInstruction::new(label, u.arbitrary()?, 0, 0))
+    }
+}
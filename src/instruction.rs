@@ -1,6 +1,31 @@
+use crate::dependency_graph::NodeId;
+use crate::symbol::SymbolId;
 use std::fmt::Display;
 use std::str::FromStr;
 
+/// A value held in a register or in shared memory, as opposed to the
+/// immediates and line numbers of the program text.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Value(pub i64);
+
+impl Value {
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ArithCommand {
     Add,
@@ -9,14 +34,60 @@ pub enum ArithCommand {
     Div,
 }
 
+/// How arithmetic treats results that don't fit into a value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ArithMode {
+    #[default]
+    Wrapping,
+    Saturating,
+    /// Overflow is a runtime error.
+    Checked,
+}
+
+/// How subtraction treats a result below zero. When set, it overrides the
+/// `ArithMode` for subtraction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubMode {
+    /// Goes below zero, `2 - 5` is `-3`.
+    SignedWrap,
+    /// Clamps at zero (monus), for counters that can't go negative.
+    SaturateZero,
+    /// A result below zero is a runtime error.
+    ErrorOnUnderflow,
+}
+
 impl ArithCommand {
-    pub fn apply(&self, lhs: usize, rhs: usize) -> usize {
-        match self {
-            Self::Add => lhs + rhs,
-            Self::Sub => lhs - rhs,
-            Self::Mul => lhs * rhs,
-            Self::Div => lhs / rhs,
+    /// `None` on overflow in `Checked` mode, on underflow in
+    /// `SubMode::ErrorOnUnderflow`, and on division by zero.
+    pub fn apply(
+        &self,
+        lhs: Value,
+        rhs: Value,
+        mode: ArithMode,
+        sub_mode: Option<SubMode>,
+    ) -> Option<Value> {
+        let (lhs, rhs) = (lhs.0, rhs.0);
+        if let (Self::Sub, Some(sub_mode)) = (self, sub_mode) {
+            let result = match sub_mode {
+                SubMode::SignedWrap => Some(lhs.wrapping_sub(rhs)),
+                SubMode::SaturateZero => Some(lhs.saturating_sub(rhs).max(0)),
+                SubMode::ErrorOnUnderflow => lhs.checked_sub(rhs).filter(|value| *value >= 0),
+            };
+            return result.map(Value);
         }
+        let result = match (mode, self) {
+            (_, Self::Div) => lhs.checked_div(rhs),
+            (ArithMode::Wrapping, Self::Add) => Some(lhs.wrapping_add(rhs)),
+            (ArithMode::Wrapping, Self::Sub) => Some(lhs.wrapping_sub(rhs)),
+            (ArithMode::Wrapping, Self::Mul) => Some(lhs.wrapping_mul(rhs)),
+            (ArithMode::Saturating, Self::Add) => Some(lhs.saturating_add(rhs)),
+            (ArithMode::Saturating, Self::Sub) => Some(lhs.saturating_sub(rhs)),
+            (ArithMode::Saturating, Self::Mul) => Some(lhs.saturating_mul(rhs)),
+            (ArithMode::Checked, Self::Add) => lhs.checked_add(rhs),
+            (ArithMode::Checked, Self::Sub) => lhs.checked_sub(rhs),
+            (ArithMode::Checked, Self::Mul) => lhs.checked_mul(rhs),
+        };
+        result.map(Value)
     }
 }
 
@@ -31,6 +102,68 @@ impl Display for ArithCommand {
     }
 }
 
+/// Which of the old value and the operand `fetch_min` and `fetch_max` store.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MinMax {
+    Min,
+    Max,
+}
+
+impl MinMax {
+    /// The value stored over `old`.
+    pub fn apply(&self, old: Value, operand: Value) -> Value {
+        match self {
+            Self::Min => old.min(operand),
+            Self::Max => old.max(operand),
+        }
+    }
+}
+
+impl Display for MinMax {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MinMax::Min => write!(f, "fetch_min"),
+            MinMax::Max => write!(f, "fetch_max"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CmpCommand {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl CmpCommand {
+    pub fn holds(&self, lhs: Value, rhs: Value) -> bool {
+        match self {
+            CmpCommand::Lt => lhs < rhs,
+            CmpCommand::Le => lhs <= rhs,
+            CmpCommand::Gt => lhs > rhs,
+            CmpCommand::Ge => lhs >= rhs,
+            CmpCommand::Eq => lhs == rhs,
+            CmpCommand::Ne => lhs != rhs,
+        }
+    }
+}
+
+impl Display for CmpCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CmpCommand::Lt => write!(f, "<"),
+            CmpCommand::Le => write!(f, "<="),
+            CmpCommand::Gt => write!(f, ">"),
+            CmpCommand::Ge => write!(f, ">="),
+            CmpCommand::Eq => write!(f, "=="),
+            CmpCommand::Ne => write!(f, "!="),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum MemoryAccessMode {
     SeqCst,
@@ -40,6 +173,53 @@ pub enum MemoryAccessMode {
     Rlx,
 }
 
+impl MemoryAccessMode {
+    /// Parses a mode case-insensitively, accepting the C++ `memory_order`
+    /// names (`relaxed`, `acquire`, `release`, `acq_rel`, `seq_cst`) too.
+    fn from_alias(mode: &str) -> Option<Self> {
+        const ALIASES: &[(&str, MemoryAccessMode)] = &[
+            ("seq_cst", MemoryAccessMode::SeqCst),
+            ("rel", MemoryAccessMode::Rel),
+            ("release", MemoryAccessMode::Rel),
+            ("acq", MemoryAccessMode::Acq),
+            ("acquire", MemoryAccessMode::Acq),
+            ("rel_acq", MemoryAccessMode::RelAcq),
+            ("acq_rel", MemoryAccessMode::RelAcq),
+            ("rlx", MemoryAccessMode::Rlx),
+            ("relaxed", MemoryAccessMode::Rlx),
+        ];
+        ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(mode))
+            .map(|&(_, mode)| mode)
+    }
+}
+
+impl FromStr for MemoryAccessMode {
+    type Err = Error;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        Self::from_alias(mode).ok_or_else(|| Error::InvalidCommand(mode.to_string()))
+    }
+}
+
+/// How programs are parsed. The `parse_*` functions and `FromStr` use the
+/// default options.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParseOptions {
+    /// Access mode of loads and stores written without one, like
+    /// `load #x r1`. Relaxed by default.
+    pub default_mode: MemoryAccessMode,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            default_mode: MemoryAccessMode::Rlx,
+        }
+    }
+}
+
 impl Display for MemoryAccessMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -52,28 +232,76 @@ impl Display for MemoryAccessMode {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// What a fence orders. A `Local` fence only keeps the thread's instructions
+/// from being reordered around it, like a compiler fence. A `Global` fence
+/// also waits for the thread's buffered writes to reach memory.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum FenceScope {
+    Local,
+    #[default]
+    Global,
+}
+
+impl FromStr for FenceScope {
+    type Err = Error;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "local" => Ok(Self::Local),
+            "global" => Ok(Self::Global),
+            _ => Err(Error::InvalidCommand(scope.to_string())),
+        }
+    }
+}
+
+impl Display for FenceScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenceScope::Local => write!(f, "local"),
+            FenceScope::Global => write!(f, "global"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Reference {
-    Register(String),
-    Memory(String),
+    Register(SymbolId),
+    Memory(SymbolId),
+}
+
+/// Whether `name` is made of letters, digits and underscores and doesn't
+/// start with a digit, as names of registers, memory locations and labels
+/// are.
+fn is_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 impl FromStr for Reference {
-    type Err = ();
+    type Err = Error;
 
+    /// `#name` is a memory location, `name` a register. Names are made of
+    /// letters, digits and underscores and don't start with a digit.
     fn from_str(cmd: &str) -> Result<Self, Self::Err> {
-        return match cmd.as_bytes() {
-            [b'#', rest @ ..] => Ok(Self::Memory(std::str::from_utf8(rest).unwrap().to_string())),
-            _ => Ok(Self::Register(cmd.to_string())),
+        let (is_memory, name) = match cmd.strip_prefix('#') {
+            Some(name) => (true, name),
+            None => (false, cmd),
         };
+        if !is_name(name) {
+            return Err(Error::InvalidReference(cmd.to_string()));
+        }
+        match is_memory {
+            true => Ok(Self::Memory(SymbolId::intern(name))),
+            false => Ok(Self::Register(SymbolId::intern(name))),
+        }
     }
 }
 
 impl Display for Reference {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Reference::Register(reg) => write!(f, "r{}", reg),
-            Reference::Memory(mem) => write!(f, "m{}", mem),
+            Reference::Register(reg) => write!(f, "{}", reg),
+            Reference::Memory(mem) => write!(f, "#{}", mem),
         }
     }
 }
@@ -81,8 +309,9 @@ impl Display for Reference {
 #[derive(Clone, Debug, PartialEq)]
 pub enum Command {
     Arith(ArithCommand),
+    Cmp(CmpCommand),
     Ref(Reference),
-    Number(usize),
+    Number(i64),
     MemoryAccess(MemoryAccessMode),
     Eq,
     Assign,
@@ -92,7 +321,11 @@ pub enum Command {
     Goto,
     Fence,
     Cas,
+    Cas2,
     Fai,
+    FetchMinMax(MinMax),
+    Print,
+    Nop,
     Label(String),
 }
 
@@ -100,22 +333,111 @@ pub enum Command {
 pub enum Error {
     InvalidCommand(String),
     InvalidInstruction(String),
+    InvalidCondition(String),
+    InvalidLitmus(String),
+    InvalidSnapshot(String),
+    InvalidReference(String),
+    /// A label defined on more than one line of a thread's program.
+    DuplicateLabel {
+        thread_id: usize,
+        label: String,
+    },
+    /// Programs exceeding one of the `Limits`.
+    TooManyThreads {
+        max: usize,
+    },
+    ProgramTooLong {
+        thread_id: usize,
+        max: usize,
+    },
+    TooManyAddresses {
+        max: usize,
+    },
+    /// A program line that failed to parse, with its 1-based line number in
+    /// the source and the underlying error.
+    InvalidLine {
+        file: Option<String>,
+        line: usize,
+        thread_id: usize,
+        text: String,
+        cause: Box<Error>,
+    },
+    /// A line of a memory initialization file that isn't `addr value`.
+    InvalidMemoryInit {
+        file: Option<String>,
+        line: usize,
+        text: String,
+    },
+    /// A scheduled option index out of range at its step.
+    InvalidSchedule {
+        step: usize,
+        index: usize,
+        options: usize,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidCommand(cmd) => write!(f, "invalid command '{}'", cmd),
+            Error::InvalidInstruction(cmd) => write!(f, "invalid instruction '{}'", cmd),
+            Error::InvalidCondition(cond) => write!(f, "invalid condition '{}'", cond),
+            Error::InvalidLitmus(message) => write!(f, "invalid litmus test: {}", message),
+            Error::InvalidSnapshot(message) => write!(f, "invalid snapshot: {}", message),
+            Error::InvalidReference(reference) => write!(f, "invalid reference '{}'", reference),
+            Error::DuplicateLabel { thread_id, label } => {
+                write!(f, "label '{}' defined twice in thread {}", label, thread_id)
+            }
+            Error::TooManyThreads { max } => write!(f, "more than {} threads", max),
+            Error::ProgramTooLong { thread_id, max } => write!(
+                f,
+                "program of thread {} longer than {} instructions",
+                thread_id, max
+            ),
+            Error::TooManyAddresses { max } => {
+                write!(f, "more than {} distinct memory addresses", max)
+            }
+            Error::InvalidLine {
+                file, line, text, ..
+            } => match file {
+                Some(file) => write!(f, "{}:{}: invalid instruction '{}'", file, line, text),
+                None => write!(f, "line {}: invalid instruction '{}'", line, text),
+            },
+            Error::InvalidMemoryInit { file, line, text } => match file {
+                Some(file) => write!(f, "{}:{}: invalid memory value '{}'", file, line, text),
+                None => write!(f, "line {}: invalid memory value '{}'", line, text),
+            },
+            Error::InvalidSchedule {
+                step,
+                index,
+                options,
+            } => write!(
+                f,
+                "invalid schedule: step {} chooses option {}, but there are {} options",
+                step, index, options
+            ),
+        }
+    }
 }
 
 impl FromStr for Command {
     type Err = Error;
 
     fn from_str(cmd: &str) -> Result<Self, Self::Err> {
-        return match cmd.as_bytes() {
+        if let Some(mode) = MemoryAccessMode::from_alias(cmd) {
+            return Ok(mode.into());
+        }
+        match cmd.as_bytes() {
             b"+" => Ok(ArithCommand::Add.into()),
             b"-" => Ok(ArithCommand::Sub.into()),
             b"/" => Ok(ArithCommand::Div.into()),
             b"*" => Ok(ArithCommand::Mul.into()),
-            b"SEQ_CST" => Ok(MemoryAccessMode::SeqCst.into()),
-            b"REL" => Ok(MemoryAccessMode::Rel.into()),
-            b"ACQ" => Ok(MemoryAccessMode::Acq.into()),
-            b"REL_ACQ" => Ok(MemoryAccessMode::RelAcq.into()),
-            b"RLX" => Ok(MemoryAccessMode::Rlx.into()),
+            b"<" => Ok(CmpCommand::Lt.into()),
+            b"<=" => Ok(CmpCommand::Le.into()),
+            b">" => Ok(CmpCommand::Gt.into()),
+            b">=" => Ok(CmpCommand::Ge.into()),
+            b"==" => Ok(CmpCommand::Eq.into()),
+            b"!=" => Ok(CmpCommand::Ne.into()),
             b"=" => Ok(Self::Eq),
             b":=" => Ok(Self::Assign),
             b"load" => Ok(Self::Load),
@@ -124,18 +446,29 @@ impl FromStr for Command {
             b"goto" => Ok(Self::Goto),
             b"fence" => Ok(Self::Fence),
             b"cas" => Ok(Self::Cas),
+            b"cas2" => Ok(Self::Cas2),
             b"fai" => Ok(Self::Fai),
-            reference if !reference.first().unwrap().is_ascii_digit() => Ok(Self::Ref(
-                Reference::from_str(std::str::from_utf8(reference).unwrap())
-                    .unwrap()
-                    .into(),
-            )),
-            num => std::str::from_utf8(num)
-                .unwrap()
-                .parse::<usize>()
-                .map(Self::Number)
-                .map_err(|_| Error::InvalidCommand(cmd.to_string())),
-        };
+            b"fetch_min" => Ok(Self::FetchMinMax(MinMax::Min)),
+            b"fetch_max" => Ok(Self::FetchMinMax(MinMax::Max)),
+            b"print" => Ok(Self::Print),
+            b"nop" => Ok(Self::Nop),
+            [] => Err(Error::InvalidCommand(cmd.to_string())),
+            [first, ..] if !first.is_ascii_digit() => Ok(Self::Ref(Reference::from_str(cmd)?)),
+            _ => {
+                // `0x1F` and `0b1010` literals besides decimal ones
+                let (digits, radix) = match cmd.get(..2) {
+                    Some("0x" | "0X") => (&cmd[2..], 16),
+                    Some("0b" | "0B") => (&cmd[2..], 2),
+                    _ => (cmd, 10),
+                };
+                match digits.chars().all(|c| c.is_digit(radix)) {
+                    true => i64::from_str_radix(digits, radix)
+                        .map(Self::Number)
+                        .map_err(|_| Error::InvalidCommand(cmd.to_string())),
+                    false => Err(Error::InvalidCommand(cmd.to_string())),
+                }
+            }
+        }
     }
 }
 
@@ -145,40 +478,287 @@ impl From<ArithCommand> for Command {
     }
 }
 
+impl From<CmpCommand> for Command {
+    fn from(cmd: CmpCommand) -> Self {
+        Self::Cmp(cmd)
+    }
+}
+
 impl From<MemoryAccessMode> for Command {
     fn from(cmd: MemoryAccessMode) -> Self {
         Self::MemoryAccess(cmd)
     }
 }
 
+#[derive(Clone)]
 pub struct WriteOperation {
-    pub(crate) addr: String,
-    pub(crate) value: usize,
+    pub(crate) addr: SymbolId,
+    pub(crate) value: Value,
+    /// Node of the instruction that made the write, if known.
+    pub(crate) source: Option<NodeId>,
 }
 
 impl WriteOperation {
-    pub fn new(addr: String, value: usize) -> Self {
-        Self { addr, value }
+    pub fn new(addr: SymbolId, value: Value) -> Self {
+        Self {
+            addr,
+            value,
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: Option<NodeId>) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+impl Display for WriteOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} := {}", self.addr, self.value)
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Instruction {
-    AssignConst(Reference, usize),
+    AssignConst(Reference, i64),
     AssignOperation(Reference, Reference, ArithCommand, Reference),
     ConditionalJump(Reference, String),
+    CondJumpCmp(Reference, CmpCommand, Reference, String),
     Load(MemoryAccessMode, Reference, Reference),
     Store(MemoryAccessMode, Reference, Reference),
     Cas(Reference, MemoryAccessMode, Reference, Reference, Reference),
+    /// Double-width compare-and-swap: destinations, mode, addresses,
+    /// expected and desired values, each a pair. Writes both addresses only
+    /// if both hold their expected value; the destinations always receive
+    /// the values read.
+    Cas2(
+        [Reference; 2],
+        MemoryAccessMode,
+        [Reference; 2],
+        [Reference; 2],
+        [Reference; 2],
+    ),
     Fai(Reference, MemoryAccessMode, Reference, Reference),
-    Fence(MemoryAccessMode),
+    /// Atomic minimum or maximum: destination, which of the two, mode,
+    /// address and operand. Reads the old value into the destination and
+    /// stores the minimum or maximum of it and the operand.
+    FetchMinMax(Reference, MinMax, MemoryAccessMode, Reference, Reference),
+    Fence(MemoryAccessMode, FenceScope),
+    FenceAddr(MemoryAccessMode, Reference),
+    /// Outputs the current value of a register or, as the thread sees it, of
+    /// a memory location. Orders nothing.
+    Print(Reference),
+    Nop,
 }
 
 impl Instruction {
     pub fn is_memory_access(&self) -> bool {
+        matches!(self, Self::Load(_, _, _) | Self::Store(_, _, _))
+    }
+
+    /// Memory location read by the instruction, if any. For `cas2`, the
+    /// first of its two.
+    pub fn read_location(&self) -> Option<&Reference> {
+        self.read_locations().first().copied()
+    }
+
+    /// Memory location written by the instruction, if any. For `cas2`, the
+    /// first of its two.
+    pub fn write_location(&self) -> Option<&Reference> {
+        self.write_locations().first().copied()
+    }
+
+    /// Every memory location read by the instruction.
+    pub fn read_locations(&self) -> Vec<&Reference> {
         match self {
-            Self::Load(_, _, _) | Self::Store(_, _, _) => true,
-            _ => false,
+            Self::Load(_, addr, _)
+            | Self::Cas(_, _, addr, _, _)
+            | Self::Fai(_, _, addr, _)
+            | Self::FetchMinMax(_, _, _, addr, _) => vec![addr],
+            Self::Cas2(_, _, addrs, _, _) => addrs.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Every memory location written by the instruction.
+    pub fn write_locations(&self) -> Vec<&Reference> {
+        match self {
+            Self::Store(_, _, addr)
+            | Self::Cas(_, _, addr, _, _)
+            | Self::Fai(_, _, addr, _)
+            | Self::FetchMinMax(_, _, _, addr, _) => vec![addr],
+            Self::Cas2(_, _, addrs, _, _) => addrs.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Every register and memory location the instruction names.
+    pub fn operands(&self) -> Vec<&Reference> {
+        match self {
+            Self::AssignConst(dest, _) => vec![dest],
+            Self::AssignOperation(dest, lhs, _, rhs) => vec![dest, lhs, rhs],
+            Self::ConditionalJump(cond, _) => vec![cond],
+            Self::CondJumpCmp(lhs, _, rhs, _) => vec![lhs, rhs],
+            Self::Load(_, addr, dest) => vec![addr, dest],
+            Self::Store(_, value, addr) => vec![value, addr],
+            Self::Cas(dest, _, addr, old, new) => vec![dest, addr, old, new],
+            Self::Cas2(dests, _, addrs, old, new) => {
+                [dests, addrs, old, new].into_iter().flatten().collect()
+            }
+            Self::Fai(dest, _, addr, value) | Self::FetchMinMax(dest, _, _, addr, value) => {
+                vec![dest, addr, value]
+            }
+            Self::FenceAddr(_, addr) => vec![addr],
+            Self::Print(operand) => vec![operand],
+            Self::Fence(_, _) | Self::Nop => vec![],
+        }
+    }
+
+    /// Registers whose value the instruction reads.
+    pub fn read_registers(&self) -> Vec<&Reference> {
+        let operands = match self {
+            Self::AssignOperation(_, lhs, _, rhs) | Self::CondJumpCmp(lhs, _, rhs, _) => {
+                vec![lhs, rhs]
+            }
+            Self::ConditionalJump(cond, _) => vec![cond],
+            Self::Store(_, value, _)
+            | Self::Fai(_, _, _, value)
+            | Self::FetchMinMax(_, _, _, _, value) => vec![value],
+            Self::Cas(_, _, _, old, new) => vec![old, new],
+            Self::Cas2(_, _, _, old, new) => old.iter().chain(new).collect(),
+            Self::Print(operand) => vec![operand],
+            _ => vec![],
+        };
+        operands
+            .into_iter()
+            .filter(|operand| matches!(operand, Reference::Register(_)))
+            .collect()
+    }
+
+    /// Registers the instruction writes.
+    pub fn write_registers(&self) -> Vec<&Reference> {
+        match self {
+            Self::AssignConst(dest, _)
+            | Self::AssignOperation(dest, _, _, _)
+            | Self::Load(_, _, dest)
+            | Self::Cas(dest, _, _, _, _)
+            | Self::Fai(dest, _, _, _)
+            | Self::FetchMinMax(dest, _, _, _, _) => vec![dest],
+            Self::Cas2(dests, _, _, _, _) => dests.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// What executing the instruction does, in a sentence.
+    pub fn semantics(&self) -> String {
+        match self {
+            Self::AssignConst(dest, value) => format!("Sets {} to {}.", dest, value),
+            Self::AssignOperation(dest, lhs, op, rhs) => {
+                format!("Sets {} to {} {} {}.", dest, lhs, op, rhs)
+            }
+            Self::ConditionalJump(cond, label) => {
+                format!("Jumps to {} or falls through, depending on {}.", label, cond)
+            }
+            Self::CondJumpCmp(lhs, cmp, rhs, label) => format!(
+                "Jumps to {} or falls through, depending on whether {} {} {}.",
+                label, lhs, cmp, rhs
+            ),
+            Self::Load(mode, addr, dest) => {
+                format!("Loads {} into {}, with access mode {}.", addr, dest, mode)
+            }
+            Self::Store(mode, value, addr) => {
+                format!("Stores {} to {}, with access mode {}.", value, addr, mode)
+            }
+            Self::Cas(dest, mode, addr, old, new) => format!(
+                "Reads {} into {} and, if it equals {}, writes {} to it, atomically with access mode {}.",
+                addr, dest, old, new, mode
+            ),
+            Self::Cas2(dests, mode, addrs, old, new) => format!(
+                "Reads {} and {} into {} and {} and, if they equal {} and {}, writes {} and {} to them, atomically with access mode {}.",
+                addrs[0], addrs[1], dests[0], dests[1], old[0], old[1], new[0], new[1], mode
+            ),
+            Self::Fai(dest, mode, addr, value) => format!(
+                "Adds {} to {} and reads its old value into {}, atomically with access mode {}.",
+                value, addr, dest, mode
+            ),
+            Self::FetchMinMax(dest, op, mode, addr, value) => format!(
+                "Stores the {} of {} and {} to it and reads its old value into {}, atomically with access mode {}.",
+                match op {
+                    MinMax::Min => "minimum",
+                    MinMax::Max => "maximum",
+                },
+                addr,
+                value,
+                dest,
+                mode
+            ),
+            Self::Fence(mode, FenceScope::Global) => format!(
+                "A {} fence: orders the thread's instructions around it and waits for its buffered writes.",
+                mode
+            ),
+            Self::Fence(mode, FenceScope::Local) => format!(
+                "A local {} fence: orders the thread's instructions around it.",
+                mode
+            ),
+            Self::FenceAddr(mode, addr) => {
+                format!("A {} fence on the accesses to {}.", mode, addr)
+            }
+            Self::Print(operand) => format!("Prints the value of {}.", operand),
+            Self::Nop => "Does nothing.".to_string(),
+        }
+    }
+
+    /// Label the instruction may jump to, if any.
+    pub fn jump_target(&self) -> Option<&str> {
+        match self {
+            Self::ConditionalJump(_, label) | Self::CondJumpCmp(_, _, _, label) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// Whether the jump is taken, when that doesn't depend on register
+    /// values. Comparing a register with itself decides the jump, so
+    /// `if r0 == r0 goto L` is an unconditional jump.
+    pub fn static_jump(&self) -> Option<bool> {
+        match self {
+            Self::CondJumpCmp(lhs, cmp, rhs, _) if lhs == rhs => {
+                Some(cmp.holds(Value::default(), Value::default()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Instruction {
+    /// The instruction in the program syntax accepted by `from_str`.
+    pub fn to_source(&self) -> String {
+        match self {
+            Instruction::AssignConst(dest, value) => format!("{} = {}", dest, value),
+            Instruction::AssignOperation(dest, lhs, op, rhs) => {
+                format!("{} = {} {} {}", dest, lhs, op, rhs)
+            }
+            Instruction::ConditionalJump(cond, label) => format!("if {} goto {}", cond, label),
+            Instruction::CondJumpCmp(lhs, cmp, rhs, label) => {
+                format!("if {} {} {} goto {}", lhs, cmp, rhs, label)
+            }
+            Instruction::Load(mode, addr, dest) => format!("load {} {} {}", mode, addr, dest),
+            Instruction::Store(mode, value, addr) => format!("store {} {} {}", mode, value, addr),
+            Instruction::Cas(dest, mode, addr, old, new) => {
+                format!("{} := cas {} {} {} {}", dest, mode, addr, old, new)
+            }
+            Instruction::Cas2(..) => self.to_string(),
+            Instruction::Fai(dest, mode, addr, value) => {
+                format!("{} := fai {} {} {}", dest, mode, addr, value)
+            }
+            Instruction::FetchMinMax(dest, op, mode, addr, value) => {
+                format!("{} := {} {} {} {}", dest, op, mode, addr, value)
+            }
+            Instruction::Fence(mode, FenceScope::Global) => format!("fence {}", mode),
+            Instruction::Fence(mode, scope) => format!("fence {} {}", mode, scope),
+            Instruction::FenceAddr(mode, addr) => format!("fence {} {}", mode, addr),
+            Instruction::Print(operand) => format!("print {}", operand),
+            Instruction::Nop => "nop".to_string(),
         }
     }
 }
@@ -195,26 +775,48 @@ impl Display for Instruction {
             Instruction::ConditionalJump(cond, label) => {
                 write!(f, "if {} goto {}", cond, label)
             }
-            Instruction::Load(mode, dest, addr) => {
+            Instruction::CondJumpCmp(lhs, cmp, rhs, label) => {
+                write!(f, "if {} {} {} goto {}", lhs, cmp, rhs, label)
+            }
+            Instruction::Load(mode, addr, dest) => {
                 write!(f, "{} := load {} {}", dest, mode, addr)
             }
-            Instruction::Store(mode, addr, value) => {
-                write!(f, "store {} {} {}", mode, addr, value)
+            Instruction::Store(mode, value, addr) => {
+                write!(f, "store {} {} {}", mode, value, addr)
             }
             Instruction::Cas(dest, mode, addr, old, new) => {
                 write!(f, "{} := cas {} {} {} {}", dest, mode, addr, old, new)
             }
+            Instruction::Cas2([dest1, dest2], mode, [addr1, addr2], [old1, old2], [new1, new2]) => {
+                write!(
+                    f,
+                    "{} {} := cas2 {} {} {} {} {} {} {}",
+                    dest1, dest2, mode, addr1, addr2, old1, old2, new1, new2
+                )
+            }
             Instruction::Fai(dest, mode, addr, value) => {
                 write!(f, "{} := fai {} {} {}", dest, mode, addr, value)
             }
-            Instruction::Fence(mode) => {
+            Instruction::FetchMinMax(dest, op, mode, addr, value) => {
+                write!(f, "{} := {} {} {} {}", dest, op, mode, addr, value)
+            }
+            Instruction::Fence(mode, FenceScope::Global) => {
                 write!(f, "fence {}", mode)
             }
+            Instruction::Fence(mode, scope) => {
+                write!(f, "fence {} {}", mode, scope)
+            }
+            Instruction::FenceAddr(mode, addr) => {
+                write!(f, "fence {} {}", mode, addr)
+            }
+            Instruction::Print(operand) => write!(f, "print {}", operand),
+            Instruction::Nop => write!(f, "nop"),
         }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LabeledInstruction {
     pub label: Option<String>,
     pub instruction: Instruction,
@@ -239,19 +841,30 @@ impl LabeledInstruction {
 
     pub(crate) fn label(cmd: &str) -> (Option<String>, String) {
         let commands: Vec<&str> = cmd.split_whitespace().collect::<Vec<&str>>();
-        let (label, commands) = if commands.first().unwrap().ends_with(':') {
-            let label = commands.first().unwrap().replace(":", "").to_string();
-            (Some(label), &commands[1..])
-        } else {
-            (None, commands.as_slice())
+        let (label, commands) = match commands.as_slice() {
+            [first, rest @ ..] if first.ends_with(':') => (Some(first.replace(':', "")), rest),
+            commands => (None, commands),
         };
-        let cmd = commands.join(" ");
-        (label, cmd.to_string())
+        // A label on its own line marks a jump target: `L1:` is `L1: nop`.
+        let cmd = match (&label, commands) {
+            (Some(_), []) => "nop".to_string(),
+            _ => commands.join(" "),
+        };
+        (label, cmd)
     }
 
     pub fn from_line(line: &str, line_index: usize, thread_id: usize) -> Result<Self, Error> {
+        Self::from_line_with(line, line_index, thread_id, ParseOptions::default())
+    }
+
+    pub fn from_line_with(
+        line: &str,
+        line_index: usize,
+        thread_id: usize,
+        options: ParseOptions,
+    ) -> Result<Self, Error> {
         let (label, cmd) = Self::label(line);
-        let instruction = Instruction::from_str(cmd.as_str())?;
+        let instruction = Instruction::parse_with(cmd.as_str(), options)?;
         Ok(Self::new(label, instruction, line_index, thread_id))
     }
 
@@ -275,35 +888,223 @@ impl FromStr for LabeledInstruction {
     }
 }
 
-impl ToString for LabeledInstruction {
-    fn to_string(&self) -> String {
+impl Display for LabeledInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let label = match &self.label {
             Some(label) => format!("{}: ", label),
             None => "".to_string(),
         };
 
-        format!(
+        write!(
+            f,
             "Thread {}, line {}: {}{}",
-            self.thread_id,
-            self.line_index,
-            label,
-            self.instruction.to_string()
+            self.thread_id, self.line_index, label, self.instruction
         )
     }
 }
 
+/// Kind of a token in an instruction form.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// The given word or symbol, verbatim.
+    Keyword(&'static str),
+    /// A register, or a memory location written `#name`.
+    Reference,
+    /// A memory location written `#name`.
+    Memory,
+    /// A decimal, `0x` hexadecimal or `0b` binary literal.
+    Number,
+    /// One of `+ - * /`.
+    ArithOp,
+    /// One of `< <= > >= == !=`.
+    CmpOp,
+    /// A memory access mode such as `RLX` or `acquire`.
+    AccessMode,
+    /// A jump target label.
+    Label,
+    /// `local` or `global`.
+    FenceScope,
+}
+
+/// Token pattern accepted for an `Instruction` variant, named after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstructionForm {
+    pub name: &'static str,
+    pub tokens: &'static [TokenKind],
+}
+
+/// Every instruction form `Instruction::from_str` accepts, in the order of
+/// its match arms. A line may be prefixed by a `label:`.
+pub fn grammar() -> &'static [InstructionForm] {
+    use TokenKind::*;
+    const fn form(name: &'static str, tokens: &'static [TokenKind]) -> InstructionForm {
+        InstructionForm { name, tokens }
+    }
+    const GRAMMAR: &[InstructionForm] = &[
+        form("AssignConst", &[Reference, Keyword("="), Number]),
+        form(
+            "AssignOperation",
+            &[Reference, Keyword("="), Reference, ArithOp, Reference],
+        ),
+        form(
+            "ConditionalJump",
+            &[Keyword("if"), Reference, Keyword("goto"), Label],
+        ),
+        form(
+            "CondJumpCmp",
+            &[
+                Keyword("if"),
+                Reference,
+                CmpOp,
+                Reference,
+                Keyword("goto"),
+                Label,
+            ],
+        ),
+        form("Load", &[Keyword("load"), AccessMode, Reference, Reference]),
+        form("Load", &[Keyword("load"), Reference, Reference]),
+        form(
+            "Store",
+            &[Keyword("store"), AccessMode, Reference, Reference],
+        ),
+        form("Store", &[Keyword("store"), Reference, Reference]),
+        form(
+            "Cas",
+            &[
+                Reference,
+                Keyword(":="),
+                Keyword("cas"),
+                AccessMode,
+                Reference,
+                Reference,
+                Reference,
+            ],
+        ),
+        form(
+            "Cas2",
+            &[
+                Reference,
+                Reference,
+                Keyword(":="),
+                Keyword("cas2"),
+                AccessMode,
+                Reference,
+                Reference,
+                Reference,
+                Reference,
+                Reference,
+                Reference,
+            ],
+        ),
+        form(
+            "Fai",
+            &[
+                Reference,
+                Keyword(":="),
+                Keyword("fai"),
+                AccessMode,
+                Reference,
+                Reference,
+            ],
+        ),
+        form(
+            "FetchMinMax",
+            &[
+                Reference,
+                Keyword(":="),
+                Keyword("fetch_min"),
+                AccessMode,
+                Reference,
+                Reference,
+            ],
+        ),
+        form(
+            "FetchMinMax",
+            &[
+                Reference,
+                Keyword(":="),
+                Keyword("fetch_max"),
+                AccessMode,
+                Reference,
+                Reference,
+            ],
+        ),
+        form("Fence", &[Keyword("fence"), AccessMode]),
+        form("Fence", &[Keyword("fence"), AccessMode, FenceScope]),
+        form("FenceAddr", &[Keyword("fence"), AccessMode, Memory]),
+        form("Print", &[Keyword("print"), Reference]),
+        form("Nop", &[Keyword("nop")]),
+    ];
+    GRAMMAR
+}
+
+/// Splits `cmd` at whitespace and around the assignment operators `:=` and `=`,
+/// so `r1:=fai ACQ #x r2` tokenizes like `r1 := fai ACQ #x r2`. Comparison
+/// operators such as `==` and `<=` are kept whole. Tokens borrow from `cmd`.
+fn tokenize(cmd: &str) -> impl Iterator<Item = &str> {
+    cmd.split_whitespace().flat_map(|mut word| {
+        std::iter::from_fn(move || {
+            if word.is_empty() {
+                return None;
+            }
+            let (token, rest) = match assignment_operator(word) {
+                Some((0, end)) => word.split_at(end),
+                Some((start, _)) => word.split_at(start),
+                None => (word, ""),
+            };
+            word = rest;
+            Some(token)
+        })
+    })
+}
+
+/// Byte range of the first `:=` or lone `=` in `word`.
+fn assignment_operator(word: &str) -> Option<(usize, usize)> {
+    let bytes = word.as_bytes();
+    (0..bytes.len()).find_map(|i| {
+        if bytes[i] != b'=' || bytes.get(i + 1) == Some(&b'=') {
+            return None;
+        }
+        match i.checked_sub(1).map(|j| bytes[j]) {
+            Some(b':') => Some((i - 1, i + 1)),
+            Some(b'<' | b'>' | b'!' | b'=') => None,
+            _ => Some((i, i + 1)),
+        }
+    })
+}
+
+/// Token count of the longest instruction form, `cas2`.
+const MAX_TOKENS: usize = 11;
+
 impl FromStr for Instruction {
     type Err = Error;
 
     fn from_str(cmd: &str) -> Result<Self, Self::Err> {
-        fn str_to_commands(cmd: &str) -> Vec<Command> {
-            cmd.split_whitespace()
-                .map(|cmd| cmd.parse::<Command>().unwrap())
-                .collect()
-        }
+        Self::parse_with(cmd, ParseOptions::default())
+    }
+}
 
-        let commands = str_to_commands(cmd);
-        return match commands.as_slice() {
+impl Instruction {
+    pub fn parse_with(cmd: &str, options: ParseOptions) -> Result<Self, Error> {
+        // Parsed into a buffer on the stack rather than a `Vec`, as `cas2` is
+        // the longest form. Tokens past it are still parsed so that an invalid
+        // one is reported as such, and such a line matches no form.
+        let mut buffer: [Command; MAX_TOKENS] = std::array::from_fn(|_| Command::Nop);
+        let mut len: usize = 0;
+        for token in tokenize(cmd) {
+            // A jump target names a label rather than a register, so it isn't
+            // interned
+            let command = match len.checked_sub(1).and_then(|last| buffer.get(last)) {
+                Some(Command::Goto) if is_name(token) => Command::Label(token.to_string()),
+                _ => token.parse::<Command>()?,
+            };
+            if let Some(slot) = buffer.get_mut(len) {
+                *slot = command;
+            }
+            len += 1;
+        }
+        let commands = buffer.get(..len).unwrap_or_default();
+        match commands {
             [Command::Ref(ref1), Command::Eq, Command::Number(num)] => {
                 Ok(Self::AssignConst(ref1.clone(), *num))
             }
@@ -311,40 +1112,80 @@ impl FromStr for Instruction {
                 Ok(Self::AssignOperation(
                     ref1.clone(),
                     ref2.clone(),
-                    cmd.clone(),
+                    *cmd,
                     ref3.clone(),
                 ))
             }
-            [Command::If, Command::Ref(ref1), Command::Goto, Command::Ref(Reference::Register(label))] => {
+            [Command::If, Command::Ref(ref1), Command::Goto, Command::Label(label)] => {
                 Ok(Self::ConditionalJump(ref1.clone(), label.clone()))
             }
+            [Command::If, Command::Ref(ref1), Command::Cmp(cmp), Command::Ref(ref2), Command::Goto, Command::Label(label)] => {
+                Ok(Self::CondJumpCmp(
+                    ref1.clone(),
+                    *cmp,
+                    ref2.clone(),
+                    label.clone(),
+                ))
+            }
             [Command::Load, Command::MemoryAccess(mem_access), Command::Ref(addr), Command::Ref(reg)] => {
-                Ok(Self::Load(mem_access.clone(), addr.clone(), reg.clone()))
+                Ok(Self::Load(*mem_access, addr.clone(), reg.clone()))
             }
             [Command::Store, Command::MemoryAccess(mem_access), Command::Ref(addr), Command::Ref(reg)] => {
-                Ok(Self::Store(mem_access.clone(), addr.clone(), reg.clone()))
+                Ok(Self::Store(*mem_access, addr.clone(), reg.clone()))
+            }
+            [Command::Load, Command::Ref(addr), Command::Ref(reg)] => {
+                Ok(Self::Load(options.default_mode, addr.clone(), reg.clone()))
+            }
+            [Command::Store, Command::Ref(addr), Command::Ref(reg)] => {
+                Ok(Self::Store(options.default_mode, addr.clone(), reg.clone()))
             }
             [Command::Ref(ref1), Command::Assign, Command::Cas, Command::MemoryAccess(mem_access), Command::Ref(ref2), Command::Ref(ref3), Command::Ref(ref4)] => {
                 Ok(Self::Cas(
                     ref1.clone(),
-                    mem_access.clone(),
+                    *mem_access,
                     ref2.clone(),
                     ref3.clone(),
                     ref4.clone(),
                 ))
             }
+            [Command::Ref(dest1), Command::Ref(dest2), Command::Assign, Command::Cas2, Command::MemoryAccess(mem_access), Command::Ref(addr1), Command::Ref(addr2), Command::Ref(old1), Command::Ref(old2), Command::Ref(new1), Command::Ref(new2)] => {
+                Ok(Self::Cas2(
+                    [dest1.clone(), dest2.clone()],
+                    *mem_access,
+                    [addr1.clone(), addr2.clone()],
+                    [old1.clone(), old2.clone()],
+                    [new1.clone(), new2.clone()],
+                ))
+            }
             [Command::Ref(ref1), Command::Assign, Command::Fai, Command::MemoryAccess(mem_access), Command::Ref(ref2), Command::Ref(ref3)] => {
                 Ok(Self::Fai(
                     ref1.clone(),
-                    mem_access.clone(),
+                    *mem_access,
                     ref2.clone(),
                     ref3.clone(),
                 ))
             }
+            [Command::Ref(dest), Command::Assign, Command::FetchMinMax(op), Command::MemoryAccess(mem_access), Command::Ref(addr), Command::Ref(value)] => {
+                Ok(Self::FetchMinMax(
+                    dest.clone(),
+                    *op,
+                    *mem_access,
+                    addr.clone(),
+                    value.clone(),
+                ))
+            }
             [Command::Fence, Command::MemoryAccess(mem_access)] => {
-                Ok(Self::Fence(mem_access.clone()))
+                Ok(Self::Fence(*mem_access, FenceScope::Global))
+            }
+            [Command::Fence, Command::MemoryAccess(mem_access), Command::Ref(Reference::Register(scope))] => {
+                Ok(Self::Fence(*mem_access, scope.as_str().parse()?))
             }
+            [Command::Fence, Command::MemoryAccess(mem_access), Command::Ref(addr @ Reference::Memory(_))] => {
+                Ok(Self::FenceAddr(*mem_access, addr.clone()))
+            }
+            [Command::Print, Command::Ref(operand)] => Ok(Self::Print(operand.clone())),
+            [Command::Nop] => Ok(Self::Nop),
             _ => Err(Error::InvalidInstruction(cmd.to_string())),
-        };
+        }
     }
 }
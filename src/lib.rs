@@ -1,115 +1,963 @@
 mod dependency_graph;
+mod exploration;
+#[cfg(feature = "arbitrary")]
+mod fuzzing;
 mod instruction;
+pub mod litmus;
 mod memory_subsystem;
+mod propagation_policy;
+#[cfg(feature = "serde")]
+mod serialization;
+mod snapshot;
+mod symbol;
 mod thread_subsystem;
 mod utils;
 
-use crate::dependency_graph::InstructionNode;
+pub use dependency_graph::{
+    DependencyGraph, GraphError, HappensBefore, InstructionNode, MpReport, NodeId, NodeInfo,
+    NodeType, Propagate,
+};
+pub use exploration::Exploration;
 pub use instruction::{
-    ArithCommand, Command, Error, Instruction, LabeledInstruction, MemoryAccessMode, Reference,
+    grammar, ArithCommand, ArithMode, CmpCommand, Command, Error, FenceScope, Instruction,
+    InstructionForm, LabeledInstruction, MemoryAccessMode, MinMax, ParseOptions, Reference,
+    SubMode, TokenKind, Value, WriteOperation,
 };
 pub use memory_subsystem::Memory;
+pub use propagation_policy::{OldestFirst, PropagationPolicy, Random};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
-pub use thread_subsystem::{SequentialConsistency, TSO};
-pub use utils::programs_to_instructions;
+use std::str::FromStr;
+pub use symbol::{Symbol, SymbolId};
+pub use thread_subsystem::{
+    ExecutionError, FinalState, Location, OptionInfo, RunToResult, SequentialConsistency,
+    StateCondition, StepEffect, TSO,
+};
+pub use utils::{
+    mapped_programs_to_instructions, missing_labels, parse_memory_init, parse_program_str,
+    parse_programs_str, parse_single_file_str, parse_thread_mapping, programs_to_instructions,
+    reachable_lines, referenced_memory, referenced_registers, single_file_to_instructions,
+    syntax_errors, Limits,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MemoryModel {
+    SC,
+    TSO,
+    PSO,
+}
+
+impl MemoryModel {
+    /// Every model, from the strongest to the weakest.
+    pub const ALL: [MemoryModel; 3] = [Self::SC, Self::TSO, Self::PSO];
+}
+
+impl FromStr for MemoryModel {
+    type Err = Error;
+
+    fn from_str(model: &str) -> Result<Self, Self::Err> {
+        match model {
+            "SC" => Ok(Self::SC),
+            "TSO" => Ok(Self::TSO),
+            "PSO" => Ok(Self::PSO),
+            _ => Err(Error::InvalidCommand(model.to_string())),
+        }
+    }
+}
+
+/// Runs the exhaustive explorer of the given memory model, starting from the
+/// `init` values, and returns every reachable final state with the number of
+/// interleavings reaching it, and the executions failing at runtime.
+/// `eager_propagate` applies to TSO and PSO, see
+/// `TSO::with_eager_propagate`. More than one of `jobs` explores on that
+/// many threads, see `TSO::explore_counts_parallel`.
+pub fn explore_outcomes(
+    programs: Vec<Vec<LabeledInstruction>>,
+    init: &[(Location, i64)],
+    model: MemoryModel,
+    step_limit: usize,
+    mem_default: i64,
+    eager_propagate: bool,
+    jobs: usize,
+) -> Exploration<HashMap<FinalState, usize>> {
+    match model {
+        MemoryModel::SC => {
+            let mut system = SequentialConsistency::new(programs).with_mem_default(mem_default);
+            for (location, value) in init {
+                system.initialize(location, *value);
+            }
+            system.explore_counts_parallel(step_limit, jobs)
+        }
+        MemoryModel::TSO | MemoryModel::PSO => {
+            let mut system = TSO::new(programs, model == MemoryModel::PSO)
+                .with_mem_default(mem_default)
+                .with_eager_propagate(eager_propagate);
+            for (location, value) in init {
+                system.initialize(location, *value);
+            }
+            system.explore_counts_parallel(step_limit, jobs)
+        }
+    }
+}
+
+/// Explores the programs under every model of `MemoryModel::ALL`, as
+/// `explore_outcomes` does, and returns the fingerprint of every final state
+/// reachable under some model with whether each model reaches it. Errors are
+/// prefixed with the model the execution failed under.
+pub fn compare_outcomes(
+    programs: Vec<Vec<LabeledInstruction>>,
+    init: &[(Location, i64)],
+    step_limit: usize,
+    mem_default: i64,
+    eager_propagate: bool,
+    jobs: usize,
+) -> Exploration<BTreeMap<String, [bool; 3]>> {
+    let mut outcomes: Exploration<BTreeMap<String, [bool; 3]>> = Exploration::default();
+    for (index, model) in MemoryModel::ALL.into_iter().enumerate() {
+        let states = explore_outcomes(
+            programs.clone(),
+            init,
+            model,
+            step_limit,
+            mem_default,
+            eager_propagate,
+            jobs,
+        );
+        for state in states.found.into_keys() {
+            outcomes.found.entry(state.fingerprint()).or_default()[index] = true;
+        }
+        for (error, count) in states.errors {
+            outcomes
+                .errors
+                .insert(format!("{:?}: {}", model, error), count);
+        }
+        outcomes.visited += states.visited;
+    }
+    outcomes
+}
+
+/// Whether some execution of the programs under `model` reaches a final
+/// state accepted by `predicate`. Exploring stops at the first such state;
+/// executions longer than `step_limit` steps are not explored, and those
+/// failing at runtime reach no state.
+pub fn is_reachable(
+    programs: Vec<Vec<LabeledInstruction>>,
+    model: MemoryModel,
+    predicate: impl Fn(&FinalState) -> bool,
+    step_limit: usize,
+) -> bool {
+    let found = match model {
+        MemoryModel::SC => {
+            SequentialConsistency::new(programs).find_final_state(step_limit, &predicate)
+        }
+        MemoryModel::TSO | MemoryModel::PSO => {
+            TSO::new(programs, model == MemoryModel::PSO).find_final_state(step_limit, &predicate)
+        }
+    };
+    found.found.is_some()
+}
+
+/// Parses the option index of a `peek <index>` command.
+fn peek_index(input: &str, options_count: usize) -> Option<usize> {
+    match input.split_whitespace().collect::<Vec<&str>>()[..] {
+        [_, index] => index
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index < options_count),
+        _ => None,
+    }
+}
+
+/// Steps `run-to` may execute when no step limit is set.
+const RUN_TO_STEP_LIMIT: usize = 1000;
+
+/// Parses the target of a `run-to <thread>:<line>` command.
+fn run_to_target(input: &str) -> Option<(usize, usize)> {
+    match input.split_whitespace().collect::<Vec<&str>>()[..] {
+        [_, target] => {
+            let (thread_id, line_index) = target.split_once(':')?;
+            Some((thread_id.parse().ok()?, line_index.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Handles the `break #x`, `unbreak #x` and `breaks` commands. Returns
+/// `false` if the input is none of them.
+fn breakpoint_command(input: &str, breakpoints: &mut HashSet<String>) -> bool {
+    match input.split_whitespace().collect::<Vec<&str>>()[..] {
+        ["breaks"] => {
+            let mut addrs = breakpoints.iter().collect::<Vec<_>>();
+            addrs.sort();
+            for addr in addrs {
+                println!("#{}", addr);
+            }
+        }
+        [command @ ("break" | "unbreak"), addr] => match (command, addr.parse::<Reference>()) {
+            ("break", Ok(Reference::Memory(addr))) => {
+                breakpoints.insert(addr.to_string());
+            }
+            (_, Ok(Reference::Memory(addr))) => {
+                if !breakpoints.remove(addr.as_str()) {
+                    println!("No breakpoint on #{}", addr);
+                }
+            }
+            _ => println!("Usage: {} #<address>", command),
+        },
+        [command @ ("break" | "unbreak"), ..] => println!("Usage: {} #<address>", command),
+        _ => return false,
+    }
+    true
+}
+
+/// Handles the `watch #x` and `unwatch #x` commands. Returns `false` if the
+/// input is neither of them.
+fn watch_command(input: &str, memory: &mut Memory) -> bool {
+    match input.split_whitespace().collect::<Vec<&str>>()[..] {
+        [command @ ("watch" | "unwatch"), addr] => match (command, addr.parse::<Reference>()) {
+            ("watch", Ok(Reference::Memory(addr))) => memory.watch(addr),
+            (_, Ok(Reference::Memory(addr))) => {
+                if !memory.unwatch(addr) {
+                    println!("No watch on #{}", addr);
+                }
+            }
+            _ => println!("Usage: {} #<address>", command),
+        },
+        [command @ ("watch" | "unwatch"), ..] => println!("Usage: {} #<address>", command),
+        _ => return false,
+    }
+    true
+}
+
+/// Handles the `coherence #x` command, printing the writes that reached
+/// `#x` in order. Returns `false` if the input is another command.
+fn coherence_command(input: &str, memory: &Memory) -> bool {
+    match input.split_whitespace().collect::<Vec<&str>>()[..] {
+        ["coherence", addr] => match addr.parse::<Reference>() {
+            Ok(Reference::Memory(addr)) => {
+                let writes = memory.coherence_order(addr);
+                if writes.is_empty() {
+                    println!("No writes to #{} reached memory", addr);
+                }
+                for (index, (thread_id, value)) in writes.iter().enumerate() {
+                    println!("{}: {} (thread {})", index, value, thread_id);
+                }
+            }
+            _ => println!("Usage: coherence #<address>"),
+        },
+        ["coherence", ..] => println!("Usage: coherence #<address>"),
+        _ => return false,
+    }
+    true
+}
+
+/// How much the interpreters print besides the prompts and the output of
+/// commands.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the prompts and the final state.
+    Quiet,
+    /// Also changes of watched addresses, warnings and summaries.
+    #[default]
+    Normal,
+    /// Also the effect of every executed step.
+    Verbose,
+}
+
+/// Prints the diagnostics of the interpreters shown at the given verbosity.
+#[derive(Debug, Copy, Clone, Default)]
+struct Log {
+    verbosity: Verbosity,
+}
+
+impl Log {
+    /// Prints `text` as whole lines if the verbosity is at least `level`.
+    fn print(&self, level: Verbosity, text: &str) {
+        if self.verbosity >= level {
+            match text.ends_with('\n') {
+                true => print!("{}", text),
+                false => println!("{}", text),
+            }
+        }
+    }
+}
+
+/// How the interpreters present the options of a step and the prompt. The
+/// default methods give the standard listing; override them to theme the
+/// REPL, e.g. for an embedding UI.
+pub trait ReplFormatter {
+    /// Line listing the option with the given index, or `None` to leave it
+    /// out of the listing. The option can still be chosen.
+    fn option(&self, index: usize, option: &OptionInfo) -> Option<String> {
+        Some(format!("{} | {}", index, option.description))
+    }
+
+    /// Prompt after the options, given the executed and total step counts.
+    fn prompt(&self, executed: usize, total: usize) -> String {
+        format!(
+            "[{}/{}] Please select an option and input the index: ",
+            executed, total
+        )
+    }
+}
+
+/// The standard listing of the interpreters.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DefaultFormatter;
+
+impl ReplFormatter for DefaultFormatter {}
+
+/// The options and the prompt, one per line, as `formatter` presents them.
+fn menu(formatter: &dyn ReplFormatter, options: &[OptionInfo], progress: (usize, usize)) -> String {
+    let mut lines: Vec<String> = options
+        .iter()
+        .enumerate()
+        .filter_map(|(index, option)| formatter.option(index, option))
+        .collect();
+    lines.push(formatter.prompt(progress.0, progress.1));
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Prints the changes of watched addresses since the last call.
+fn print_watched_changes(memory: &mut Memory, log: Log) {
+    for (addr, old, new) in memory.take_changes() {
+        log.print(Verbosity::Normal, &format!("#{}: {} -> {}", addr, old, new));
+    }
+}
+
+/// Resolves the option chosen by the user: an index into the options, or a
+/// `t<thread>:<line>` mnemonic naming an instruction (`p<thread>:<line>` for
+/// the propagate of the write at that line).
+fn select_option(input: &str, options: &[OptionInfo]) -> Result<usize, &'static str> {
+    if let Ok(index) = input.parse::<usize>() {
+        return match index < options.len() {
+            true => Ok(index),
+            false => Err("Invalid index"),
+        };
+    }
+    let mut chars = input.chars();
+    let kind = chars.next().ok_or("Invalid command or index")?;
+    let (thread_id, line_index) = chars
+        .as_str()
+        .split_once(':')
+        .and_then(|(thread_id, line_index)| {
+            Some((
+                thread_id.parse::<usize>().ok()?,
+                line_index.parse::<usize>().ok()?,
+            ))
+        })
+        .filter(|_| kind == 't' || kind == 'p')
+        .ok_or("Invalid command or index")?;
+    options
+        .iter()
+        .position(|option| {
+            (option.is_propagate, option.thread_id, option.line_index)
+                == (kind == 'p', thread_id, line_index)
+        })
+        .ok_or("No matching option")
+}
 
 pub struct InterpretorSC {
     system: SequentialConsistency,
+    max_steps: Option<usize>,
+    breakpoints: HashSet<String>,
+    log: Log,
+    schedule: Vec<usize>,
+    formatter: Box<dyn ReplFormatter>,
+    init: Vec<(Location, i64)>,
 }
 
 impl InterpretorSC {
     pub fn new(program_paths: Vec<String>) -> Self {
-        let instructions = programs_to_instructions(program_paths);
+        Self::from_instructions(programs_to_instructions(program_paths))
+    }
+
+    /// Builds the interpreter from already parsed programs, one per thread,
+    /// e.g. ones generated in code.
+    pub fn from_instructions(instructions: Vec<Vec<LabeledInstruction>>) -> Self {
         Self {
             system: SequentialConsistency::new(instructions),
+            max_steps: None,
+            breakpoints: HashSet::new(),
+            log: Log::default(),
+            schedule: Vec::new(),
+            formatter: Box::new(DefaultFormatter),
+            init: Vec::new(),
         }
     }
 
-    pub fn run(&mut self) {
+    /// The engine the interpreter runs, for stepping it without the REPL.
+    pub fn system(&mut self) -> &mut SequentialConsistency {
+        &mut self.system
+    }
+
+    /// Stops the run after `max_steps` executed instructions.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Sets the value of memory locations and registers never written.
+    pub fn with_mem_default(mut self, mem_default: i64) -> Self {
+        self.system = self.system.with_mem_default(mem_default);
+        self
+    }
+
+    /// Sets the initial values of the given locations, e.g. the ones read by
+    /// `parse_memory_init`. `reset` restores them.
+    pub fn with_init(mut self, init: Vec<(Location, i64)>) -> Self {
+        self.init = init;
+        self.initialize();
+        self
+    }
+
+    fn initialize(&mut self) {
+        for (location, value) in &self.init {
+            self.system.initialize(location, *value);
+        }
+    }
+
+    /// Sets how much is printed besides the prompts.
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.log = Log { verbosity };
+        self
+    }
+
+    /// Chooses the options of the first steps without prompting, by their
+    /// index in the list of options at each step. The run continues
+    /// interactively after the schedule, and `run` fails at an index out of
+    /// range.
+    pub fn with_schedule(mut self, schedule: Vec<usize>) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Sets how the options and the prompt are presented.
+    pub fn with_formatter(mut self, formatter: impl ReplFormatter + 'static) -> Self {
+        self.formatter = Box::new(formatter);
+        self
+    }
+
+    /// The options of the next step and the prompt, as printed by `run`.
+    pub fn menu(&self) -> String {
+        menu(
+            self.formatter.as_ref(),
+            &self.system.options_detailed(),
+            self.system.progress(),
+        )
+    }
+
+    /// Effect of executing `option`, computed only when it will be printed.
+    fn verbose_effect(&self, option: &LabeledInstruction) -> String {
+        match self.log.verbosity {
+            Verbosity::Verbose => self
+                .system
+                .preview(option.clone())
+                .map(|effect| effect.to_string())
+                .unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
+    /// Runs the REPL until the programs finish or the user exits, and fails
+    /// on a scheduled option out of range.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let mut steps = 0;
+        let mut schedule = std::mem::take(&mut self.schedule).into_iter().enumerate();
         loop {
+            if self.max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                println!("Step limit reached after {} steps", steps);
+                println!("{}", self.system.final_state());
+                break;
+            }
+            print_watched_changes(&mut self.system.memory_subsystem.memory, self.log);
+            for warning in self.system.take_warnings() {
+                self.log.print(Verbosity::Normal, &warning);
+            }
+            for output in self.system.take_output() {
+                println!("{}", output);
+            }
             let options = self.system.get_instructions_to_exec();
             if options.is_empty() {
                 println!("No more instructions to execute");
+                if self.log.verbosity == Verbosity::Quiet {
+                    println!("{}", self.system.final_state());
+                }
                 break;
             }
-            for (index, option) in options.iter().enumerate() {
-                println!("{} | {}", index, option.to_string());
-            }
-            println!("Please select an option and input the index: ");
+            let details = self.system.options_detailed();
             let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
+            match schedule.next() {
+                Some((step, index)) if index >= options.len() => {
+                    return Err(Error::InvalidSchedule {
+                        step,
+                        index,
+                        options: options.len(),
+                    });
+                }
+                Some((_, index)) => input = index.to_string(),
+                None => {
+                    print!("{}", self.menu());
+                    match std::io::stdin().read_line(&mut input) {
+                        Ok(0) => break,
+                        Ok(_) => {}
+                        Err(err) => {
+                            println!("Unable to read input: {}", err);
+                            break;
+                        }
+                    }
+                }
+            }
             if input.trim() == "exit" {
                 break;
+            } else if input.trim() == "reset" {
+                self.system.reset();
+                self.initialize();
+                steps = 0;
+                println!("Reset to the initial state");
+                continue;
             } else if input.trim() == "registers" {
                 println!("{}", self.system.registers);
                 continue;
             } else if input.trim() == "memory" {
-                println!("{}", self.system.memory_subsystem.memory);
+                println!("{}", self.system.memory_subsystem.memory.to_shared_string());
                 continue;
-            }
-            let index: usize = input
-                .trim()
-                .parse::<usize>()
-                .expect("Invalid command or index");
-            if index >= options.len() {
-                println!("Invalid index");
+            } else if input.trim() == "list" {
+                print!("{}", self.system.listing());
+                continue;
+            } else if input.trim() == "timeline" {
+                print!("{}", self.system.trace_as_swimlane());
+                continue;
+            } else if input.trim() == "pointers" {
+                for (thread_id, program) in self.system.programs.iter().enumerate() {
+                    match program.get(self.system.instruction_pointers[thread_id]) {
+                        Some(instruction) => println!("{}", instruction),
+                        None => println!("Thread {}, finished", thread_id),
+                    }
+                }
+                continue;
+            } else if breakpoint_command(&input, &mut self.breakpoints)
+                || watch_command(&input, &mut self.system.memory_subsystem.memory)
+                || coherence_command(&input, &self.system.memory_subsystem.memory)
+            {
+                continue;
+            } else if input.starts_with("run-to") {
+                let (thread_id, line_index) = match run_to_target(&input) {
+                    Some(target) => target,
+                    None => {
+                        println!("Usage: run-to <thread>:<line>");
+                        continue;
+                    }
+                };
+                let step_limit = self
+                    .max_steps
+                    .map_or(RUN_TO_STEP_LIMIT, |max_steps| max_steps - steps);
+                match self
+                    .system
+                    .run_to(thread_id, line_index, &self.breakpoints, step_limit)
+                {
+                    RunToResult::Reached(executed) => steps += executed,
+                    RunToResult::Breakpoint(executed, addr) => {
+                        steps += executed;
+                        println!("Breakpoint: the next step writes #{}", addr);
+                    }
+                    RunToResult::Unreachable(executed) => {
+                        steps += executed;
+                        println!("Thread {}, line {} is unreachable", thread_id, line_index);
+                    }
+                }
+                continue;
+            } else if input.starts_with("peek") {
+                match peek_index(&input, options.len()) {
+                    Some(index) => match self.system.preview(options[index].clone()) {
+                        Ok(effect) => println!("{}", effect),
+                        Err(err) => println!("{}", err),
+                    },
+                    None => println!("Usage: peek <index>"),
+                }
+                continue;
+            } else if input.starts_with("explain") {
+                match peek_index(&input, options.len()) {
+                    Some(index) => print!("{}", self.system.explain(options[index].clone())),
+                    None => println!("Usage: explain <index>"),
+                }
                 continue;
             }
+            let index = match select_option(input.trim(), &details) {
+                Ok(index) => index,
+                Err(message) => {
+                    println!("{}", message);
+                    continue;
+                }
+            };
             let option: LabeledInstruction = options[index].clone();
-            self.system.exec_instruction(option);
+            let effect = self.verbose_effect(&option);
+            match self.system.exec_instruction(option) {
+                Ok(()) => {
+                    steps += 1;
+                    self.log.print(Verbosity::Verbose, &effect);
+                }
+                Err(err) => println!("{}", err),
+            }
         }
+        Ok(())
     }
 }
 
 pub struct InterpretorTSO {
     system: TSO,
+    max_steps: Option<usize>,
+    breakpoints: HashSet<String>,
+    log: Log,
+    two_phase: bool,
+    formatter: Box<dyn ReplFormatter>,
+    init: Vec<(Location, i64)>,
 }
 
 impl InterpretorTSO {
     pub fn new(program_paths: Vec<String>, is_pso: bool) -> Self {
-        let instructions = programs_to_instructions(program_paths);
+        Self::from_instructions(programs_to_instructions(program_paths), is_pso)
+    }
+
+    /// Builds the interpreter from already parsed programs, one per thread,
+    /// e.g. ones generated in code.
+    pub fn from_instructions(instructions: Vec<Vec<LabeledInstruction>>, is_pso: bool) -> Self {
         Self {
             system: TSO::new(instructions, is_pso),
+            max_steps: None,
+            breakpoints: HashSet::new(),
+            log: Log::default(),
+            two_phase: false,
+            formatter: Box::new(DefaultFormatter),
+            init: Vec::new(),
+        }
+    }
+
+    /// The engine the interpreter runs, for stepping it without the REPL.
+    pub fn system(&mut self) -> &mut TSO {
+        &mut self.system
+    }
+
+    /// Stops the run after `max_steps` executed instructions and propagates.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Sets the value of memory locations and registers never written.
+    pub fn with_mem_default(mut self, mem_default: i64) -> Self {
+        self.system = self.system.with_mem_default(mem_default);
+        self
+    }
+
+    /// Sets the initial values of the given locations, e.g. the ones read by
+    /// `parse_memory_init`. `reset` restores them.
+    pub fn with_init(mut self, init: Vec<(Location, i64)>) -> Self {
+        self.init = init;
+        self.initialize();
+        self
+    }
+
+    fn initialize(&mut self) {
+        for (location, value) in &self.init {
+            self.system.initialize(location, *value);
+        }
+    }
+
+    /// Propagates writes eagerly, see `TSO::with_eager_propagate`.
+    pub fn with_eager_propagate(mut self, eager_propagate: bool) -> Self {
+        self.system = self.system.with_eager_propagate(eager_propagate);
+        self
+    }
+
+    /// Sets how much is printed besides the prompts.
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.log = Log { verbosity };
+        self
+    }
+
+    /// Shows stores in two phases, for teaching: a step announces the writes
+    /// it puts in a store buffer and the ones it propagates to memory, and
+    /// `memory` annotates the values still buffered.
+    pub fn with_two_phase(mut self, two_phase: bool) -> Self {
+        self.two_phase = two_phase;
+        self
+    }
+
+    /// Sets how the options and the prompt are presented.
+    pub fn with_formatter(mut self, formatter: impl ReplFormatter + 'static) -> Self {
+        self.formatter = Box::new(formatter);
+        self
+    }
+
+    /// The options of the next step and the prompt, as printed by `run`.
+    pub fn menu(&self) -> String {
+        menu(
+            self.formatter.as_ref(),
+            &self.system.options_detailed(),
+            self.system.progress(),
+        )
+    }
+
+    /// Buffered writes of every thread, to compare before and after a step.
+    fn buffered_writes(&self) -> Vec<Vec<(String, i64)>> {
+        (0..self.system.programs.len())
+            .map(|thread_id| self.system.memory_subsystem.pending_writes(thread_id))
+            .collect()
+    }
+
+    /// Prints the writes that entered or left a store buffer since `before`.
+    fn print_buffer_transitions(&self, before: &[Vec<(String, i64)>]) {
+        for (thread_id, after) in self.buffered_writes().iter().enumerate() {
+            // Propagates remove writes from anywhere in the buffer and stores
+            // append theirs, so what remains is the unchanged part of `after`
+            let mut remaining = after.iter().peekable();
+            for write in &before[thread_id] {
+                if remaining.next_if_eq(&write).is_none() {
+                    println!(
+                        "Propagated #{} := {} of thread {}, now visible to every thread",
+                        write.0, write.1, thread_id
+                    );
+                }
+            }
+            for (addr, value) in remaining {
+                println!(
+                    "Buffered #{} := {} in thread {}, not visible to other threads until propagated",
+                    addr, value, thread_id
+                );
+            }
+        }
+    }
+
+    /// Effect of executing `option`, computed only when it will be printed.
+    fn verbose_effect(&self, option: &Rc<RefCell<InstructionNode>>) -> String {
+        match self.log.verbosity {
+            Verbosity::Verbose => self
+                .system
+                .preview(option.clone())
+                .map(|effect| effect.to_string())
+                .unwrap_or_default(),
+            _ => String::new(),
         }
     }
 
     pub fn run(&mut self) {
+        let races = self.system.dependency_graph.potential_races();
+        let races = match races.is_empty() {
+            true => "No potential races found".to_string(),
+            false => format!(
+                "Potential races ({}): {}",
+                races.len(),
+                races
+                    .iter()
+                    .map(|(first, second)| format!("{}/{}", first, second))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        self.log.print(Verbosity::Normal, &races);
+        let mut steps = 0;
         loop {
+            if self.max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                println!("Step limit reached after {} steps", steps);
+                println!("{}", self.system.final_state());
+                break;
+            }
+            print_watched_changes(&mut self.system.memory_subsystem.memory, self.log);
+            for warning in self.system.take_warnings() {
+                self.log.print(Verbosity::Normal, &warning);
+            }
+            for output in self.system.take_output() {
+                println!("{}", output);
+            }
             let options = self.system.get_instructions_to_exec();
             if options.is_empty() {
                 println!("No more instructions to execute");
+                if self.log.verbosity == Verbosity::Quiet {
+                    println!("{}", self.system.final_state());
+                }
                 break;
             }
-            for (index, option) in options.iter().enumerate() {
-                println!("{} | {}", index, option.borrow().instruction.to_string());
-            }
-            println!("Please select an option and input the index: ");
+            let details = self.system.options_detailed();
+            print!("{}", self.menu());
             let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
+            match std::io::stdin().read_line(&mut input) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) => {
+                    println!("Unable to read input: {}", err);
+                    break;
+                }
+            }
             if input.trim() == "exit" {
                 break;
+            } else if input.trim() == "reset" {
+                self.system.reset();
+                self.initialize();
+                steps = 0;
+                println!("Reset to the initial state");
+                continue;
             } else if input.trim() == "registers" {
                 println!("{}", self.system.registers);
                 continue;
             } else if input.trim() == "memory" {
-                println!("{}", self.system.memory_subsystem.memory);
+                match self.two_phase {
+                    true => println!("{}", self.system.memory_subsystem.to_annotated_string()),
+                    false => println!("{}", self.system.memory_subsystem.memory.to_shared_string()),
+                }
+                continue;
+            } else if input.trim() == "list" {
+                print!("{}", self.system.listing());
+                continue;
+            } else if input.trim() == "timeline" {
+                print!("{}", self.system.trace_as_swimlane());
                 continue;
             } else if input.starts_with("graph") {
-                let path = input.trim().split(" ").collect::<Vec<&str>>()[1];
-                self.system.save_graph(path);
+                let target = match input.split_whitespace().collect::<Vec<&str>>()[1..] {
+                    [path] => Some((path, false)),
+                    [path, "legend"] => Some((path, true)),
+                    _ => None,
+                };
+                match target {
+                    Some((path, legend)) => {
+                        if let Err(err) = self.system.save_graph(path, legend) {
+                            println!("Unable to save {}: {}", path, err);
+                        }
+                    }
+                    None => println!("Usage: graph <path> [legend]"),
+                }
                 continue;
-            }
-            let index: usize = input
-                .trim()
-                .parse::<usize>()
-                .expect("Invalid command or index");
-            if index >= options.len() {
-                println!("Invalid index");
+            } else if input.starts_with("mermaid") {
+                match input.split_whitespace().nth(1) {
+                    Some(path) => {
+                        if let Err(err) = self.system.save_mermaid(path) {
+                            println!("Unable to save {}: {}", path, err);
+                        }
+                    }
+                    None => println!("Usage: mermaid <path>"),
+                }
+                continue;
+            } else if breakpoint_command(&input, &mut self.breakpoints)
+                || watch_command(&input, &mut self.system.memory_subsystem.memory)
+                || coherence_command(&input, &self.system.memory_subsystem.memory)
+            {
+                continue;
+            } else if input.starts_with("run-to") {
+                let (thread_id, line_index) = match run_to_target(&input) {
+                    Some(target) => target,
+                    None => {
+                        println!("Usage: run-to <thread>:<line>");
+                        continue;
+                    }
+                };
+                let step_limit = self
+                    .max_steps
+                    .map_or(RUN_TO_STEP_LIMIT, |max_steps| max_steps - steps);
+                match self
+                    .system
+                    .run_to(thread_id, line_index, &self.breakpoints, step_limit)
+                {
+                    RunToResult::Reached(executed) => steps += executed,
+                    RunToResult::Breakpoint(executed, addr) => {
+                        steps += executed;
+                        println!("Breakpoint: the next step writes #{}", addr);
+                    }
+                    RunToResult::Unreachable(executed) => {
+                        steps += executed;
+                        println!("Thread {}, line {} is unreachable", thread_id, line_index);
+                    }
+                }
+                continue;
+            } else if input.starts_with("peek") {
+                match peek_index(&input, options.len()) {
+                    Some(index) => match self.system.preview(options[index].clone()) {
+                        Ok(effect) => println!("{}", effect),
+                        Err(err) => println!("{}", err),
+                    },
+                    None => println!("Usage: peek <index>"),
+                }
+                continue;
+            } else if input.starts_with("explain") {
+                match peek_index(&input, options.len()) {
+                    Some(index) => print!("{}", self.system.explain(options[index].clone())),
+                    None => println!("Usage: explain <index>"),
+                }
+                continue;
+            } else if input.starts_with("flush") {
+                let thread_id = match input.split_whitespace().collect::<Vec<&str>>()[1..] {
+                    [thread_id] => thread_id.parse::<usize>().ok(),
+                    _ => None,
+                };
+                match thread_id {
+                    Some(thread_id) => {
+                        let flushed = self.system.flush_thread(thread_id);
+                        steps += flushed;
+                        println!("Flushed {} writes of thread {}", flushed, thread_id);
+                    }
+                    None => println!("Usage: flush <thread>"),
+                }
+                continue;
+            } else if input.trim() == "run-rlx" {
+                let (executed, result) = self.system.run_register_ops();
+                steps += executed;
+                println!("Executed {} register instructions", executed);
+                if let Err(err) = result {
+                    println!("{}", err);
+                }
+                continue;
+            } else if input.starts_with("why") {
+                let target = match input.split_whitespace().collect::<Vec<&str>>()[1..] {
+                    [thread_id, line_index] => match (thread_id.parse(), line_index.parse()) {
+                        (Ok(thread_id), Ok(line_index)) => Some((thread_id, line_index)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let Some((thread_id, line_index)) = target else {
+                    println!("Usage: why <thread> <line>");
+                    continue;
+                };
+                match self.system.blocked_by(thread_id, line_index) {
+                    None => println!(
+                        "No pending instruction at thread {}, line {}",
+                        thread_id, line_index
+                    ),
+                    Some(dependencies) if dependencies.is_empty() => println!(
+                        "Thread {}, line {} is not waiting for anything",
+                        thread_id, line_index
+                    ),
+                    Some(dependencies) => {
+                        println!("Thread {}, line {} waits for:", thread_id, line_index);
+                        for dependency in dependencies {
+                            println!("  {}", dependency);
+                        }
+                    }
+                }
                 continue;
             }
+            let index = match select_option(input.trim(), &details) {
+                Ok(index) => index,
+                Err(message) => {
+                    println!("{}", message);
+                    continue;
+                }
+            };
             let option: Rc<RefCell<InstructionNode>> = options[index].clone();
-            self.system.exec_instruction(option);
+            let effect = self.verbose_effect(&option);
+            let buffered = self.buffered_writes();
+            match self.system.exec_instruction(option) {
+                Ok(()) => {
+                    steps += 1;
+                    self.log.print(Verbosity::Verbose, &effect);
+                    if self.two_phase {
+                        self.print_buffer_transitions(&buffered);
+                    }
+                }
+                Err(err) => println!("{}", err),
+            }
         }
+        self.log
+            .print(Verbosity::Normal, &self.system.buffer_summary());
     }
 }
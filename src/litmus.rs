@@ -0,0 +1,203 @@
+use crate::instruction::{Error, LabeledInstruction};
+use crate::thread_subsystem::{FinalState, Location, StateCondition};
+use crate::utils::check_label_unique;
+use crate::{explore_outcomes, MemoryModel};
+use std::fs;
+use std::str::FromStr;
+
+/// A test in the herd litmus format. Thread columns hold instructions in
+/// this crate's grammar, e.g.:
+///
+/// ```text
+/// ISA SB
+/// "Store buffering"
+/// { x=0; y=0; 0:r1=1; 1:r1=1; }
+///  P0               | P1               ;
+///  store RLX r1 #x  | store RLX r1 #y  ;
+///  load RLX #y r2   | load RLX #x r2   ;
+/// exists (0:r2=0 /\ 1:r2=0)
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LitmusTest {
+    pub name: String,
+    pub programs: Vec<Vec<LabeledInstruction>>,
+    pub init: Vec<(Location, i64)>,
+    pub condition: StateCondition,
+}
+
+impl LitmusTest {
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        fs::read_to_string(path)
+            .map_err(|err| Error::InvalidLitmus(format!("{}: {}", path, err)))?
+            .parse::<LitmusTest>()
+    }
+
+    /// Explores all executions under `model` and returns a final state
+    /// satisfying the `exists` condition, if there is one.
+    pub fn check(&self, model: MemoryModel, step_limit: usize) -> Option<FinalState> {
+        // Litmus tests initialize what isn't 0 in their init section
+        let mem_default = 0;
+        explore_outcomes(
+            self.programs.clone(),
+            &self.init,
+            model,
+            step_limit,
+            mem_default,
+            false,
+            1,
+        )
+        .found
+        .into_keys()
+        .find(|state| self.condition.holds(state, mem_default))
+    }
+}
+
+fn parse_location(location: &str) -> Result<Location, Error> {
+    let location = location.trim();
+    match location.split_once(':') {
+        Some((thread_id, reg)) => {
+            Ok(Location::Register(
+                Some(thread_id.trim().parse::<usize>().map_err(|_| {
+                    Error::InvalidLitmus(format!("invalid thread in '{}'", location))
+                })?),
+                reg.trim().to_string(),
+            ))
+        }
+        None => Ok(Location::Memory(
+            location.trim_start_matches('#').to_string(),
+        )),
+    }
+}
+
+fn parse_assignment(assignment: &str) -> Result<(Location, i64), Error> {
+    let invalid = || Error::InvalidLitmus(format!("unsupported assignment '{}'", assignment));
+    let (location, value) = assignment.split_once('=').ok_or_else(invalid)?;
+    let value = value.trim().parse::<i64>().map_err(|_| invalid())?;
+    Ok((parse_location(location)?, value))
+}
+
+fn split_columns(line: &str) -> Vec<&str> {
+    line.trim()
+        .trim_end_matches(';')
+        .split('|')
+        .map(str::trim)
+        .collect()
+}
+
+impl FromStr for LitmusTest {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let mut lines = src
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .peekable();
+
+        let name = lines
+            .next()
+            .and_then(|header| header.split_whitespace().nth(1))
+            .ok_or_else(|| Error::InvalidLitmus("missing '<arch> <name>' header".to_string()))?
+            .to_string();
+        while lines.next_if(|line| line.starts_with('"')).is_some() {}
+
+        let mut init = Vec::new();
+        if lines.peek().is_some_and(|line| line.starts_with('{')) {
+            let mut block = String::new();
+            for line in lines.by_ref() {
+                block.push_str(line);
+                block.push(' ');
+                if line.contains('}') {
+                    break;
+                }
+            }
+            let block = block.trim().trim_start_matches('{');
+            let block = block.split('}').next().unwrap();
+            for assignment in block.split(';').map(str::trim).filter(|a| !a.is_empty()) {
+                init.push(parse_assignment(assignment)?);
+            }
+        }
+
+        let header = lines
+            .next()
+            .ok_or_else(|| Error::InvalidLitmus("missing thread columns".to_string()))?;
+        let thread_count = split_columns(header).len();
+        for (thread_id, column) in split_columns(header).into_iter().enumerate() {
+            if column != format!("P{}", thread_id) {
+                return Err(Error::InvalidLitmus(format!(
+                    "expected column 'P{}', found '{}'",
+                    thread_id, column
+                )));
+            }
+        }
+
+        let mut programs: Vec<Vec<LabeledInstruction>> = vec![Vec::new(); thread_count];
+        while let Some(line) = lines.next_if(|line| line.ends_with(';') || line.contains('|')) {
+            let cells = split_columns(line);
+            if cells.len() > thread_count {
+                return Err(Error::InvalidLitmus(format!(
+                    "too many columns in '{}'",
+                    line
+                )));
+            }
+            for (thread_id, cell) in cells.into_iter().enumerate() {
+                if cell.is_empty() {
+                    continue;
+                }
+                let parsed = cell.parse::<LabeledInstruction>().map_err(|_| {
+                    Error::InvalidLitmus(format!(
+                        "unsupported instruction '{}' in P{}",
+                        cell, thread_id
+                    ))
+                })?;
+                let program = &mut programs[thread_id];
+                let instruction = LabeledInstruction::new(
+                    parsed.label,
+                    parsed.instruction,
+                    program.len(),
+                    thread_id,
+                );
+                check_label_unique(program, &instruction)?;
+                program.push(instruction);
+            }
+        }
+
+        let condition = lines.collect::<Vec<_>>().join(" ");
+        let condition = condition
+            .strip_prefix("exists")
+            .ok_or_else(|| {
+                Error::InvalidLitmus(format!("unsupported final condition '{}'", condition))
+            })?
+            .trim()
+            .trim_start_matches('(')
+            .trim_end_matches(')');
+        if condition.contains("\\/") || condition.contains('~') {
+            return Err(Error::InvalidLitmus(format!(
+                "only conjunctions are supported in '{}'",
+                condition
+            )));
+        }
+        let constraints = condition
+            .split("/\\")
+            .map(parse_assignment)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (location, _) in init.iter().chain(constraints.iter()) {
+            if let Location::Register(Some(thread_id), _) = location {
+                if *thread_id >= thread_count {
+                    return Err(Error::InvalidLitmus(format!(
+                        "no thread P{} in the test",
+                        thread_id
+                    )));
+                }
+            }
+        }
+
+        Ok(Self {
+            name,
+            programs,
+            init,
+            condition: StateCondition { constraints },
+        })
+    }
+}
@@ -1,4 +1,167 @@
-use clap::{arg, command, Command};
+use clap::{arg, command, value_parser, ArgMatches, Command};
+use isa_interpreter::{
+    LabeledInstruction, Location, MemoryModel, ParseOptions, StateCondition, Verbosity,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Warns about instructions that can never execute and jumps to labels that
+/// don't exist.
+fn warn_dead_code(programs: &[Vec<LabeledInstruction>]) {
+    for (thread_id, program) in programs.iter().enumerate() {
+        for (line, label) in isa_interpreter::missing_labels(program) {
+            eprintln!(
+                "Warning: thread {}, line {} jumps to missing label '{}'",
+                thread_id, line, label
+            );
+        }
+        let reachable = isa_interpreter::reachable_lines(program);
+        for instruction in program {
+            if !reachable.contains(&instruction.line_index) {
+                eprintln!(
+                    "Warning: thread {}, line {} is unreachable: {}",
+                    thread_id,
+                    instruction.line_index,
+                    instruction.instruction.to_source()
+                );
+            }
+        }
+    }
+}
+
+/// Parse options, with the access mode of loads and stores written without
+/// one.
+fn parse_options(sub_matches: &ArgMatches) -> ParseOptions {
+    ParseOptions {
+        default_mode: sub_matches
+            .get_one::<String>("default-mode")
+            .unwrap()
+            .parse()
+            .expect("Invalid access mode"),
+    }
+}
+
+/// Option indices given with `--schedule`, one per step.
+fn schedule(sub_matches: &ArgMatches) -> Option<Vec<usize>> {
+    sub_matches.get_one::<String>("schedule").map(|schedule| {
+        schedule
+            .split(',')
+            .map(|index| index.trim().parse().expect("Invalid schedule"))
+            .collect()
+    })
+}
+
+/// Initial memory values from the `--init-mem` file, if given.
+fn memory_init(sub_matches: &ArgMatches) -> Vec<(Location, i64)> {
+    match sub_matches.get_one::<String>("init-mem") {
+        Some(path) => {
+            let src =
+                std::fs::read_to_string(path).unwrap_or_else(|err| panic!("{}: {}", path, err));
+            isa_interpreter::parse_memory_init(&src, Some(path))
+                .unwrap_or_else(|err| panic!("{}", err))
+        }
+        None => Vec::new(),
+    }
+}
+
+fn read_programs(sub_matches: &ArgMatches) -> Vec<Vec<LabeledInstruction>> {
+    let programs = read_program_files(sub_matches, parse_options(sub_matches));
+    warn_dead_code(&programs);
+    programs
+}
+
+fn read_program_files(
+    sub_matches: &ArgMatches,
+    options: ParseOptions,
+) -> Vec<Vec<LabeledInstruction>> {
+    if let Some(path) = sub_matches.get_one::<String>("single-file") {
+        return options.single_file_to_instructions(path.to_string());
+    }
+    if let Some(spec) = sub_matches.get_one::<String>("threads") {
+        let mapping = isa_interpreter::parse_thread_mapping(spec).expect("Invalid thread mapping");
+        return options.mapped_programs_to_instructions(mapping);
+    }
+    let program_paths = sub_matches.get_one::<String>("PROGRAM_PATHS").unwrap();
+    if program_paths.trim() == "-" {
+        let src = std::io::read_to_string(std::io::stdin()).expect("Unable to read stdin");
+        return options
+            .parse_single_file_str(&src, Some("<stdin>"))
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+    let program_paths = program_paths
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .collect::<Vec<String>>();
+    options.programs_to_instructions(program_paths)
+}
+
+/// Reports the executions that failed at runtime and have no final state,
+/// with how many failed with each error.
+fn print_failures(errors: &BTreeMap<String, usize>) {
+    if errors.is_empty() {
+        return;
+    }
+    println!(
+        "{} executions failed at runtime",
+        errors.values().sum::<usize>()
+    );
+    for (error, count) in errors {
+        println!("{:>6} | {}", count, error);
+    }
+}
+
+/// Checks one batch test: the file must parse, and when a `<name>.expected`
+/// file sits next to it, the reachable final states must be exactly the
+/// ones it lists, one per line. Returns the number of final states.
+fn check_batch_test(
+    path: &Path,
+    memory_model: MemoryModel,
+    step_limit: usize,
+    options: ParseOptions,
+) -> Result<usize, String> {
+    let name = path.display().to_string();
+    let src = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let programs = options
+        .parse_single_file_str(&src, Some(&name))
+        .map_err(|err| err.to_string())?;
+    let states =
+        isa_interpreter::explore_outcomes(programs, &[], memory_model, step_limit, 0, false, 1)
+            .found
+            .into_keys()
+            .map(|state| state.to_string())
+            .collect::<BTreeSet<_>>();
+    let expected = match std::fs::read_to_string(path.with_extension("expected")) {
+        Ok(expected) => expected
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect::<BTreeSet<_>>(),
+        Err(_) => return Ok(states.len()),
+    };
+    if let Some(state) = states.difference(&expected).next() {
+        return Err(format!("unexpected final state {}", state));
+    }
+    if let Some(state) = expected.difference(&states).next() {
+        return Err(format!("missing final state {}", state));
+    }
+    Ok(states.len())
+}
+
+/// Adds the arguments `check` and `compare` share: the programs and how to
+/// explore them.
+fn exploration_args(command: Command) -> Command {
+    command
+        .arg(arg!([PROGRAM_PATHS] "List of paths to programs to run in different threads. Format: \'<path1>, <path2>, ...\', or \'-\' to read the programs of all threads from stdin, separated as in --single-file").short('p').required_unless_present_any(["single-file", "threads"]))
+        .arg(arg!(--"single-file" <PATH> "Path to a file with the programs of all threads, separated by \'---\' or \'thread:\' lines").conflicts_with("PROGRAM_PATHS"))
+        .arg(arg!(--threads <MAPPING> "Programs bound to explicit thread ids. Format: \'T0=<path1>, T1=<path2>, ...\'").conflicts_with_all(["PROGRAM_PATHS", "single-file"]))
+        .arg(arg!(--"step-limit" <STEPS> "Maximum length of an explored execution").value_parser(value_parser!(usize)).default_value("1000"))
+        .arg(arg!(--"mem-default" <N> "Value of memory locations and registers never written").value_parser(value_parser!(i64)).default_value("0"))
+        .arg(arg!(--"default-mode" <MODE> "Access mode of loads and stores written without one, e.g. RLX or SEQ_CST").default_value("RLX"))
+        .arg(arg!(--"eager-propagate" "TSO and PSO: before offering a memory read, propagate every buffered write that can be propagated"))
+        .arg(arg!(--"init-mem" <FILE> "Initial values of shared memory, one 'addr value' per line"))
+        .arg(arg!(-j --jobs <N> "Number of threads exploring executions in parallel").value_parser(value_parser!(usize)).default_value("1"))
+}
 
 fn main() {
     let matches = command!()
@@ -12,36 +175,248 @@ fn main() {
             Command::new("run")
                 .about("Run an interpreter on a given program")
                 .arg(arg!([MEMORY_MODEL] "Which memory model to use: SC, TSO or PSO.").short('m').required(true))
-                .arg(arg!([PROGRAM_PATHS] "List of paths to programs to run in different threads. Format: \'<path1>, <path2>, ...\'").short('p').required(true))
+                .arg(arg!([PROGRAM_PATHS] "List of paths to programs to run in different threads. Format: \'<path1>, <path2>, ...\', or \'-\' to read the programs of all threads from stdin, separated as in --single-file").short('p').required_unless_present_any(["single-file", "threads"]))
+                .arg(arg!(--"single-file" <PATH> "Path to a file with the programs of all threads, separated by \'---\' or \'thread:\' lines").conflicts_with("PROGRAM_PATHS"))
+                .arg(arg!(--threads <MAPPING> "Programs bound to explicit thread ids. Format: \'T0=<path1>, T1=<path2>, ...\'").conflicts_with_all(["PROGRAM_PATHS", "single-file"]))
+                .arg(arg!(--"max-steps" <STEPS> "Stop after executing this many steps").value_parser(value_parser!(usize)))
+                .arg(arg!(--"mem-default" <N> "Value of memory locations and registers never written").value_parser(value_parser!(i64)).default_value("0"))
+                .arg(arg!(-v --verbose "Also print the effect of every executed step"))
+                .arg(arg!(-q --quiet "Print only the prompts and the final state").conflicts_with("verbose"))
+                .arg(arg!(--"default-mode" <MODE> "Access mode of loads and stores written without one, e.g. RLX or SEQ_CST").default_value("RLX"))
+                .arg(arg!(--"eager-propagate" "TSO and PSO: before offering a memory read, propagate every buffered write that can be propagated"))
+                .arg(arg!(--"two-phase" "TSO and PSO: announce writes entering and leaving store buffers, and mark buffered values in the memory dump"))
+                .arg(arg!(--"init-mem" <FILE> "Initial values of shared memory, one 'addr value' per line"))
+                .arg(arg!(--schedule <INDICES> "SC: option index to choose at each of the first steps, without prompting. Format: '0,1,0,...'"))
+        )
+        .subcommand(
+            exploration_args(Command::new("check")
+                .about("Explore all interleavings of a given program and summarize the final states")
+                .arg(arg!([MEMORY_MODEL] "Which memory model to use: SC, TSO or PSO.").short('m').required(true))
+                .arg(arg!(--"assert-unreachable" <STATE> "Exit with an error if the final state is reachable. Format: \'0:r1=0, 1:r2=0, #x=1\'")))
+        )
+        .subcommand(
+            exploration_args(Command::new("compare")
+                .about("Explore all interleavings of a given program under SC, TSO and PSO and tabulate which model reaches each final state"))
+        )
+        .subcommand(
+            Command::new("check-syntax")
+                .about("Parse the given program files without running them and report invalid lines")
+                .arg(arg!(<FILES> ... "Paths to the program files to check"))
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Check every test in a directory, each a single-file program of all threads, and print a pass/fail table")
+                .arg(arg!(<DIR> "Directory with the tests. A test 'name.txt' passes if it parses and, given 'name.expected', reaches exactly the final states listed there"))
+                .arg(arg!([MEMORY_MODEL] "Which memory model to use: SC, TSO or PSO.").short('m').required(true))
+                .arg(arg!(--"step-limit" <STEPS> "Maximum length of an explored execution").value_parser(value_parser!(usize)).default_value("1000"))
+                .arg(arg!(--"default-mode" <MODE> "Access mode of loads and stores written without one, e.g. RLX or SEQ_CST").default_value("RLX"))
         )
         .get_matches();
 
     match matches.subcommand() {
         Some(("run", sub_matches)) => {
             let memory_model = sub_matches.get_one::<String>("MEMORY_MODEL").unwrap();
-            let program_paths = sub_matches
-                .get_one::<String>("PROGRAM_PATHS")
-                .unwrap()
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect::<Vec<String>>();
+            let instructions = read_programs(sub_matches);
+            let max_steps = sub_matches
+                .get_one::<usize>("max-steps")
+                .copied()
+                .unwrap_or(usize::MAX);
+            let mem_default = *sub_matches.get_one::<i64>("mem-default").unwrap();
+            let verbosity = match (
+                sub_matches.get_flag("verbose"),
+                sub_matches.get_flag("quiet"),
+            ) {
+                (true, _) => Verbosity::Verbose,
+                (_, true) => Verbosity::Quiet,
+                _ => Verbosity::Normal,
+            };
+            let eager_propagate = sub_matches.get_flag("eager-propagate");
+            let two_phase = sub_matches.get_flag("two-phase");
+            let schedule = schedule(sub_matches);
+            let init = memory_init(sub_matches);
+            if schedule.is_some() && memory_model != "SC" {
+                eprintln!("--schedule is supported only with -m SC");
+                std::process::exit(2);
+            }
 
             match memory_model.as_str() {
                 "SC" => {
-                    let mut inter = isa_interpreter::InterpretorSC::new(program_paths);
-                    inter.run();
+                    let mut inter = isa_interpreter::InterpretorSC::from_instructions(instructions)
+                        .with_max_steps(max_steps)
+                        .with_mem_default(mem_default)
+                        .with_init(init)
+                        .with_schedule(schedule.unwrap_or_default())
+                        .with_verbosity(verbosity);
+                    if let Err(err) = inter.run() {
+                        eprintln!("{}", err);
+                        std::process::exit(2);
+                    }
                 }
                 "TSO" => {
-                    let mut inter = isa_interpreter::InterpretorTSO::new(program_paths, false);
+                    let mut inter =
+                        isa_interpreter::InterpretorTSO::from_instructions(instructions, false)
+                            .with_max_steps(max_steps)
+                            .with_mem_default(mem_default)
+                            .with_init(init)
+                            .with_eager_propagate(eager_propagate)
+                            .with_two_phase(two_phase)
+                            .with_verbosity(verbosity);
                     inter.run();
                 }
                 "PSO" => {
-                    let mut inter = isa_interpreter::InterpretorTSO::new(program_paths, true);
+                    let mut inter =
+                        isa_interpreter::InterpretorTSO::from_instructions(instructions, true)
+                            .with_max_steps(max_steps)
+                            .with_mem_default(mem_default)
+                            .with_init(init)
+                            .with_eager_propagate(eager_propagate)
+                            .with_two_phase(two_phase)
+                            .with_verbosity(verbosity);
                     inter.run();
                 }
                 _ => panic!("Invalid memory model"),
             }
         }
+        Some(("check", sub_matches)) => {
+            let memory_model = sub_matches
+                .get_one::<String>("MEMORY_MODEL")
+                .unwrap()
+                .parse::<MemoryModel>()
+                .expect("Invalid memory model");
+            let step_limit = *sub_matches.get_one::<usize>("step-limit").unwrap();
+            let mem_default = *sub_matches.get_one::<i64>("mem-default").unwrap();
+            let unreachable = sub_matches
+                .get_one::<String>("assert-unreachable")
+                .map(|cond| cond.parse::<StateCondition>().expect("Invalid state"));
+
+            let instructions = read_programs(sub_matches);
+            let outcomes = isa_interpreter::explore_outcomes(
+                instructions,
+                &memory_init(sub_matches),
+                memory_model,
+                step_limit,
+                mem_default,
+                sub_matches.get_flag("eager-propagate"),
+                *sub_matches.get_one::<usize>("jobs").unwrap(),
+            );
+            let mut histogram = outcomes.found.iter().collect::<Vec<_>>();
+            histogram.sort_by_key(|(state, count)| (std::cmp::Reverse(**count), state.to_string()));
+            println!(
+                "Explored {} interleavings, {} distinct final states",
+                outcomes.found.values().sum::<usize>(),
+                outcomes.found.len()
+            );
+            for (state, count) in histogram {
+                println!("{:>6} | {}", count, state);
+            }
+            print_failures(&outcomes.errors);
+
+            if let Some(cond) = unreachable {
+                if let Some(state) = outcomes
+                    .found
+                    .keys()
+                    .find(|state| cond.holds(state, mem_default))
+                {
+                    println!("Assertion failed, reachable state: {}", state);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(("compare", sub_matches)) => {
+            let outcomes = isa_interpreter::compare_outcomes(
+                read_programs(sub_matches),
+                &memory_init(sub_matches),
+                *sub_matches.get_one::<usize>("step-limit").unwrap(),
+                *sub_matches.get_one::<i64>("mem-default").unwrap(),
+                sub_matches.get_flag("eager-propagate"),
+                *sub_matches.get_one::<usize>("jobs").unwrap(),
+            );
+            // Outcomes of more models first, so those of weaker ones only
+            // come last
+            let mut rows = outcomes.found.into_iter().collect::<Vec<_>>();
+            rows.sort_by_key(|(_, reached)| reached.iter().filter(|reached| !**reached).count());
+            println!(
+                "{} distinct final states, {} not reachable under SC",
+                rows.len(),
+                rows.iter().filter(|(_, [sc, ..])| !sc).count()
+            );
+            println!(" SC | TSO | PSO | final state");
+            for (state, [sc, tso, pso]) in &rows {
+                let mark = |reached: bool| if reached { "✓" } else { " " };
+                println!(
+                    " {:^2} | {:^3} | {:^3} | {}{}",
+                    mark(*sc),
+                    mark(*tso),
+                    mark(*pso),
+                    state,
+                    if *sc { "" } else { "  <- weaker models only" }
+                );
+            }
+            print_failures(&outcomes.errors);
+        }
+        Some(("check-syntax", sub_matches)) => {
+            let mut valid = true;
+            for path in sub_matches.get_many::<String>("FILES").unwrap() {
+                let errors = match std::fs::read_to_string(path) {
+                    Ok(src) => isa_interpreter::syntax_errors(&src, Some(path)),
+                    Err(err) => {
+                        eprintln!("{}: {}", path, err);
+                        valid = false;
+                        continue;
+                    }
+                };
+                for error in &errors {
+                    eprintln!("{}", error);
+                }
+                if errors.is_empty() {
+                    println!("{}: ok", path);
+                } else {
+                    valid = false;
+                }
+            }
+            if !valid {
+                std::process::exit(1);
+            }
+        }
+        Some(("batch", sub_matches)) => {
+            let memory_model = sub_matches
+                .get_one::<String>("MEMORY_MODEL")
+                .unwrap()
+                .parse::<MemoryModel>()
+                .expect("Invalid memory model");
+            let step_limit = *sub_matches.get_one::<usize>("step-limit").unwrap();
+            let options = parse_options(sub_matches);
+            let dir = sub_matches.get_one::<String>("DIR").unwrap();
+            let mut tests = std::fs::read_dir(dir)
+                .unwrap_or_else(|err| panic!("{}: {}", dir, err))
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| {
+                    path.is_file() && path.extension().is_some_and(|ext| ext != "expected")
+                })
+                .collect::<Vec<_>>();
+            tests.sort();
+
+            let width = tests
+                .iter()
+                .map(|path| path.file_name().unwrap().len())
+                .max()
+                .unwrap_or(0);
+            let mut failed = 0;
+            for path in &tests {
+                let name = path.file_name().unwrap().to_string_lossy();
+                match check_batch_test(path, memory_model, step_limit, options) {
+                    Ok(states) => println!("{:<width$} | pass | {} final states", name, states),
+                    Err(reason) => {
+                        failed += 1;
+                        println!("{:<width$} | FAIL | {}", name, reason);
+                    }
+                }
+            }
+            println!("{} passed, {} failed", tests.len() - failed, failed);
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
         _ => unreachable!("Exhausted list of subcommands and subcommand_required prevents `None`"),
     }
 }
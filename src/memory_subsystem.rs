@@ -1,29 +1,136 @@
-use crate::instruction::WriteOperation;
-use std::collections::{HashMap, VecDeque};
+use crate::dependency_graph::NodeId;
+use crate::instruction::{Value, WriteOperation};
+use crate::symbol::{Symbol, SymbolId};
+use std::collections::{HashMap, HashSet};
 
+/// Values of memory locations or registers. Addresses are given as names or
+/// as their interned `SymbolId`s.
+#[derive(Clone)]
 pub struct Memory {
-    pub data: HashMap<String, usize>,
+    pub data: HashMap<SymbolId, Value>,
+    /// Value of locations never written.
+    default: Value,
+    watches: HashSet<SymbolId>,
+    changes: Vec<(SymbolId, Value, Value)>,
+    /// Writes of the threads in the order they reached memory, per address.
+    history: HashMap<SymbolId, Vec<(usize, Value)>>,
+    /// Node of the write that set the current value of each address, for
+    /// writes committed with one.
+    sources: HashMap<SymbolId, NodeId>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         Self {
             data: HashMap::new(),
+            default: Value::default(),
+            watches: HashSet::new(),
+            changes: Vec::new(),
+            history: HashMap::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Sets the value loaded from locations never written.
+    pub fn set_default(&mut self, default: Value) {
+        self.default = default;
+    }
+
+    pub fn load(&self, addr: impl Symbol) -> Value {
+        addr.lookup()
+            .and_then(|addr| self.data.get(&addr))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    pub fn store(&mut self, addr: impl Into<SymbolId>, value: Value) {
+        let addr = addr.into();
+        let old = self.data.insert(addr, value).unwrap_or(self.default);
+        self.sources.remove(&addr);
+        if old != value && self.watches.contains(&addr) {
+            self.changes.push((addr, old, value));
+        }
+    }
+
+    /// Stores a write of the thread, recording it in the coherence order of
+    /// `addr`.
+    pub fn commit(&mut self, addr: impl Into<SymbolId>, value: Value, thread_id: usize) {
+        self.commit_from(addr, value, thread_id, None);
+    }
+
+    /// Like `commit`, remembering the node that made the write, if known.
+    pub fn commit_from(
+        &mut self,
+        addr: impl Into<SymbolId>,
+        value: Value,
+        thread_id: usize,
+        source: Option<NodeId>,
+    ) {
+        let addr = addr.into();
+        self.store(addr, value);
+        self.history
+            .entry(addr)
+            .or_default()
+            .push((thread_id, value));
+        if let Some(source) = source {
+            self.sources.insert(addr, source);
         }
     }
 
-    pub fn load(&self, addr: &str) -> usize {
-        *self.data.get(addr).unwrap_or(&0)
+    /// Node of the write the value at `addr` comes from, or `None` for an
+    /// initial value or a write made without one.
+    pub fn source(&self, addr: impl Symbol) -> Option<&NodeId> {
+        self.sources.get(&addr.lookup()?)
     }
 
-    pub fn store(&mut self, addr: &str, value: usize) {
-        self.data.insert(addr.to_string(), value);
+    /// The writes committed to `addr` as `(thread, value)`, in the order they
+    /// reached memory. Values set directly with `store` aren't included.
+    pub fn coherence_order(&self, addr: impl Symbol) -> &[(usize, Value)] {
+        addr.lookup()
+            .and_then(|addr| self.history.get(&addr))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Records every later change of the value at `addr`.
+    pub fn watch(&mut self, addr: impl Into<SymbolId>) {
+        self.watches.insert(addr.into());
+    }
+
+    /// Stops recording changes at `addr`. Returns `false` if it wasn't
+    /// watched.
+    pub fn unwatch(&mut self, addr: impl Symbol) -> bool {
+        addr.lookup().is_some_and(|addr| self.watches.remove(&addr))
+    }
+
+    /// Changes of watched addresses since the last call, as
+    /// `(addr, old, new)` in the order they happened.
+    pub fn take_changes(&mut self) -> Vec<(String, Value, Value)> {
+        std::mem::take(&mut self.changes)
+            .into_iter()
+            .map(|(addr, old, new)| (addr.to_string(), old, new))
+            .collect()
+    }
+
+    /// Lists the contents as shared memory, with addresses written `#addr`
+    /// as in programs, so they can't be mistaken for registers.
+    pub fn to_shared_string(&self) -> String {
+        let mut keys: Vec<&SymbolId> = self.data.keys().collect();
+        keys.sort();
+        keys.iter()
+            .map(|key| format!("#{}: {}\n", key, self.data[*key]))
+            .collect()
     }
 }
 
 impl std::fmt::Display for Memory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut keys: Vec<&String> = self.data.keys().collect();
+        let mut keys: Vec<&SymbolId> = self.data.keys().collect();
         keys.sort();
         for key in keys {
             writeln!(f, "{}: {}", key, self.data[key])?;
@@ -33,11 +140,24 @@ impl std::fmt::Display for Memory {
 }
 
 pub trait MemorySubsystem {
-    fn store(&mut self, addr: &str, value: usize, thread_id: usize);
-    fn load(&self, addr: &str, thread_id: usize) -> usize;
-    fn propagate(&mut self, thread_id: usize);
+    fn store(&mut self, addr: SymbolId, value: Value, thread_id: usize) {
+        self.store_from(addr, value, thread_id, None);
+    }
+    /// Stores a write made by the node `source`, if known.
+    fn store_from(
+        &mut self,
+        addr: SymbolId,
+        value: Value,
+        thread_id: usize,
+        source: Option<NodeId>,
+    );
+    fn load(&self, addr: SymbolId, thread_id: usize) -> Value;
+    /// Node of the write `load` reads from, if known.
+    fn last_write(&self, addr: SymbolId, thread_id: usize) -> Option<&NodeId>;
+    fn propagate(&mut self, thread_id: usize, addr: SymbolId);
 }
 
+#[derive(Clone)]
 pub struct SCMemorySubsystem {
     pub memory: Memory,
 }
@@ -50,44 +170,87 @@ impl SCMemorySubsystem {
     }
 }
 
+impl SCMemorySubsystem {
+    /// The writes to `addr` as `(thread, value)`, in execution order.
+    pub fn coherence_order(&self, addr: impl Symbol) -> &[(usize, Value)] {
+        self.memory.coherence_order(addr)
+    }
+}
+
 impl MemorySubsystem for SCMemorySubsystem {
-    fn store(&mut self, addr: &str, value: usize, _thread_id: usize) {
-        self.memory.store(addr, value);
+    fn store_from(
+        &mut self,
+        addr: SymbolId,
+        value: Value,
+        thread_id: usize,
+        source: Option<NodeId>,
+    ) {
+        self.memory.commit_from(addr, value, thread_id, source);
     }
-    fn load(&self, addr: &str, _thread_id: usize) -> usize {
+    fn load(&self, addr: SymbolId, _thread_id: usize) -> Value {
         self.memory.load(addr)
     }
-    fn propagate(&mut self, _thread_id: usize) {}
+    fn last_write(&self, addr: SymbolId, _thread_id: usize) -> Option<&NodeId> {
+        self.memory.source(addr)
+    }
+    fn propagate(&mut self, _thread_id: usize, _addr: SymbolId) {}
 }
 
+#[derive(Clone)]
 pub struct Buffer {
-    operations: VecDeque<WriteOperation>,
+    operations: Vec<WriteOperation>,
 }
 
 impl Buffer {
     pub fn new() -> Self {
         Self {
-            operations: VecDeque::new(),
+            operations: Vec::new(),
         }
     }
 
-    pub fn load(&self, addr: &str) -> Option<usize> {
-        self.operations
-            .iter()
-            .rev()
-            .find(|op| op.addr == addr)
-            .map(|op| op.value)
+    /// Store forwarding: a thread reads its own latest buffered write to
+    /// `addr` before it reaches memory. Writes to one address are buffered
+    /// and propagated in program order, so this is also the last of them to
+    /// propagate.
+    pub fn load(&self, addr: SymbolId) -> Option<Value> {
+        self.latest(addr).map(|op| op.value)
+    }
+
+    /// The buffered write `load` reads.
+    fn latest(&self, addr: SymbolId) -> Option<&WriteOperation> {
+        self.operations.iter().rev().find(|op| op.addr == addr)
     }
 
     pub fn push(&mut self, operation: WriteOperation) {
-        self.operations.push_back(operation);
+        self.operations.push(operation);
+    }
+
+    /// Removes the oldest buffered write to `addr`.
+    pub fn propagate(&mut self, addr: SymbolId) -> Option<WriteOperation> {
+        let index = self.operations.iter().position(|op| op.addr == addr)?;
+        Some(self.operations.remove(index))
     }
 
-    pub fn propagate(&mut self) -> Option<WriteOperation> {
-        self.operations.pop_front()
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
     }
 }
 
+impl std::fmt::Display for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.operations.is_empty() {
+            return write!(f, "empty");
+        }
+        let operations = self
+            .operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", operations.join(", "))
+    }
+}
+
+#[derive(Clone)]
 pub struct TSOMemorySubsystem {
     pub memory: Memory,
     pub buffers: HashMap<usize, Buffer>,
@@ -102,25 +265,108 @@ impl TSOMemorySubsystem {
     }
 }
 
+impl TSOMemorySubsystem {
+    /// The writes to `addr` as `(thread, value)`, in the order they were
+    /// propagated to memory. Buffered writes aren't included.
+    pub fn coherence_order(&self, addr: impl Symbol) -> &[(usize, Value)] {
+        self.memory.coherence_order(addr)
+    }
+
+    /// Writes of the thread not yet propagated to memory, oldest first.
+    pub fn pending(&self, thread_id: usize) -> &[WriteOperation] {
+        self.buffers
+            .get(&thread_id)
+            .map_or(&[], |buffer| &buffer.operations)
+    }
+
+    /// Lists the shared memory like `Memory::to_shared_string`, annotating
+    /// each address with the writes to it still waiting in a buffer, which
+    /// only their own thread can see yet.
+    pub fn to_annotated_string(&self) -> String {
+        let mut addrs: Vec<&SymbolId> = self.memory.data.keys().collect();
+        for buffer in self.buffers.values() {
+            addrs.extend(buffer.operations.iter().map(|op| &op.addr));
+        }
+        addrs.sort();
+        addrs.dedup();
+        let mut thread_ids: Vec<&usize> = self.buffers.keys().collect();
+        thread_ids.sort();
+        addrs
+            .iter()
+            .map(|addr| {
+                let buffered = thread_ids
+                    .iter()
+                    .flat_map(|thread_id| {
+                        self.buffers[thread_id]
+                            .operations
+                            .iter()
+                            .filter(|op| op.addr == **addr)
+                            .map(move |op| format!("{} from thread {}", op.value, thread_id))
+                    })
+                    .collect::<Vec<_>>();
+                match buffered.is_empty() {
+                    true => format!("#{}: {}\n", addr, self.memory.load(**addr)),
+                    false => format!(
+                        "#{}: {} (buffered, not yet visible: {})\n",
+                        addr,
+                        self.memory.load(**addr),
+                        buffered.join(", ")
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    /// The writes of `pending`, as address names and values.
+    pub fn pending_writes(&self, thread_id: usize) -> Vec<(String, i64)> {
+        self.pending(thread_id)
+            .iter()
+            .map(|op| (op.addr.to_string(), op.value.0))
+            .collect()
+    }
+}
+
 impl MemorySubsystem for TSOMemorySubsystem {
-    fn store(&mut self, addr: &str, value: usize, thread_id: usize) {
+    fn store_from(
+        &mut self,
+        addr: SymbolId,
+        value: Value,
+        thread_id: usize,
+        source: Option<NodeId>,
+    ) {
         self.buffers
             .entry(thread_id)
             .or_insert(Buffer::new())
-            .push(WriteOperation::new(addr.to_string(), value));
+            .push(WriteOperation::new(addr, value).with_source(source));
     }
 
-    fn load(&self, addr: &str, thread_id: usize) -> usize {
+    fn load(&self, addr: SymbolId, thread_id: usize) -> Value {
         self.buffers
             .get(&thread_id)
             .and_then(|buffer| buffer.load(addr))
             .unwrap_or_else(|| self.memory.load(addr))
     }
 
-    fn propagate(&mut self, thread_id: usize) {
-        let write = self.buffers.get_mut(&thread_id).unwrap().propagate();
+    fn last_write(&self, addr: SymbolId, thread_id: usize) -> Option<&NodeId> {
+        match self
+            .buffers
+            .get(&thread_id)
+            .and_then(|buffer| buffer.latest(addr))
+        {
+            Some(write) => write.source.as_ref(),
+            None => self.memory.source(addr),
+        }
+    }
+
+    fn propagate(&mut self, thread_id: usize, addr: SymbolId) {
+        // A restored snapshot has no buffer for threads with none pending
+        let write = self
+            .buffers
+            .get_mut(&thread_id)
+            .and_then(|buffer| buffer.propagate(addr));
         if let Some(write) = write {
-            self.memory.store(&write.addr, write.value);
+            self.memory
+                .commit_from(write.addr, write.value, thread_id, write.source);
         }
     }
 }
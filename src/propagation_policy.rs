@@ -0,0 +1,58 @@
+use crate::dependency_graph::{InstructionNode, NodeType};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Picks the next node to execute when a TSO/PSO execution is run
+/// automatically, see `TSO::with_policy`.
+pub trait PropagationPolicy {
+    /// Index of the chosen option, or `None` to stop the execution.
+    fn choose(&self, options: &[Rc<RefCell<InstructionNode>>]) -> Option<usize>;
+}
+
+/// Flushes store buffers eagerly: propagates the oldest buffered write
+/// first, and otherwise executes the first instruction in (thread, line)
+/// order. Without propagation choices left, runs look like SC runs.
+pub struct OldestFirst;
+
+impl PropagationPolicy for OldestFirst {
+    fn choose(&self, options: &[Rc<RefCell<InstructionNode>>]) -> Option<usize> {
+        let propagate = options
+            .iter()
+            .position(|option| matches!(option.borrow().instruction, NodeType::Propagate(_)));
+        propagate.or_else(|| {
+            (0..options.len()).min_by_key(|index| {
+                let node = options[*index].borrow();
+                match &node.instruction {
+                    NodeType::Instruction(instruction) => {
+                        (instruction.thread_id, instruction.line_index)
+                    }
+                    NodeType::Propagate(_) => unreachable!(),
+                }
+            })
+        })
+    }
+}
+
+/// Chooses uniformly at random; the same seed gives the same execution.
+pub struct Random {
+    rng: RefCell<StdRng>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl PropagationPolicy for Random {
+    fn choose(&self, options: &[Rc<RefCell<InstructionNode>>]) -> Option<usize> {
+        if options.is_empty() {
+            return None;
+        }
+        Some(self.rng.borrow_mut().gen_range(0..options.len()))
+    }
+}
@@ -0,0 +1,99 @@
+//! `serde` support for the program AST. Instructions and their parts are
+//! serialized as strings in the program syntax, e.g. `"store RLX r1 #x"`, so
+//! the serialized form stays stable as long as the grammar does.
+
+use crate::instruction::{
+    ArithCommand, CmpCommand, Command, Instruction, MemoryAccessMode, Reference,
+};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Reads a string and parses it with `parse`, naming `expected` on failure.
+fn deserialize_source<'de, D, T>(
+    deserializer: D,
+    expected: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let source = String::deserialize(deserializer)?;
+    parse(&source).ok_or_else(|| D::Error::custom(format!("invalid {} '{}'", expected, source)))
+}
+
+impl Serialize for ArithCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ArithCommand {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_source(
+            deserializer,
+            "arithmetic operation",
+            |source| match source.parse() {
+                Ok(Command::Arith(cmd)) => Some(cmd),
+                _ => None,
+            },
+        )
+    }
+}
+
+impl Serialize for CmpCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CmpCommand {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_source(deserializer, "comparison", |source| {
+            match source.parse::<Command>() {
+                Ok(Command::Cmp(cmp)) => Some(cmp),
+                _ => None,
+            }
+        })
+    }
+}
+
+impl Serialize for MemoryAccessMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MemoryAccessMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_source(deserializer, "memory access mode", |source| {
+            match source.parse::<Command>() {
+                Ok(Command::MemoryAccess(mode)) => Some(mode),
+                _ => None,
+            }
+        })
+    }
+}
+
+impl Serialize for Reference {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Reference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_source(deserializer, "reference", |source| source.parse().ok())
+    }
+}
+
+impl Serialize for Instruction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_source())
+    }
+}
+
+impl<'de> Deserialize<'de> for Instruction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_source(deserializer, "instruction", |source| source.parse().ok())
+    }
+}
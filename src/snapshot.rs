@@ -0,0 +1,446 @@
+//! Saving and restoring engines mid-execution. Snapshots are plain text, one
+//! record per line:
+//!
+//! ```text
+//! model TSO
+//! thread 0
+//! program 0 r1 = 1
+//! register 0 r1 1
+//! memory x 1
+//! buffer 0 x 1
+//! node 0 instruction 0 2
+//! node 1 propagate 0 1 x
+//! edge 0 1
+//! removed instruction 0 0
+//! label L true
+//! pointer 0 1
+//! ```
+//!
+//! Graph nodes refer to program lines by thread and line index, edges to
+//! nodes by their position in the graph (`edge 0 1`: node 0 depends on
+//! node 1).
+
+use crate::dependency_graph::{DependencyGraph, InstructionNode, NodeType, Propagate};
+use crate::instruction::{Error, LabeledInstruction, Reference, Value};
+use crate::memory_subsystem::{Memory, MemorySubsystem};
+use crate::symbol::SymbolId;
+use crate::thread_subsystem::{Registers, SequentialConsistency, TSO};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::rc::Rc;
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidSnapshot(message.into())
+}
+
+fn write_programs(out: &mut Vec<String>, programs: &[Vec<LabeledInstruction>]) {
+    for (thread_id, program) in programs.iter().enumerate() {
+        out.push(format!("thread {}", thread_id));
+        for instruction in program {
+            let label = match &instruction.label {
+                Some(label) => format!("{}: ", label),
+                None => "".to_string(),
+            };
+            out.push(format!(
+                "program {} {}{}",
+                thread_id,
+                label,
+                instruction.instruction.to_source()
+            ));
+        }
+    }
+}
+
+fn write_registers(out: &mut Vec<String>, registers: &Registers) {
+    let mut threads = registers.registers.iter().collect::<Vec<_>>();
+    threads.sort_by_key(|(thread_id, _)| **thread_id);
+    for (thread_id, memory) in threads {
+        let mut values = memory.data.iter().collect::<Vec<_>>();
+        values.sort();
+        for (reg, value) in values {
+            out.push(format!("register {} {} {}", thread_id, reg, value));
+        }
+    }
+}
+
+fn node_record(node: &NodeType) -> String {
+    match node {
+        NodeType::Instruction(instruction) => format!(
+            "instruction {} {}",
+            instruction.thread_id, instruction.line_index
+        ),
+        NodeType::Propagate(Propagate {
+            associated_write,
+            to_location: Reference::Memory(addr),
+        }) => format!(
+            "propagate {} {} {}",
+            associated_write.thread_id, associated_write.line_index, addr
+        ),
+        NodeType::Propagate(_) => panic!("Propagate to a non-memory location"),
+    }
+}
+
+fn parse_number(token: Option<&str>, line: &str) -> Result<usize, Error> {
+    token
+        .and_then(|token| token.parse::<usize>().ok())
+        .ok_or_else(|| invalid(format!("invalid record '{}'", line)))
+}
+
+fn parse_value(token: Option<&str>, line: &str) -> Result<Value, Error> {
+    token
+        .and_then(|token| token.parse::<i64>().ok())
+        .map(Value)
+        .ok_or_else(|| invalid(format!("invalid record '{}'", line)))
+}
+
+/// Records of a snapshot, grouped by their leading keyword.
+struct Records<'a> {
+    model: &'a str,
+    programs: Vec<Vec<LabeledInstruction>>,
+    registers: Registers,
+    memory: Vec<(&'a str, Value)>,
+    buffers: Vec<(usize, &'a str, Value)>,
+    nodes: Vec<&'a str>,
+    edges: Vec<(usize, usize)>,
+    removed: Vec<&'a str>,
+    labels: Vec<(String, bool)>,
+    pointers: Vec<(usize, usize)>,
+}
+
+impl<'a> Records<'a> {
+    fn parse(src: &'a str) -> Result<Self, Error> {
+        let mut records = Records {
+            model: "",
+            programs: Vec::new(),
+            registers: Registers::new(),
+            memory: Vec::new(),
+            buffers: Vec::new(),
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            removed: Vec::new(),
+            labels: Vec::new(),
+            pointers: Vec::new(),
+        };
+        for line in src.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let mut tokens = rest.split_whitespace();
+            match keyword {
+                "model" => records.model = rest.trim(),
+                "thread" => {
+                    records.programs.push(Vec::new());
+                    records
+                        .registers
+                        .registers
+                        .insert(records.programs.len() - 1, Memory::new());
+                }
+                "program" => {
+                    let (thread_id, source) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| invalid(format!("invalid record '{}'", line)))?;
+                    let thread_id = parse_number(Some(thread_id), line)?;
+                    let program = records
+                        .programs
+                        .get_mut(thread_id)
+                        .ok_or_else(|| invalid(format!("unknown thread in '{}'", line)))?;
+                    program.push(LabeledInstruction::from_line(
+                        source,
+                        program.len(),
+                        thread_id,
+                    )?);
+                }
+                "register" => {
+                    let thread_id = parse_number(tokens.next(), line)?;
+                    let reg = tokens.next().unwrap_or_default();
+                    let value = parse_value(tokens.next(), line)?;
+                    records
+                        .registers
+                        .registers
+                        .get_mut(&thread_id)
+                        .ok_or_else(|| invalid(format!("unknown thread in '{}'", line)))?
+                        .store(reg, value);
+                }
+                "memory" => {
+                    let addr = tokens.next().unwrap_or_default();
+                    records
+                        .memory
+                        .push((addr, parse_value(tokens.next(), line)?));
+                }
+                "buffer" => {
+                    let thread_id = parse_number(tokens.next(), line)?;
+                    let addr = tokens.next().unwrap_or_default();
+                    let value = parse_value(tokens.next(), line)?;
+                    records.buffers.push((thread_id, addr, value));
+                }
+                "node" => {
+                    if parse_number(tokens.next(), line)? != records.nodes.len() {
+                        return Err(invalid(format!("nodes out of order at '{}'", line)));
+                    }
+                    let node = rest.split_once(' ').map(|(_, node)| node).unwrap_or("");
+                    records.nodes.push(node);
+                }
+                "edge" => {
+                    let from = parse_number(tokens.next(), line)?;
+                    records
+                        .edges
+                        .push((from, parse_number(tokens.next(), line)?));
+                }
+                "removed" => records.removed.push(rest),
+                "label" => {
+                    let label = tokens.next().unwrap_or_default().to_string();
+                    let active = tokens.next() == Some("true");
+                    records.labels.push((label, active));
+                }
+                "pointer" => {
+                    let thread_id = parse_number(tokens.next(), line)?;
+                    records
+                        .pointers
+                        .push((thread_id, parse_number(tokens.next(), line)?));
+                }
+                _ => return Err(invalid(format!("unknown record '{}'", line))),
+            }
+        }
+        Ok(records)
+    }
+
+    fn node(&self, record: &str) -> Result<NodeType, Error> {
+        let tokens = record.split_whitespace().collect::<Vec<_>>();
+        let instruction = |thread_id: &str, line_index: &str| {
+            let thread_id = parse_number(Some(thread_id), record)?;
+            let line_index = parse_number(Some(line_index), record)?;
+            self.programs
+                .get(thread_id)
+                .and_then(|program| program.get(line_index))
+                .cloned()
+                .ok_or_else(|| invalid(format!("unknown instruction in '{}'", record)))
+        };
+        match tokens[..] {
+            ["instruction", thread_id, line_index] => {
+                Ok(NodeType::Instruction(instruction(thread_id, line_index)?))
+            }
+            ["propagate", thread_id, line_index, addr] => Ok(NodeType::Propagate(Propagate::new(
+                instruction(thread_id, line_index)?,
+                Reference::Memory(addr.into()),
+            ))),
+            _ => Err(invalid(format!("invalid node '{}'", record))),
+        }
+    }
+}
+
+impl TSO {
+    /// Saves the engine, including the pending dependency graph, so that
+    /// `load_state` can resume the execution later.
+    pub fn save_state(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, self.to_snapshot())
+    }
+
+    /// The snapshot `save_state` writes.
+    pub(crate) fn to_snapshot(&self) -> String {
+        let mut out = vec![format!("model {}", if self.is_pso { "PSO" } else { "TSO" })];
+        write_programs(&mut out, &self.programs);
+        self.write_state(&mut out, false);
+        out.join("\n") + "\n"
+    }
+
+    /// The records of `to_snapshot` after the programs, leaving out what
+    /// depends only on the order of steps that led to the state. Engines of
+    /// the same programs with equal fingerprints reach the same final states.
+    pub(crate) fn state_fingerprint(&self) -> String {
+        let mut out = Vec::new();
+        self.write_state(&mut out, true);
+        out.join("\n")
+    }
+
+    /// The end of `remove_queue` a jump back may still put into the graph:
+    /// a jump stops at the last removal of its label, so nothing before the
+    /// last removal of every active label comes back.
+    fn revertible(&self) -> &[Rc<RefCell<InstructionNode>>] {
+        let mut start = self.remove_queue.len();
+        let mut seen = HashSet::new();
+        for (index, node) in self.remove_queue.iter().enumerate().rev() {
+            if let Some(label) = node.borrow().instruction.label() {
+                if self.label_map.get(&label) == Some(&true) && seen.insert(label) {
+                    start = index;
+                }
+            }
+        }
+        &self.remove_queue[start..]
+    }
+
+    /// Appends the records after the programs. `canonical` records are those
+    /// of `state_fingerprint`.
+    fn write_state(&self, out: &mut Vec<String>, canonical: bool) {
+        write_registers(out, &self.registers);
+        let mut memory = self.memory_subsystem.memory.data.iter().collect::<Vec<_>>();
+        memory.sort();
+        for (addr, value) in memory {
+            out.push(format!("memory {} {}", addr, value));
+        }
+        for thread_id in 0..self.programs.len() {
+            for (addr, value) in self.memory_subsystem.pending_writes(thread_id) {
+                out.push(format!("buffer {} {} {}", thread_id, addr, value));
+            }
+        }
+
+        let mut nodes = self.dependency_graph.nodes().iter().collect::<Vec<_>>();
+        if canonical {
+            // Only the order of the nodes of a thread among themselves matters
+            nodes.sort_by_key(|node| node.borrow().instruction.thread_id());
+        }
+        let index_of: HashMap<*const RefCell<InstructionNode>, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (Rc::as_ptr(node), index))
+            .collect();
+        for (index, node) in nodes.iter().enumerate() {
+            out.push(format!(
+                "node {} {}",
+                index,
+                node_record(&node.borrow().instruction)
+            ));
+        }
+        let mut edges = Vec::new();
+        for (index, node) in nodes.iter().enumerate() {
+            for dependency in &node.borrow().depends_on {
+                edges.push((index, index_of[&Rc::as_ptr(dependency)]));
+            }
+        }
+        if canonical {
+            edges.sort_unstable();
+        }
+        for (from, to) in edges {
+            out.push(format!("edge {} {}", from, to));
+        }
+
+        let removed = match canonical {
+            true => self.revertible(),
+            false => &self.remove_queue,
+        };
+        for node in removed {
+            out.push(format!(
+                "removed {}",
+                node_record(&node.borrow().instruction)
+            ));
+        }
+        let mut labels = self.label_map.iter().collect::<Vec<_>>();
+        labels.sort();
+        // An inactive label is no different from one never removed
+        for (label, active) in labels
+            .into_iter()
+            .filter(|(_, active)| !canonical || **active)
+        {
+            out.push(format!("label {} {}", label, active));
+        }
+    }
+
+    /// Restores an engine saved with `save_state`.
+    pub fn load_state(path: &str) -> Result<Self, Error> {
+        let src = fs::read_to_string(path).map_err(|err| invalid(format!("{}: {}", path, err)))?;
+        Self::from_snapshot(&src)
+    }
+
+    /// Restores an engine from the snapshot `to_snapshot` returned.
+    pub(crate) fn from_snapshot(src: &str) -> Result<Self, Error> {
+        let records = Records::parse(src)?;
+        let is_pso = match records.model {
+            "TSO" => false,
+            "PSO" => true,
+            model => return Err(invalid(format!("not a TSO or PSO snapshot: '{}'", model))),
+        };
+
+        let mut engine = TSO::new(records.programs.clone(), is_pso);
+        engine.registers = records.registers.clone();
+        for (addr, value) in &records.memory {
+            engine.memory_subsystem.memory.store(*addr, *value);
+        }
+        for (thread_id, addr, value) in &records.buffers {
+            engine
+                .memory_subsystem
+                .store(SymbolId::intern(addr), *value, *thread_id);
+        }
+
+        let mut graph = DependencyGraph::new();
+        for record in &records.nodes {
+            graph.add_some_node(records.node(record)?);
+        }
+        for (from, to) in &records.edges {
+            match (graph.nodes().get(*from), graph.nodes().get(*to)) {
+                (Some(from), Some(to)) => InstructionNode::add_dependency(from.clone(), to.clone()),
+                _ => return Err(invalid(format!("edge to an unknown node: {} {}", from, to))),
+            }
+        }
+        if let Some(cycle) = graph.detect_cycle() {
+            return Err(invalid(format!("cyclic graph: {}", cycle.join(" -> "))));
+        }
+        engine.dependency_graph = graph;
+        engine.remove_queue = records
+            .removed
+            .iter()
+            .map(|record| records.node(record).map(InstructionNode::detached))
+            .collect::<Result<_, _>>()?;
+        engine.label_map = records.labels.iter().cloned().collect();
+        Ok(engine)
+    }
+}
+
+impl SequentialConsistency {
+    /// Saves the engine so that `load_state` can resume the execution later.
+    pub fn save_state(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, self.to_snapshot())
+    }
+
+    /// The snapshot `save_state` writes.
+    pub(crate) fn to_snapshot(&self) -> String {
+        let mut out = vec!["model SC".to_string()];
+        write_programs(&mut out, &self.programs);
+        self.write_state(&mut out);
+        out.join("\n") + "\n"
+    }
+
+    /// See `TSO::state_fingerprint`. The state of an SC engine is the same
+    /// whatever order of steps led to it.
+    pub(crate) fn state_fingerprint(&self) -> String {
+        let mut out = Vec::new();
+        self.write_state(&mut out);
+        out.join("\n")
+    }
+
+    fn write_state(&self, out: &mut Vec<String>) {
+        write_registers(out, &self.registers);
+        let mut memory = self.memory_subsystem.memory.data.iter().collect::<Vec<_>>();
+        memory.sort();
+        for (addr, value) in memory {
+            out.push(format!("memory {} {}", addr, value));
+        }
+        for (thread_id, pointer) in self.instruction_pointers.iter().enumerate() {
+            out.push(format!("pointer {} {}", thread_id, pointer));
+        }
+    }
+
+    /// Restores an engine saved with `save_state`.
+    pub fn load_state(path: &str) -> Result<Self, Error> {
+        let src = fs::read_to_string(path).map_err(|err| invalid(format!("{}: {}", path, err)))?;
+        Self::from_snapshot(&src)
+    }
+
+    /// Restores an engine from the snapshot `to_snapshot` returned.
+    pub(crate) fn from_snapshot(src: &str) -> Result<Self, Error> {
+        let records = Records::parse(src)?;
+        if records.model != "SC" {
+            return Err(invalid(format!("not an SC snapshot: '{}'", records.model)));
+        }
+
+        let mut engine = SequentialConsistency::new(records.programs.clone());
+        engine.registers = records.registers;
+        for (addr, value) in &records.memory {
+            engine.memory_subsystem.memory.store(*addr, *value);
+        }
+        for (thread_id, pointer) in records.pointers {
+            match engine.instruction_pointers.get_mut(thread_id) {
+                Some(instruction_pointer) => *instruction_pointer = pointer,
+                None => return Err(invalid(format!("unknown thread {}", thread_id))),
+            }
+        }
+        Ok(engine)
+    }
+}
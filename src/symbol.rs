@@ -0,0 +1,123 @@
+//! Interned names of registers and memory locations. Programs name the same
+//! few locations over and over, so each name is stored once and references
+//! carry a `SymbolId`, which copies, compares and hashes as a pointer to the
+//! stored name.
+
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+/// A name stored in the `Interner`. Equal names have equal ids.
+#[derive(Copy, Clone)]
+pub struct SymbolId(&'static str);
+
+impl SymbolId {
+    /// The id of `name`, interning it if it wasn't yet.
+    pub fn intern(name: &str) -> Self {
+        Interner::global().intern(name)
+    }
+
+    /// The id of `name` if it was interned, without interning it.
+    pub fn find(name: &str) -> Option<Self> {
+        Interner::global().names.get(name).copied().map(Self)
+    }
+
+    /// The name the id was interned from.
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl From<&str> for SymbolId {
+    fn from(name: &str) -> Self {
+        Self::intern(name)
+    }
+}
+
+/// Names are interned once, so equal names are the same string.
+impl PartialEq for SymbolId {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for SymbolId {}
+
+impl Hash for SymbolId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state)
+    }
+}
+
+/// Ordered by name, so that sorting ids sorts names.
+impl Ord for SymbolId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(other.0)
+    }
+}
+
+impl PartialOrd for SymbolId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for SymbolId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Shows the name, as `Debug` of the `String` it replaces did.
+impl Debug for SymbolId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// A name to look up. Lookups that only read take one, so that looking up a
+/// name never written doesn't intern it.
+pub trait Symbol {
+    /// The id of the name, `None` if it was never interned.
+    fn lookup(self) -> Option<SymbolId>;
+}
+
+impl Symbol for SymbolId {
+    fn lookup(self) -> Option<SymbolId> {
+        Some(self)
+    }
+}
+
+impl Symbol for &str {
+    fn lookup(self) -> Option<SymbolId> {
+        SymbolId::find(self)
+    }
+}
+
+/// The names interned by the process. Names are never removed: they are
+/// leaked into `'static` strings, so resolving an id needs no lock and the
+/// interner can be shared by every engine and thread.
+#[derive(Default)]
+struct Interner {
+    names: HashSet<&'static str>,
+}
+
+impl Interner {
+    fn global() -> MutexGuard<'static, Interner> {
+        static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+        INTERNER
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(name) = self.names.get(name) {
+            return SymbolId(name);
+        }
+        let name: &'static str = Box::leak(name.into());
+        self.names.insert(name);
+        SymbolId(name)
+    }
+}
@@ -1,14 +1,22 @@
-use crate::dependency_graph::{DependencyGraph, InstructionNode, NodeType, Propagate};
-use crate::instruction::{Instruction, LabeledInstruction, Reference};
+use crate::dependency_graph::{
+    DependencyGraph, GraphError, InstructionNode, MpReport, NodeId, NodeType, Propagate,
+};
+use crate::exploration::{self, Exploration, Explore, Settings};
+use crate::instruction::{
+    ArithCommand, ArithMode, Error, Instruction, LabeledInstruction, Reference, SubMode, Value,
+};
 use crate::memory_subsystem::{Memory, MemorySubsystem, SCMemorySubsystem, TSOMemorySubsystem};
-use std::borrow::Borrow;
+use crate::propagation_policy::{OldestFirst, PropagationPolicy};
+use crate::symbol::{Symbol, SymbolId};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
 use std::process::Command;
 use std::rc::Rc;
+use std::str::FromStr;
 
+#[derive(Clone)]
 pub struct Registers {
     pub registers: HashMap<usize, Memory>,
 }
@@ -20,28 +28,581 @@ impl Registers {
         }
     }
 
-    pub fn load(&self, addr: &str, thread_id: usize) -> usize {
+    pub fn load(&self, addr: impl Symbol, thread_id: usize) -> Value {
         self.registers.get(&thread_id).unwrap().load(addr)
     }
 
-    pub fn store(&mut self, addr: &str, value: usize, thread_id: usize) {
+    pub fn store(&mut self, addr: impl Into<SymbolId>, value: Value, thread_id: usize) {
         self.registers
             .get_mut(&thread_id)
             .unwrap()
             .store(addr, value);
     }
+
+    pub fn set_default(&mut self, default: Value) {
+        for memory in self.registers.values_mut() {
+            memory.set_default(default);
+        }
+    }
+
+    /// Warning for a store of a register its thread never assigned, which
+    /// silently writes the default value, usually by mistake.
+    fn unassigned_store_warning(
+        &self,
+        reg: SymbolId,
+        addr: SymbolId,
+        thread_id: usize,
+        line_index: usize,
+    ) -> Option<String> {
+        let assigned = self.registers[&thread_id].data.contains_key(&reg);
+        (!assigned).then(|| {
+            format!(
+                "Warning: thread {}, line {} stores {} to #{}, but {} was never assigned",
+                thread_id, line_index, reg, addr, reg
+            )
+        })
+    }
 }
 
 impl std::fmt::Display for Registers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for (thread_id, memory) in self.registers.iter() {
+        let mut thread_ids: Vec<&usize> = self.registers.keys().collect();
+        thread_ids.sort();
+        for thread_id in thread_ids {
             writeln!(f, "Thread {}", thread_id)?;
-            writeln!(f, "{}", memory)?;
+            writeln!(f, "{}", self.registers[thread_id])?;
+        }
+        Ok(())
+    }
+}
+
+/// Observable outcome of a finished execution: shared memory and the
+/// registers of every thread, in a canonical (sorted) form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FinalState {
+    pub memory: BTreeMap<String, i64>,
+    pub registers: BTreeMap<usize, BTreeMap<String, i64>>,
+}
+
+impl FinalState {
+    fn values(memory: &Memory) -> BTreeMap<String, i64> {
+        memory
+            .data
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.0))
+            .collect()
+    }
+
+    /// Compact single-line form of the state, equal for equal states
+    /// whatever order their locations were written in, e.g.
+    /// `#x=1, 0:r1=1`. Also parses back as a `StateCondition`.
+    pub fn fingerprint(&self) -> String {
+        self.to_string()
+    }
+
+    pub fn new(memory: &Memory, registers: &Registers) -> Self {
+        Self {
+            memory: Self::values(memory),
+            registers: registers
+                .registers
+                .iter()
+                .map(|(thread_id, memory)| (*thread_id, Self::values(memory)))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for FinalState {
+    /// Uses the same `#addr=value` / `thread:reg=value` syntax that
+    /// `StateCondition` accepts.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let memory = self
+            .memory
+            .iter()
+            .map(|(addr, value)| format!("#{}={}", addr, value));
+        let registers = self.registers.iter().flat_map(|(thread_id, registers)| {
+            registers
+                .iter()
+                .map(move |(reg, value)| format!("{}:{}={}", thread_id, reg, value))
+        });
+        write!(
+            f,
+            "{}",
+            memory.chain(registers).collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+/// What executing a single option changes: registers and shared memory
+/// written, and writes added to store buffers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepEffect {
+    pub registers: Vec<(usize, String, i64)>,
+    pub memory: Vec<(String, i64)>,
+    pub buffered: Vec<(usize, String, i64)>,
+}
+
+impl StepEffect {
+    fn between(before: &FinalState, after: &FinalState) -> Self {
+        let registers = after
+            .registers
+            .iter()
+            .flat_map(|(thread_id, registers)| {
+                registers
+                    .iter()
+                    .filter(move |(reg, value)| {
+                        before
+                            .registers
+                            .get(thread_id)
+                            .and_then(|registers| registers.get(*reg))
+                            != Some(value)
+                    })
+                    .map(move |(reg, value)| (*thread_id, reg.clone(), *value))
+            })
+            .collect();
+        let memory = after
+            .memory
+            .iter()
+            .filter(|(addr, value)| before.memory.get(*addr) != Some(value))
+            .map(|(addr, value)| (addr.clone(), *value))
+            .collect();
+        Self {
+            registers,
+            memory,
+            buffered: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for StepEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.registers.is_empty() && self.memory.is_empty() && self.buffered.is_empty() {
+            return writeln!(f, "No visible changes");
+        }
+        for (thread_id, reg, value) in &self.registers {
+            writeln!(f, "{}:{} := {}", thread_id, reg, value)?;
+        }
+        for (addr, value) in &self.memory {
+            writeln!(f, "#{} := {}", addr, value)?;
+        }
+        for (thread_id, addr, value) in &self.buffered {
+            writeln!(f, "buffer {}: #{} := {}", thread_id, addr, value)?;
         }
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    Memory(String),
+    /// A register of the given thread, or of any thread if `None`.
+    Register(Option<usize>, String),
+}
+
+/// Conjunction of `location=value` constraints over a `FinalState`, parsed
+/// from strings like `0:r1=0,1:r2=0,#x=1`. A register without a thread
+/// prefix matches if any thread holds that value in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateCondition {
+    pub constraints: Vec<(Location, i64)>,
+}
+
+impl StateCondition {
+    /// Whether `state` meets every constraint. A location the state leaves
+    /// out was never written, so it holds `default`, the value the engine
+    /// gives such locations.
+    pub fn holds(&self, state: &FinalState, default: i64) -> bool {
+        self.constraints
+            .iter()
+            .all(|(location, expected)| match location {
+                Location::Memory(addr) => state.memory.get(addr).unwrap_or(&default) == expected,
+                Location::Register(Some(thread_id), reg) => state
+                    .registers
+                    .get(thread_id)
+                    .map(|registers| registers.get(reg).unwrap_or(&default) == expected)
+                    .unwrap_or(false),
+                Location::Register(None, reg) => state
+                    .registers
+                    .values()
+                    .any(|registers| registers.get(reg).unwrap_or(&default) == expected),
+            })
+    }
+}
+
+impl FromStr for StateCondition {
+    type Err = Error;
+
+    fn from_str(cond: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCondition(cond.to_string());
+        let mut constraints = Vec::new();
+        for constraint in cond.split(',').map(str::trim) {
+            let (location, value) = constraint.split_once('=').ok_or_else(invalid)?;
+            let value = value.trim().parse::<i64>().map_err(|_| invalid())?;
+            let location = match location.trim() {
+                "" | "#" => return Err(invalid()),
+                location if location.starts_with('#') => {
+                    Location::Memory(location[1..].to_string())
+                }
+                location => match location.split_once(':') {
+                    Some((thread_id, reg)) if !reg.is_empty() => Location::Register(
+                        Some(thread_id.parse::<usize>().map_err(|_| invalid())?),
+                        reg.to_string(),
+                    ),
+                    Some(_) => return Err(invalid()),
+                    None => Location::Register(None, location.to_string()),
+                },
+            };
+            constraints.push((location, value));
+        }
+        Ok(Self { constraints })
+    }
+}
+
+/// Why an instruction couldn't be executed.
+#[derive(Debug, PartialEq)]
+pub enum ExecutionError {
+    Graph(GraphError),
+    /// Overflow in `ArithMode::Checked`.
+    Arithmetic {
+        thread_id: usize,
+        line_index: usize,
+    },
+    /// Subtraction below zero in `SubMode::ErrorOnUnderflow`.
+    Underflow {
+        thread_id: usize,
+        line_index: usize,
+    },
+    DivisionByZero {
+        thread_id: usize,
+        line_index: usize,
+    },
+    /// A jump to a label the thread's program doesn't define.
+    MissingLabel {
+        thread_id: usize,
+        line_index: usize,
+        label: String,
+    },
+    /// An instruction the engine can't execute, e.g. one with a memory
+    /// location where it needs a register.
+    UnsupportedInstruction {
+        thread_id: usize,
+        line_index: usize,
+        instruction: Box<Instruction>,
+    },
+}
+
+impl ExecutionError {
+    fn unsupported(instruction: &LabeledInstruction) -> Self {
+        ExecutionError::UnsupportedInstruction {
+            thread_id: instruction.thread_id,
+            line_index: instruction.line_index,
+            instruction: Box::new(instruction.instruction.clone()),
+        }
+    }
+
+    /// The error of an arithmetic operation that had no result.
+    fn arithmetic(
+        operation: ArithCommand,
+        sub_mode: Option<SubMode>,
+        thread_id: usize,
+        line_index: usize,
+    ) -> Self {
+        match (operation, sub_mode) {
+            (ArithCommand::Sub, Some(SubMode::ErrorOnUnderflow)) => ExecutionError::Underflow {
+                thread_id,
+                line_index,
+            },
+            (ArithCommand::Div, _) => ExecutionError::DivisionByZero {
+                thread_id,
+                line_index,
+            },
+            _ => ExecutionError::Arithmetic {
+                thread_id,
+                line_index,
+            },
+        }
+    }
+}
+
+impl From<GraphError> for ExecutionError {
+    fn from(err: GraphError) -> Self {
+        ExecutionError::Graph(err)
+    }
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::Graph(err) => write!(f, "{}", err),
+            ExecutionError::Arithmetic {
+                thread_id,
+                line_index,
+            } => write!(
+                f,
+                "Thread {}, line {}: arithmetic overflow",
+                thread_id, line_index
+            ),
+            ExecutionError::Underflow {
+                thread_id,
+                line_index,
+            } => write!(
+                f,
+                "Thread {}, line {}: subtraction below zero",
+                thread_id, line_index
+            ),
+            ExecutionError::DivisionByZero {
+                thread_id,
+                line_index,
+            } => write!(
+                f,
+                "Thread {}, line {}: division by zero",
+                thread_id, line_index
+            ),
+            ExecutionError::MissingLabel {
+                thread_id,
+                line_index,
+                label,
+            } => write!(
+                f,
+                "Thread {}, line {}: no label '{}' to jump to",
+                thread_id, line_index, label
+            ),
+            ExecutionError::UnsupportedInstruction {
+                thread_id,
+                line_index,
+                instruction,
+            } => write!(
+                f,
+                "Thread {}, line {}: unsupported instruction '{}'",
+                thread_id, line_index, instruction
+            ),
+        }
+    }
+}
+
+/// Line output by a `print` instruction: the current value of the operand,
+/// memory as the printing thread sees it.
+fn print_output(
+    registers: &Registers,
+    memory: &impl MemorySubsystem,
+    operand: &Reference,
+    thread_id: usize,
+    line_index: usize,
+) -> String {
+    let value = match operand {
+        Reference::Register(reg) => registers.load(*reg, thread_id),
+        Reference::Memory(addr) => memory.load(*addr, thread_id),
+    };
+    format!(
+        "Thread {}, line {}: {} = {}",
+        thread_id, line_index, operand, value
+    )
+}
+
+/// Executes a `cas2`: reads both addresses into the destinations and, if
+/// both hold their expected value, writes the desired ones as the node
+/// `source`. Returns whether it wrote, or `None` without any effect if an
+/// operand has the wrong kind.
+fn cas2(
+    registers: &mut Registers,
+    memory: &mut impl MemorySubsystem,
+    [dests, addrs, expected, desired]: [&[Reference; 2]; 4],
+    thread_id: usize,
+    source: Option<NodeId>,
+) -> Option<bool> {
+    let address = |reference: &Reference| match reference {
+        Reference::Memory(addr) => Some(*addr),
+        Reference::Register(_) => None,
+    };
+    let register = |reference: &Reference| match reference {
+        Reference::Register(reg) => Some(*reg),
+        Reference::Memory(_) => None,
+    };
+    let addrs = [address(&addrs[0])?, address(&addrs[1])?];
+    let dests = [register(&dests[0])?, register(&dests[1])?];
+    let expected = [register(&expected[0])?, register(&expected[1])?];
+    let desired = [register(&desired[0])?, register(&desired[1])?];
+    let current = addrs.map(|addr| memory.load(addr, thread_id));
+    let matches = (0..2).all(|i| current[i] == registers.load(expected[i], thread_id));
+    if matches {
+        for i in 0..2 {
+            let value = registers.load(desired[i], thread_id);
+            memory.store_from(addrs[i], value, thread_id, source.clone());
+        }
+    }
+    for i in 0..2 {
+        registers.store(dests[i], current[i], thread_id);
+    }
+    Some(matches)
+}
+
+/// Index of the line labeled `label` in the program of the thread of `jump`.
+/// Disabled lines are not part of the program, so a label on one can't be
+/// jumped to.
+fn label_index(
+    program: &[LabeledInstruction],
+    jump: &LabeledInstruction,
+    label: &str,
+) -> Result<usize, ExecutionError> {
+    program
+        .iter()
+        .position(|instruction| instruction.label.as_deref() == Some(label))
+        .ok_or_else(|| ExecutionError::MissingLabel {
+            thread_id: jump.thread_id,
+            line_index: jump.line_index,
+            label: label.to_string(),
+        })
+}
+
+/// Lists the references, or says there are none.
+fn join_references(references: Vec<String>) -> String {
+    match references.is_empty() {
+        true => "nothing".to_string(),
+        false => references.join(", "),
+    }
+}
+
+/// The text of the `explain` command: the step, what it does, what it reads
+/// and writes, what executing it would change and whether it may run now.
+fn explanation(
+    step: &str,
+    semantics: &str,
+    [reads, writes]: [Vec<String>; 2],
+    effect: Result<StepEffect, ExecutionError>,
+    allowed: &str,
+) -> String {
+    let effect = match effect {
+        Ok(effect) => effect
+            .to_string()
+            .lines()
+            .map(|line| format!("  {}\n", line))
+            .collect(),
+        Err(err) => format!("  Fails: {}\n", err),
+    };
+    format!(
+        "{}\n{}\nReads: {}\nWrites: {}\nEffect:\n{}Allowed now: {}\n",
+        step,
+        semantics,
+        join_references(reads),
+        join_references(writes),
+        effect,
+        allowed
+    )
+}
+
+/// Registers and memory locations the instruction reads and writes, memory
+/// writes marked as going to the store buffer if `buffered`.
+fn accesses(instruction: &Instruction, buffered: bool) -> [Vec<String>; 2] {
+    let mut reads: Vec<&Reference> = instruction.read_registers();
+    reads.extend(instruction.read_locations());
+    if let Instruction::Print(operand @ Reference::Memory(_)) = instruction {
+        reads.push(operand);
+    }
+    let writes = instruction
+        .write_registers()
+        .into_iter()
+        .map(|reg| reg.to_string());
+    let memory_writes = instruction
+        .write_locations()
+        .into_iter()
+        .map(|addr| match buffered {
+            true => format!("{} (into the store buffer)", addr),
+            false => addr.to_string(),
+        });
+    [
+        reads
+            .iter()
+            .map(|reference| reference.to_string())
+            .collect(),
+        writes.chain(memory_writes).collect(),
+    ]
+}
+
+/// How `run_to` stopped, with the number of executed steps.
+#[derive(Debug, PartialEq)]
+pub enum RunToResult {
+    Reached(usize),
+    /// The next step writes the given watched address.
+    Breakpoint(usize, String),
+    Unreachable(usize),
+}
+
+/// An option that can be executed next, with what a UI needs to list it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionInfo {
+    pub thread_id: usize,
+    /// Line of the instruction, or of the write a propagate commits.
+    pub line_index: usize,
+    pub is_propagate: bool,
+    pub description: String,
+}
+
+impl OptionInfo {
+    /// Mnemonic of the option that doesn't change when the options are
+    /// reordered: `t<thread>:<line>`, or `p<thread>:<line>` for a propagate.
+    pub fn mnemonic(&self) -> String {
+        let kind = if self.is_propagate { 'p' } else { 't' };
+        format!("{}{}:{}", kind, self.thread_id, self.line_index)
+    }
+}
+
+impl From<&LabeledInstruction> for OptionInfo {
+    fn from(instruction: &LabeledInstruction) -> Self {
+        Self {
+            thread_id: instruction.thread_id,
+            line_index: instruction.line_index,
+            is_propagate: false,
+            description: instruction.to_string(),
+        }
+    }
+}
+
+impl From<&NodeType> for OptionInfo {
+    fn from(node: &NodeType) -> Self {
+        match node {
+            NodeType::Instruction(instruction) => instruction.into(),
+            NodeType::Propagate(propagate) => Self {
+                thread_id: propagate.associated_write.thread_id,
+                line_index: propagate.associated_write.line_index,
+                is_propagate: true,
+                description: node.to_string(),
+            },
+        }
+    }
+}
+
+/// Renders `(thread_id, text)` steps as a table with one column per thread,
+/// time flowing downward.
+fn swimlane(thread_count: usize, steps: impl Iterator<Item = (usize, String)>) -> String {
+    let mut rows = vec![(0..thread_count)
+        .map(|thread_id| format!("Thread {}", thread_id))
+        .collect::<Vec<_>>()];
+    for (thread_id, text) in steps {
+        let mut row = vec![String::new(); thread_count];
+        row[thread_id] = text;
+        rows.push(row);
+    }
+    let widths = (0..thread_count)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let mut output = String::new();
+    for (index, row) in rows.iter().enumerate() {
+        let step = match index {
+            0 => "step".to_string(),
+            _ => index.to_string(),
+        };
+        let cells = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>();
+        let line = format!("{:>4} | {}", step, cells.join(" | "));
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
 pub struct TSO {
     pub memory_subsystem: TSOMemorySubsystem,
     pub programs: Vec<Vec<LabeledInstruction>>,
@@ -50,10 +611,62 @@ pub struct TSO {
     pub is_pso: bool,
     pub remove_queue: Vec<Rc<RefCell<InstructionNode>>>,
     pub label_map: HashMap<String, bool>,
+    pub arith_mode: ArithMode,
+    pub sub_mode: Option<SubMode>,
+    /// Value of memory locations and registers never written.
+    pub mem_default: i64,
+    /// Executed instructions and propagates, in execution order.
+    pub trace: Vec<NodeType>,
+    /// See `with_eager_propagate`.
+    pub eager_propagate: bool,
+    /// See `with_policy`.
+    policy: Rc<dyn PropagationPolicy>,
+    warnings: Vec<String>,
+    output: Vec<String>,
+    /// See `observed_values`.
+    observed: HashMap<NodeId, Vec<(Value, Option<NodeId>)>>,
+}
+
+impl Clone for TSO {
+    fn clone(&self) -> Self {
+        Self {
+            memory_subsystem: self.memory_subsystem.clone(),
+            programs: self.programs.clone(),
+            dependency_graph: self.dependency_graph.clone(),
+            registers: self.registers.clone(),
+            is_pso: self.is_pso,
+            // Removed nodes are only ever read back for their instruction
+            remove_queue: self
+                .remove_queue
+                .iter()
+                .map(|node| InstructionNode::detached(RefCell::borrow(node).instruction.clone()))
+                .collect(),
+            label_map: self.label_map.clone(),
+            arith_mode: self.arith_mode,
+            sub_mode: self.sub_mode,
+            mem_default: self.mem_default,
+            trace: self.trace.clone(),
+            eager_propagate: self.eager_propagate,
+            policy: self.policy.clone(),
+            warnings: self.warnings.clone(),
+            output: self.output.clone(),
+            observed: self.observed.clone(),
+        }
+    }
 }
 
 impl TSO {
+    /// Panics if the dependency graph of `programs` is cyclic, which would be
+    /// a bug of the graph, see `try_new`.
     pub fn new(programs: Vec<Vec<LabeledInstruction>>, is_pso: bool) -> Self {
+        Self::try_new(programs, is_pso).unwrap_or_else(|err| panic!("Internal error: {}", err))
+    }
+
+    /// Like `new`, but returns a cyclic dependency graph as an error.
+    pub fn try_new(
+        programs: Vec<Vec<LabeledInstruction>>,
+        is_pso: bool,
+    ) -> Result<Self, GraphError> {
         let mut registers = Registers::new();
         let mut dependency_graph = DependencyGraph::new();
         for (thread_id, program) in programs.iter().enumerate() {
@@ -63,8 +676,11 @@ impl TSO {
             registers.registers.insert(thread_id, Memory::new());
         }
         dependency_graph.build_dependencies();
+        if let Some(cycle) = dependency_graph.detect_cycle() {
+            return Err(GraphError::Cycle(cycle));
+        }
 
-        Self {
+        Ok(Self {
             memory_subsystem: TSOMemorySubsystem::new(),
             programs,
             dependency_graph,
@@ -72,18 +688,414 @@ impl TSO {
             is_pso,
             remove_queue: Vec::new(),
             label_map: HashMap::new(),
+            arith_mode: ArithMode::default(),
+            sub_mode: None,
+            mem_default: 0,
+            trace: Vec::new(),
+            eager_propagate: false,
+            policy: Rc::new(OldestFirst),
+            warnings: Vec::new(),
+            output: Vec::new(),
+            observed: HashMap::new(),
+        })
+    }
+
+    /// Schedules propagates eagerly: after every step, while some option
+    /// reads memory (a load or a read-modify-write), every propagate that can
+    /// run is executed before the options are offered. A read then sees
+    /// every write issued before it whose propagation nothing holds back,
+    /// instead of missing it because it is still buffered. Reorderings of
+    /// the instructions themselves are unaffected. Only the order of steps is
+    /// restricted; what a read sees for a given order is unchanged, and the
+    /// automatic propagates are part of the trace.
+    pub fn with_eager_propagate(mut self, eager_propagate: bool) -> Self {
+        self.eager_propagate = eager_propagate;
+        self
+    }
+
+    /// Sets the policy picking the steps of `run` and `run_to`,
+    /// `OldestFirst` by default.
+    pub fn with_policy(mut self, policy: Box<dyn PropagationPolicy>) -> Self {
+        self.policy = policy.into();
+        self
+    }
+
+    pub fn with_arith_mode(mut self, arith_mode: ArithMode) -> Self {
+        self.arith_mode = arith_mode;
+        self
+    }
+
+    /// Overrides the `ArithMode` for subtraction.
+    pub fn with_sub_mode(mut self, sub_mode: SubMode) -> Self {
+        self.sub_mode = Some(sub_mode);
+        self
+    }
+
+    /// Warnings about suspicious steps since the last call, such as stores
+    /// of registers never assigned.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Lines output by `print` instructions since the last call.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Sets the value of memory locations and registers never written.
+    pub fn with_mem_default(mut self, mem_default: i64) -> Self {
+        self.mem_default = mem_default;
+        self.memory_subsystem.memory.set_default(Value(mem_default));
+        self.registers.set_default(Value(mem_default));
+        self
+    }
+
+    /// Returns to the initial state, rebuilding the dependency graph from the
+    /// programs the engine was created with.
+    pub fn reset(&mut self) {
+        let mut initial =
+            Self::new(self.programs.clone(), self.is_pso).with_settings(self.settings());
+        initial.policy = self.policy.clone();
+        *self = initial;
+    }
+
+    pub(crate) fn settings(&self) -> Settings {
+        Settings {
+            arith_mode: self.arith_mode,
+            sub_mode: self.sub_mode,
+            mem_default: self.mem_default,
+            eager_propagate: self.eager_propagate,
         }
     }
 
+    pub(crate) fn with_settings(self, settings: Settings) -> Self {
+        let mut system = self
+            .with_arith_mode(settings.arith_mode)
+            .with_mem_default(settings.mem_default)
+            .with_eager_propagate(settings.eager_propagate);
+        system.sub_mode = settings.sub_mode;
+        system
+    }
+
     pub fn get_instructions_to_exec(&self) -> Vec<Rc<RefCell<InstructionNode>>> {
-        return self.dependency_graph.get_leaves();
+        self.dependency_graph.get_leaves()
     }
 
-    pub fn save_graph(&self, filename: &str) {
-        let file_content = self.dependency_graph.to_dot();
-        let mut file = File::create(filename).expect("Unable to create file");
-        file.write_all(file_content.as_bytes())
-            .expect("Unable to write data");
+    /// The options of `get_instructions_to_exec`, in the same order, with
+    /// their thread, line, kind and description.
+    pub fn options_detailed(&self) -> Vec<OptionInfo> {
+        self.get_instructions_to_exec()
+            .iter()
+            .map(|option| OptionInfo::from(&RefCell::borrow(option).instruction))
+            .collect()
+    }
+
+    /// Message passing idioms of the pending instructions and whether the
+    /// access modes and fences synchronize them.
+    pub fn check_message_passing(&self) -> Vec<MpReport> {
+        self.dependency_graph.message_passing()
+    }
+
+    /// Sets an initial value before execution starts. Memory is written
+    /// directly to shared memory, bypassing the store buffers.
+    pub fn initialize(&mut self, location: &Location, value: i64) {
+        let value = Value(value);
+        match location {
+            Location::Memory(addr) => self.memory_subsystem.memory.store(addr.as_str(), value),
+            Location::Register(Some(thread_id), reg) => {
+                self.registers.store(reg.as_str(), value, *thread_id)
+            }
+            Location::Register(None, reg) => {
+                for memory in self.registers.registers.values_mut() {
+                    memory.store(reg.as_str(), value);
+                }
+            }
+        }
+    }
+
+    /// The value the last execution of the load or read-modify-write with
+    /// the given node id read, see `observed_values`.
+    pub fn observed_value(&self, node_id: &str) -> Option<(Value, Option<NodeId>)> {
+        self.observed_values(node_id).last().cloned()
+    }
+
+    /// The values each execution of the load or read-modify-write with the
+    /// given node id read, in execution order, with the id of the store or
+    /// read-modify-write each was read from, `None` for an initial value or
+    /// a write restored from a snapshot. A node run again after a jump keeps
+    /// its id, so it has an entry per run. A `cas2` reads two locations and
+    /// adds an entry for each, in the order of its addresses.
+    pub fn observed_values(&self, node_id: &str) -> &[(Value, Option<NodeId>)] {
+        self.observed.get(node_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// The value `thread_id` reads at `addr`, with the write it comes from.
+    fn read_with_source(&self, addr: SymbolId, thread_id: usize) -> (Value, Option<NodeId>) {
+        let value = self.memory_subsystem.load(addr, thread_id);
+        (
+            value,
+            self.memory_subsystem.last_write(addr, thread_id).cloned(),
+        )
+    }
+
+    fn observe(&mut self, node_id: NodeId, read: (Value, Option<NodeId>)) {
+        self.observed.entry(node_id).or_default().push(read);
+    }
+
+    pub fn final_state(&self) -> FinalState {
+        FinalState::new(&self.memory_subsystem.memory, &self.registers)
+    }
+
+    /// Explores every interleaving of instructions and propagates and collects
+    /// the distinct final states. Executions longer than `step_limit` steps
+    /// are abandoned and contribute no outcome, those failing at runtime are
+    /// counted by error.
+    ///
+    /// Interleavings that only differ in the order of independent steps meet
+    /// in the same state, which is explored once: a state is skipped when it
+    /// was already explored with at least as many steps left.
+    pub fn explore_all(&self, step_limit: usize) -> Exploration<HashSet<FinalState>> {
+        exploration::explore_all(self, step_limit)
+    }
+
+    /// Like `explore_all`, but also counts how many interleavings reach
+    /// each final state. A state reached again with as many steps left isn't
+    /// explored again: what was counted from it is added once more, so the
+    /// counts and errors are those of every interleaving.
+    pub fn explore_counts(&self, step_limit: usize) -> Exploration<HashMap<FinalState, usize>> {
+        exploration::explore_counts(self, step_limit)
+    }
+
+    /// Like `explore_counts`, but explores on `jobs` worker threads. The
+    /// first steps are explored here, until there are at least `jobs`
+    /// executions to continue. The engine can't leave its thread, so each
+    /// execution is handed to a worker as a snapshot. A worker only skips
+    /// the states it explored itself.
+    pub fn explore_counts_parallel(
+        &self,
+        step_limit: usize,
+        jobs: usize,
+    ) -> Exploration<HashMap<FinalState, usize>> {
+        exploration::explore_counts_parallel(self, step_limit, jobs)
+    }
+
+    /// Explores executions like `explore_all`, skipping the same states, but
+    /// stops at the first final state accepted by `predicate` and returns it.
+    pub fn find_final_state(
+        &self,
+        step_limit: usize,
+        predicate: &dyn Fn(&FinalState) -> bool,
+    ) -> Exploration<Option<FinalState>> {
+        exploration::find_final_state(self, step_limit, predicate)
+    }
+
+    /// Runs the execution to completion, letting the policy pick every step.
+    /// Returns the final state, or `None` if the policy stopped early or the
+    /// execution didn't finish within `step_limit` steps.
+    pub fn run(&mut self, step_limit: usize) -> Option<FinalState> {
+        for _ in 0..=step_limit {
+            let options = self.get_instructions_to_exec();
+            if options.is_empty() {
+                return Some(self.final_state());
+            }
+            let option = options.get(self.policy.choose(&options)?)?.clone();
+            self.exec_instruction(option).ok()?;
+        }
+        None
+    }
+
+    /// Executes options picked by the policy until the instruction at the
+    /// given thread and line is executed, pausing before a step writing one
+    /// of the `breakpoints` addresses. The first step never pauses, so running
+    /// again from a breakpoint makes progress.
+    pub fn run_to(
+        &mut self,
+        thread_id: usize,
+        line_index: usize,
+        breakpoints: &HashSet<String>,
+        step_limit: usize,
+    ) -> RunToResult {
+        let breakpoints: HashSet<SymbolId> = breakpoints
+            .iter()
+            .map(|addr| addr.as_str().into())
+            .collect();
+        for steps in 0..step_limit {
+            let options = self.get_instructions_to_exec();
+            let target = options.iter().position(|option| {
+                matches!(&RefCell::borrow(option).instruction, NodeType::Instruction(instruction)
+                    if instruction.thread_id == thread_id && instruction.line_index == line_index)
+            });
+            let option = match target.or_else(|| self.policy.choose(&options)) {
+                Some(index) if index < options.len() => options[index].clone(),
+                _ => return RunToResult::Unreachable(steps),
+            };
+            if let Some(Reference::Memory(addr)) =
+                RefCell::borrow(&option).instruction.write_location()
+            {
+                if steps > 0 && breakpoints.contains(addr) {
+                    return RunToResult::Breakpoint(steps, addr.to_string());
+                }
+            }
+            if self.exec_instruction(option).is_err() {
+                return RunToResult::Unreachable(steps);
+            }
+            if target.is_some() {
+                return RunToResult::Reached(steps + 1);
+            }
+        }
+        RunToResult::Unreachable(step_limit)
+    }
+
+    /// Computes what executing `node` would change, leaving the engine
+    /// untouched.
+    pub fn preview(
+        &self,
+        node: Rc<RefCell<InstructionNode>>,
+    ) -> Result<StepEffect, ExecutionError> {
+        self.dependency_graph.check_removable(&node)?;
+        let index = self
+            .get_instructions_to_exec()
+            .iter()
+            .position(|option| Rc::ptr_eq(option, &node))
+            .unwrap();
+        let mut next = self.clone();
+        let option = next.get_instructions_to_exec()[index].clone();
+        next.exec_instruction(option)?;
+
+        let mut effect = StepEffect::between(&self.final_state(), &next.final_state());
+        for thread_id in 0..self.registers.registers.len() {
+            let before = self.memory_subsystem.pending_writes(thread_id);
+            let after = next.memory_subsystem.pending_writes(thread_id);
+            if after.len() > before.len() {
+                let (addr, value) = after.last().unwrap().clone();
+                effect.buffered.push((thread_id, addr, value));
+            }
+        }
+        Ok(effect)
+    }
+
+    /// Explains `node` for the `explain` command: what it does, the
+    /// locations it reads and writes, what executing it would change, and
+    /// why it may run now or what it still waits for.
+    pub fn explain(&self, node: Rc<RefCell<InstructionNode>>) -> String {
+        let (instruction, waits_for) = {
+            let node = RefCell::borrow(&node);
+            let waits_for = node
+                .depends_on
+                .iter()
+                .map(|dependency| RefCell::borrow(dependency).instruction.to_string())
+                .collect::<Vec<_>>();
+            (node.instruction.clone(), waits_for)
+        };
+        let (step, semantics, accesses, leaf) = match &instruction {
+            NodeType::Instruction(instruction) => (
+                instruction.to_string(),
+                instruction.instruction.semantics(),
+                accesses(&instruction.instruction, true),
+                "it waits for nothing, as every step its access mode, a fence or an \
+                 access to the same address orders it after has executed.",
+            ),
+            NodeType::Propagate(propagate) => (
+                format!(
+                    "Thread {}, line {}: propagate {}",
+                    propagate.associated_write.thread_id,
+                    propagate.associated_write.line_index,
+                    propagate.to_location
+                ),
+                format!(
+                    "Moves the oldest buffered write of thread {} to {} into the shared memory, making it visible to every thread.",
+                    propagate.associated_write.thread_id, propagate.to_location
+                ),
+                [
+                    vec![format!(
+                        "the store buffer of thread {}",
+                        propagate.associated_write.thread_id
+                    )],
+                    vec![propagate.to_location.to_string()],
+                ],
+                "it waits for nothing, as the writes buffered before it (to the same \
+                 address, under PSO) have been propagated.",
+            ),
+        };
+        let allowed = match waits_for.is_empty() {
+            true => format!("yes, {}", leaf),
+            false => format!("no, it waits for: {}", waits_for.join("; ")),
+        };
+        explanation(&step, &semantics, accesses, self.preview(node), &allowed)
+    }
+
+    /// Program lines already removed from the dependency graph, out of all
+    /// program lines. Propagates are not counted; a jump back to a label puts
+    /// the reverted lines back into the graph.
+    pub fn progress(&self) -> (usize, usize) {
+        let lines = self.programs.iter().flatten();
+        let pending = lines
+            .clone()
+            .filter(|instruction| {
+                self.dependency_graph
+                    .find_instruction(instruction.thread_id, instruction.line_index)
+                    .is_some()
+            })
+            .count();
+        let total = lines.count();
+        (total - pending, total)
+    }
+
+    /// Programs of all threads, one instruction per line, with `[x]` marking
+    /// the instructions already removed from the dependency graph.
+    pub fn listing(&self) -> String {
+        self.programs
+            .iter()
+            .flatten()
+            .map(|instruction| {
+                let pending = self
+                    .dependency_graph
+                    .find_instruction(instruction.thread_id, instruction.line_index)
+                    .is_some();
+                let marker = if pending { "[ ]" } else { "[x]" };
+                format!("{} {}\n", marker, instruction)
+            })
+            .collect()
+    }
+
+    /// Remaining store buffer contents of every thread, followed by a warning
+    /// if some writes were never propagated.
+    pub fn buffer_summary(&self) -> String {
+        let mut summary = String::from("Store buffers:\n");
+        let mut unpropagated = false;
+        for thread_id in 0..self.programs.len() {
+            let buffer = self.memory_subsystem.buffers.get(&thread_id);
+            unpropagated |= buffer.is_some_and(|buffer| !buffer.is_empty());
+            let contents = buffer.map_or("empty".to_string(), |buffer| buffer.to_string());
+            summary += &format!("  Thread {}: {}\n", thread_id, contents);
+        }
+        if unpropagated {
+            summary +=
+                "Warning: some writes were never propagated and are invisible to other threads\n";
+        }
+        summary
+    }
+
+    /// Nodes the pending instruction at the given thread and line waits for,
+    /// or `None` if there is no such pending instruction.
+    pub fn blocked_by(&self, thread_id: usize, line_index: usize) -> Option<Vec<NodeType>> {
+        let node = self
+            .dependency_graph
+            .find_instruction(thread_id, line_index)?;
+        let dependencies = RefCell::borrow(&node)
+            .depends_on
+            .iter()
+            .map(|dependency| RefCell::borrow(dependency).instruction.clone())
+            .collect();
+        Some(dependencies)
+    }
+
+    /// Saves the graph in the `dot` format, with a legend if `legend`, and
+    /// tries to render it to a PNG next to it.
+    pub fn save_graph(&self, filename: &str, legend: bool) -> std::io::Result<()> {
+        let file_content = self.dependency_graph.to_dot(legend);
+        let mut file = File::create(filename)?;
+        file.write_all(file_content.as_bytes())?;
 
         let _ = Command::new("dot")
             .arg("-Tpng")
@@ -91,15 +1103,17 @@ impl TSO {
             .arg("-o")
             .arg(filename.replace(".dot", ".png"))
             .output();
+        Ok(())
+    }
+
+    pub fn save_mermaid(&self, filename: &str) -> std::io::Result<()> {
+        let file_content = self.dependency_graph.to_mermaid();
+        let mut file = File::create(filename)?;
+        file.write_all(file_content.as_bytes())
     }
 
     pub fn prepare_for_delete(&mut self, node: Rc<RefCell<InstructionNode>>) {
-        let label = match node.borrow_mut().instruction.clone() {
-            NodeType::Instruction(labeled_instruction) => labeled_instruction.label.clone(),
-            NodeType::Propagate(Propagate {
-                associated_write, ..
-            }) => associated_write.label.clone(),
-        };
+        let label = node.borrow().instruction.label();
         self.remove_queue.push(node);
         if let Some(label) = label {
             self.label_map.insert(label, true);
@@ -110,7 +1124,15 @@ impl TSO {
         if self.label_map.contains_key(&label) && self.label_map[&label] {
             while let Some(node) = self.remove_queue.pop() {
                 let instruction: NodeType = node.borrow_mut().instruction.clone();
-                self.dependency_graph.add_some_node(instruction.clone());
+                match &instruction {
+                    NodeType::Instruction(instruction) => {
+                        self.dependency_graph
+                            .add_node_with_deps(instruction.clone());
+                    }
+                    NodeType::Propagate(_) => {
+                        self.dependency_graph.add_some_node(instruction.clone())
+                    }
+                }
 
                 if let Some(inactive_label) = instruction.label() {
                     self.label_map.insert(inactive_label.clone(), false);
@@ -119,172 +1141,530 @@ impl TSO {
                     }
                 }
             }
-            self.dependency_graph.build_dependencies();
         }
     }
 
-    pub fn exec_instruction(&mut self, instruction_node: Rc<RefCell<InstructionNode>>) {
-        let instruction: NodeType = instruction_node.borrow_mut().instruction.clone();
-        let thread_id = match instruction.borrow() {
-            NodeType::Propagate(Propagate {
-                associated_write, ..
-            }) => associated_write.thread_id,
-            NodeType::Instruction(labeled_instruction) => labeled_instruction.thread_id,
-        };
-        match instruction.clone() {
-            NodeType::Propagate(Propagate { .. }) => {
-                self.memory_subsystem.propagate(thread_id);
-                self.prepare_for_delete(instruction_node.clone());
-                self.dependency_graph
-                    .remove_node(instruction_node.clone(), None, self.is_pso);
+    /// Executes a conditional jump. A backward jump reverts to its label, so
+    /// the lines from the label on run again. A forward jump is removed with
+    /// the pending lines it skips, which then never run; a skipped line that
+    /// already ran before the jump keeps its effect, as nothing orders it
+    /// after the jump.
+    fn exec_jump(
+        &mut self,
+        node: Rc<RefCell<InstructionNode>>,
+        jump: &LabeledInstruction,
+        label: &str,
+        taken: bool,
+    ) -> Result<(), ExecutionError> {
+        let target = match taken {
+            true => {
+                let program = &self.programs[jump.thread_id];
+                Some(program[label_index(program, jump, label)?].line_index)
             }
-            NodeType::Instruction(labeled_instruction) => match labeled_instruction
-                .instruction
-                .clone()
-            {
-                Instruction::AssignConst(Reference::Register(reg), value) => {
-                    self.registers.store(reg.as_str(), value, thread_id);
-                    self.prepare_for_delete(instruction_node.clone());
-                    self.dependency_graph
-                        .remove_node(instruction_node.clone(), None, self.is_pso);
+            false => None,
+        };
+        if target.is_some_and(|target| target <= jump.line_index) {
+            self.revert_to_label(label.to_string());
+            return Ok(());
+        }
+        self.prepare_for_delete(node.clone());
+        self.dependency_graph
+            .remove_node(node, Vec::new(), self.is_pso)?;
+        let Some(target) = target else {
+            return Ok(());
+        };
+        let skipped: Vec<_> = self
+            .dependency_graph
+            .nodes()
+            .iter()
+            .filter(|node| match &node.borrow().instruction {
+                NodeType::Instruction(instruction) => {
+                    instruction.thread_id == jump.thread_id
+                        && instruction.line_index > jump.line_index
+                        && instruction.line_index < target
+                }
+                NodeType::Propagate(_) => false,
+            })
+            .cloned()
+            .collect();
+        for node in skipped {
+            self.prepare_for_delete(node.clone());
+            self.dependency_graph.discard_node(node);
+        }
+        Ok(())
+    }
+
+    pub fn exec_instruction(
+        &mut self,
+        instruction_node: Rc<RefCell<InstructionNode>>,
+    ) -> Result<(), ExecutionError> {
+        let step = RefCell::borrow(&instruction_node).instruction.clone();
+        self.exec_node(instruction_node, &step)?;
+        self.trace.push(step);
+        if self.eager_propagate {
+            self.propagate_before_reads()?;
+        }
+        Ok(())
+    }
+
+    /// Executes propagates while both a propagate and a memory read are
+    /// among the options, see `with_eager_propagate`.
+    fn propagate_before_reads(&mut self) -> Result<(), ExecutionError> {
+        loop {
+            let options = self.get_instructions_to_exec();
+            let reads = options.iter().any(|option| {
+                matches!(&RefCell::borrow(option).instruction, NodeType::Instruction(instruction)
+                    if !instruction.instruction.read_locations().is_empty())
+            });
+            let propagate = options.into_iter().find(|option| {
+                matches!(RefCell::borrow(option).instruction, NodeType::Propagate(_))
+            });
+            match propagate.filter(|_| reads) {
+                Some(propagate) => {
+                    let step = RefCell::borrow(&propagate).instruction.clone();
+                    self.exec_node(propagate, &step)?;
+                    self.trace.push(step);
                 }
-                Instruction::AssignOperation(
-                    Reference::Register(reg),
-                    Reference::Register(reg1),
-                    operation,
-                    Reference::Register(reg2),
-                ) => {
-                    let value1 = self.registers.load(reg1.as_str(), thread_id);
-                    let value2 = self.registers.load(reg2.as_str(), thread_id);
-
-                    let result = operation.apply(value1, value2);
-                    self.registers.store(reg.as_str(), result, thread_id);
-                    self.prepare_for_delete(instruction_node.clone());
-                    self.dependency_graph
-                        .remove_node(instruction_node.clone(), None, self.is_pso);
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Propagates the buffered writes of the thread, oldest first, until its
+    /// buffer is empty or no propagate of the thread can run. Returns the
+    /// number of propagated writes.
+    pub fn flush_thread(&mut self, thread_id: usize) -> usize {
+        let mut flushed = 0;
+        while let Some(propagate) = self.get_instructions_to_exec().into_iter().find(|node| {
+            matches!(
+                &RefCell::borrow(node).instruction,
+                NodeType::Propagate(propagate) if propagate.associated_write.thread_id == thread_id
+            )
+        }) {
+            if self.exec_instruction(propagate).is_err() {
+                break;
+            }
+            flushed += 1;
+        }
+        flushed
+    }
+
+    /// Executes assignments to registers (`r1 = 1`, `r1 = r2 + r3`) that are
+    /// independent of the rest of their thread, while there are any. The
+    /// graph doesn't order instructions by the registers they use, so an
+    /// assignment qualifies only once no other pending instruction of its
+    /// thread uses a register it writes or writes one it reads, and no
+    /// pending jump could revert it. Running such an assignment early then
+    /// changes no outcome, as it touches neither memory nor store buffers.
+    /// Returns the number of executed instructions, along with the error of
+    /// the one that failed, e.g. dividing by zero, which stops the run.
+    pub fn run_register_ops(&mut self) -> (usize, Result<(), ExecutionError>) {
+        let mut executed = 0;
+        while let Some(node) = self
+            .get_instructions_to_exec()
+            .into_iter()
+            .find(|node| self.is_independent_register_op(node))
+        {
+            if let Err(err) = self.exec_instruction(node) {
+                return (executed, Err(err));
+            }
+            executed += 1;
+        }
+        (executed, Ok(()))
+    }
+
+    fn is_independent_register_op(&self, node: &Rc<RefCell<InstructionNode>>) -> bool {
+        let op = match &RefCell::borrow(node).instruction {
+            NodeType::Instruction(op) => op.clone(),
+            NodeType::Propagate(_) => return false,
+        };
+        let is_register_op = matches!(
+            op.instruction,
+            Instruction::AssignConst(..) | Instruction::AssignOperation(..)
+        ) && op
+            .instruction
+            .operands()
+            .iter()
+            .all(|operand| matches!(operand, Reference::Register(_)));
+        let writes = op.instruction.write_registers();
+        let reads = op.instruction.read_registers();
+        is_register_op
+            && self.dependency_graph.nodes().iter().all(|other| {
+                if Rc::ptr_eq(other, node) {
+                    return true;
                 }
-                Instruction::Load(_, Reference::Memory(mem), Reference::Register(reg)) => {
-                    let value = self.memory_subsystem.load(mem.as_str(), thread_id);
-                    self.registers.store(reg.as_str(), value, thread_id);
-                    self.prepare_for_delete(instruction_node.clone());
-                    self.dependency_graph
-                        .remove_node(instruction_node.clone(), None, self.is_pso);
+                match &RefCell::borrow(other).instruction {
+                    NodeType::Instruction(other) if other.thread_id == op.thread_id => {
+                        let other = &other.instruction;
+                        other.jump_target().is_none()
+                            && !other.write_registers().iter().any(|register| {
+                                writes.contains(register) || reads.contains(register)
+                            })
+                            && !other
+                                .read_registers()
+                                .iter()
+                                .any(|register| writes.contains(register))
+                    }
+                    _ => true,
                 }
-                Instruction::Store(_, Reference::Register(reg), Reference::Memory(mem)) => {
-                    let value = self.registers.load(reg.as_str(), thread_id);
-                    self.memory_subsystem.store(mem.as_str(), value, thread_id);
-                    if let Instruction::Store(_, _, mem_ref) =
-                        labeled_instruction.instruction.clone()
-                    {
-                        let prop = (labeled_instruction.clone(), mem_ref.clone());
+            })
+    }
+
+    /// The executed steps, one row each, in the column of their thread.
+    /// Propagates show in the column of the thread that issued the write.
+    pub fn trace_as_swimlane(&self) -> String {
+        let steps = self.trace.iter().map(|step| match step {
+            NodeType::Instruction(instruction) => (
+                instruction.thread_id,
+                format!("{}: {}", instruction.line_index, instruction.instruction),
+            ),
+            NodeType::Propagate(propagate) => (
+                propagate.associated_write.thread_id,
+                format!(
+                    "{}: propagate {}",
+                    propagate.associated_write.line_index, propagate.to_location
+                ),
+            ),
+        });
+        swimlane(self.programs.len(), steps)
+    }
+
+    /// Executes `instruction_node`, whose instruction is `step`. The caller
+    /// keeps `step` for the trace; executing only clones what the created
+    /// propagates need.
+    fn exec_node(
+        &mut self,
+        instruction_node: Rc<RefCell<InstructionNode>>,
+        step: &NodeType,
+    ) -> Result<(), ExecutionError> {
+        self.dependency_graph.check_removable(&instruction_node)?;
+        let thread_id = step.thread_id();
+        let node_id = || RefCell::borrow(&instruction_node).id();
+        match step {
+            NodeType::Propagate(Propagate {
+                to_location: Reference::Memory(addr),
+                ..
+            }) => {
+                self.memory_subsystem.propagate(thread_id, *addr);
+                self.prepare_for_delete(instruction_node.clone());
+                self.dependency_graph.remove_node(
+                    instruction_node.clone(),
+                    Vec::new(),
+                    self.is_pso,
+                )?;
+            }
+            NodeType::Propagate(_) => {
+                panic!("Propagate to a non-memory location");
+            }
+            NodeType::Instruction(labeled_instruction) => {
+                match &labeled_instruction.instruction {
+                    Instruction::AssignConst(Reference::Register(reg), value) => {
+                        self.registers.store(*reg, Value(*value), thread_id);
                         self.prepare_for_delete(instruction_node.clone());
                         self.dependency_graph.remove_node(
                             instruction_node.clone(),
-                            Some(prop),
+                            Vec::new(),
                             self.is_pso,
-                        );
-                    } else {
-                        panic!("Expected store instruction");
+                        )?;
                     }
-                }
-                Instruction::Cas(
-                    Reference::Register(ref1),
-                    _,
-                    Reference::Memory(addr),
-                    Reference::Register(reg3),
-                    Reference::Register(reg4),
-                ) => {
-                    let expected = self.registers.load(reg3.as_str(), thread_id);
-                    let desired_set = self.registers.load(reg4.as_str(), thread_id);
-                    let cur_value = self.memory_subsystem.load(addr.as_str(), thread_id);
-
-                    if cur_value == expected {
+                    Instruction::AssignOperation(
+                        Reference::Register(reg),
+                        Reference::Register(reg1),
+                        operation,
+                        Reference::Register(reg2),
+                    ) => {
+                        let value1 = self.registers.load(*reg1, thread_id);
+                        let value2 = self.registers.load(*reg2, thread_id);
+
+                        let result = operation
+                            .apply(value1, value2, self.arith_mode, self.sub_mode)
+                            .ok_or(ExecutionError::arithmetic(
+                                *operation,
+                                self.sub_mode,
+                                thread_id,
+                                labeled_instruction.line_index,
+                            ))?;
+                        self.registers.store(*reg, result, thread_id);
+                        self.prepare_for_delete(instruction_node.clone());
+                        self.dependency_graph.remove_node(
+                            instruction_node.clone(),
+                            Vec::new(),
+                            self.is_pso,
+                        )?;
+                    }
+                    Instruction::Load(_, Reference::Memory(mem), Reference::Register(reg)) => {
+                        let read = self.read_with_source(*mem, thread_id);
+                        let value = read.0;
+                        self.observe(node_id(), read);
+                        self.registers.store(*reg, value, thread_id);
+                        self.prepare_for_delete(instruction_node.clone());
+                        self.dependency_graph.remove_node(
+                            instruction_node.clone(),
+                            Vec::new(),
+                            self.is_pso,
+                        )?;
+                    }
+                    Instruction::Store(
+                        _,
+                        Reference::Register(reg),
+                        mem_ref @ Reference::Memory(mem),
+                    ) => {
+                        let line_index = labeled_instruction.line_index;
+                        let warning = self
+                            .registers
+                            .unassigned_store_warning(*reg, *mem, thread_id, line_index);
+                        self.warnings.extend(warning);
+                        let value = self.registers.load(*reg, thread_id);
                         self.memory_subsystem
-                            .store(addr.as_str(), desired_set, thread_id);
-                        self.registers.store(ref1.as_str(), cur_value, thread_id);
+                            .store_from(*mem, value, thread_id, Some(node_id()));
+                        let prop = (labeled_instruction.clone(), mem_ref.clone());
+                        self.prepare_for_delete(instruction_node.clone());
+                        self.dependency_graph.remove_node(
+                            instruction_node.clone(),
+                            vec![prop],
+                            self.is_pso,
+                        )?;
+                    }
+                    Instruction::Cas(
+                        Reference::Register(ref1),
+                        _,
+                        mem_ref @ Reference::Memory(addr),
+                        Reference::Register(reg3),
+                        Reference::Register(reg4),
+                    ) => {
+                        let expected = self.registers.load(*reg3, thread_id);
+                        let desired_set = self.registers.load(*reg4, thread_id);
+                        let read = self.read_with_source(*addr, thread_id);
+                        let cur_value = read.0;
+                        self.observe(node_id(), read);
+
+                        if cur_value == expected {
+                            self.memory_subsystem.store_from(
+                                *addr,
+                                desired_set,
+                                thread_id,
+                                Some(node_id()),
+                            );
+                            self.registers.store(*ref1, cur_value, thread_id);
 
-                        if let Instruction::Cas(_, _, mem_ref, _, _) =
-                            labeled_instruction.instruction.clone()
-                        {
                             let prop = (labeled_instruction.clone(), mem_ref.clone());
                             self.prepare_for_delete(instruction_node.clone());
                             self.dependency_graph.remove_node(
                                 instruction_node.clone(),
-                                Some(prop),
+                                vec![prop],
                                 self.is_pso,
-                            );
+                            )?;
                         } else {
-                            panic!("Expected cas instruction");
+                            self.registers.store(*ref1, cur_value, thread_id);
+                            self.dependency_graph.remove_node(
+                                instruction_node.clone(),
+                                Vec::new(),
+                                self.is_pso,
+                            )?;
+                        }
+                    }
+                    Instruction::Cas2(dests, _, addrs, expected, desired) => {
+                        let reads = addrs
+                            .iter()
+                            .filter_map(|addr| match addr {
+                                Reference::Memory(addr) => {
+                                    Some(self.read_with_source(*addr, thread_id))
+                                }
+                                Reference::Register(_) => None,
+                            })
+                            .collect::<Vec<_>>();
+                        let written = cas2(
+                            &mut self.registers,
+                            &mut self.memory_subsystem,
+                            [dests, addrs, expected, desired],
+                            thread_id,
+                            Some(node_id()),
+                        )
+                        .ok_or_else(|| ExecutionError::unsupported(labeled_instruction))?;
+                        for read in reads {
+                            self.observe(node_id(), read);
                         }
-                    } else {
-                        self.registers.store(ref1.as_str(), cur_value, thread_id);
+                        let props = match written {
+                            true => addrs
+                                .iter()
+                                .map(|addr| (labeled_instruction.clone(), addr.clone()))
+                                .collect(),
+                            false => Vec::new(),
+                        };
+                        self.prepare_for_delete(instruction_node.clone());
                         self.dependency_graph.remove_node(
                             instruction_node.clone(),
-                            None,
+                            props,
                             self.is_pso,
-                        );
+                        )?;
                     }
-                }
-                Instruction::Fai(
-                    Reference::Register(ref1),
-                    _,
-                    Reference::Memory(addr),
-                    Reference::Register(reg3),
-                ) => {
-                    let prior_to_increment = self.memory_subsystem.load(addr.as_str(), thread_id);
-                    let increment_by = self.registers.load(reg3.as_str(), thread_id);
-                    let new_value = prior_to_increment + increment_by;
-
-                    self.memory_subsystem
-                        .store(addr.as_str(), new_value, thread_id);
-                    self.registers
-                        .store(ref1.as_str(), prior_to_increment, thread_id);
-
-                    if let Instruction::Fai(_, _, mem_ref, _) =
-                        labeled_instruction.instruction.clone()
-                    {
+                    Instruction::Fai(
+                        Reference::Register(ref1),
+                        _,
+                        mem_ref @ Reference::Memory(addr),
+                        Reference::Register(reg3),
+                    ) => {
+                        let read = self.read_with_source(*addr, thread_id);
+                        let prior_to_increment = read.0;
+                        let increment_by = self.registers.load(*reg3, thread_id);
+                        let new_value = ArithCommand::Add
+                            .apply(prior_to_increment, increment_by, self.arith_mode, None)
+                            .ok_or(ExecutionError::arithmetic(
+                                ArithCommand::Add,
+                                None,
+                                thread_id,
+                                labeled_instruction.line_index,
+                            ))?;
+
+                        self.observe(node_id(), read);
+                        self.memory_subsystem.store_from(
+                            *addr,
+                            new_value,
+                            thread_id,
+                            Some(node_id()),
+                        );
+                        self.registers.store(*ref1, prior_to_increment, thread_id);
+
                         let prop = (labeled_instruction.clone(), mem_ref.clone());
                         self.prepare_for_delete(instruction_node.clone());
                         self.dependency_graph.remove_node(
                             instruction_node.clone(),
-                            Some(prop),
+                            vec![prop],
                             self.is_pso,
+                        )?;
+                    }
+                    Instruction::FetchMinMax(
+                        Reference::Register(dest),
+                        op,
+                        _,
+                        mem_ref @ Reference::Memory(addr),
+                        Reference::Register(operand),
+                    ) => {
+                        let read = self.read_with_source(*addr, thread_id);
+                        let old = read.0;
+                        self.observe(node_id(), read);
+                        let operand = self.registers.load(*operand, thread_id);
+                        // Written even when it stays the same, so it
+                        // propagates like any other write
+                        let new_value = op.apply(old, operand);
+                        self.memory_subsystem.store_from(
+                            *addr,
+                            new_value,
+                            thread_id,
+                            Some(node_id()),
                         );
-                    } else {
-                        panic!("Expected fai instruction");
+                        self.registers.store(*dest, old, thread_id);
+                        let prop = (labeled_instruction.clone(), mem_ref.clone());
+                        self.prepare_for_delete(instruction_node.clone());
+                        self.dependency_graph.remove_node(
+                            instruction_node.clone(),
+                            vec![prop],
+                            self.is_pso,
+                        )?;
                     }
-                }
-                Instruction::Fence(_) => {
-                    self.prepare_for_delete(instruction_node.clone());
-                    self.dependency_graph
-                        .remove_node(instruction_node.clone(), None, self.is_pso);
-                }
-                Instruction::ConditionalJump(Reference::Register(reg), label) => {
-                    let value = self.registers.load(reg.as_str(), thread_id);
-                    if value == 0 {
-                        self.revert_to_label(label);
-                    } else {
+                    Instruction::Print(operand) => {
+                        self.output.push(print_output(
+                            &self.registers,
+                            &self.memory_subsystem,
+                            operand,
+                            thread_id,
+                            labeled_instruction.line_index,
+                        ));
                         self.prepare_for_delete(instruction_node.clone());
                         self.dependency_graph.remove_node(
                             instruction_node.clone(),
-                            None,
+                            Vec::new(),
                             self.is_pso,
-                        );
+                        )?;
                     }
+                    Instruction::Fence(_, _) | Instruction::FenceAddr(_, _) | Instruction::Nop => {
+                        self.prepare_for_delete(instruction_node.clone());
+                        self.dependency_graph.remove_node(
+                            instruction_node.clone(),
+                            Vec::new(),
+                            self.is_pso,
+                        )?;
+                    }
+                    Instruction::ConditionalJump(Reference::Register(reg), label) => {
+                        let taken = !self.registers.load(*reg, thread_id).is_zero();
+                        self.exec_jump(
+                            instruction_node.clone(),
+                            labeled_instruction,
+                            label,
+                            taken,
+                        )?;
+                    }
+                    Instruction::CondJumpCmp(
+                        Reference::Register(lhs),
+                        cmp,
+                        Reference::Register(rhs),
+                        label,
+                    ) => {
+                        let lhs = self.registers.load(*lhs, thread_id);
+                        let rhs = self.registers.load(*rhs, thread_id);
+                        let taken = cmp.holds(lhs, rhs);
+                        self.exec_jump(
+                            instruction_node.clone(),
+                            labeled_instruction,
+                            label,
+                            taken,
+                        )?;
+                    }
+                    _ => return Err(ExecutionError::unsupported(labeled_instruction)),
                 }
-                _ => {
-                    panic!("Instruction not supported");
-                }
-            },
+            }
         }
+        Ok(())
     }
 }
 
+impl Explore for TSO {
+    fn options_count(&self) -> usize {
+        self.get_instructions_to_exec().len()
+    }
+
+    fn exec_option(&mut self, index: usize) -> Result<(), ExecutionError> {
+        let option = self.get_instructions_to_exec()[index].clone();
+        self.exec_instruction(option)
+    }
+
+    fn final_state(&self) -> FinalState {
+        self.final_state()
+    }
+
+    fn state_fingerprint(&self) -> String {
+        self.state_fingerprint()
+    }
+
+    fn to_snapshot(&self) -> String {
+        self.to_snapshot()
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings()
+    }
+
+    fn restore(snapshot: &str, settings: Settings) -> Self {
+        TSO::from_snapshot(snapshot)
+            .expect("Internal error: unreadable snapshot")
+            .with_settings(settings)
+    }
+}
+
+#[derive(Clone)]
 pub struct SequentialConsistency {
     pub memory_subsystem: SCMemorySubsystem,
     pub programs: Vec<Vec<LabeledInstruction>>,
     pub instruction_pointers: Vec<usize>,
     pub registers: Registers,
+    pub arith_mode: ArithMode,
+    pub sub_mode: Option<SubMode>,
+    /// Value of memory locations and registers never written.
+    pub mem_default: i64,
+    /// Executed instructions, in execution order.
+    pub trace: Vec<LabeledInstruction>,
+    warnings: Vec<String>,
+    output: Vec<String>,
 }
 
 impl SequentialConsistency {
@@ -298,9 +1678,69 @@ impl SequentialConsistency {
             programs: programs.clone(),
             instruction_pointers: vec![0; programs.len()],
             registers,
+            arith_mode: ArithMode::default(),
+            sub_mode: None,
+            mem_default: 0,
+            trace: Vec::new(),
+            warnings: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn with_arith_mode(mut self, arith_mode: ArithMode) -> Self {
+        self.arith_mode = arith_mode;
+        self
+    }
+
+    /// Overrides the `ArithMode` for subtraction.
+    pub fn with_sub_mode(mut self, sub_mode: SubMode) -> Self {
+        self.sub_mode = Some(sub_mode);
+        self
+    }
+
+    /// Warnings about suspicious steps since the last call, such as stores
+    /// of registers never assigned.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Lines output by `print` instructions since the last call.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Sets the value of memory locations and registers never written.
+    pub fn with_mem_default(mut self, mem_default: i64) -> Self {
+        self.mem_default = mem_default;
+        self.memory_subsystem.memory.set_default(Value(mem_default));
+        self.registers.set_default(Value(mem_default));
+        self
+    }
+
+    /// Returns to the initial state of the programs the engine was created
+    /// with.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.programs.clone()).with_settings(self.settings());
+    }
+
+    /// SC executes no propagates, `eager_propagate` is always off.
+    pub(crate) fn settings(&self) -> Settings {
+        Settings {
+            arith_mode: self.arith_mode,
+            sub_mode: self.sub_mode,
+            mem_default: self.mem_default,
+            eager_propagate: false,
         }
     }
 
+    pub(crate) fn with_settings(self, settings: Settings) -> Self {
+        let mut system = self
+            .with_arith_mode(settings.arith_mode)
+            .with_mem_default(settings.mem_default);
+        system.sub_mode = settings.sub_mode;
+        system
+    }
+
     pub fn get_instructions_to_exec(&self) -> Vec<LabeledInstruction> {
         let mut instructions_to_exec = Vec::new();
         for (thread_id, program) in self.programs.iter().enumerate() {
@@ -312,23 +1752,189 @@ impl SequentialConsistency {
         instructions_to_exec
     }
 
-    fn find_label_index(&self, thread_id: usize, label: &str) -> usize {
-        let program = &self.programs[thread_id];
-        for (index, instruction) in program.iter().enumerate() {
-            if let Some(labeled_label) = instruction.label.clone() {
-                if labeled_label == label {
-                    return index;
+    /// The options of `get_instructions_to_exec`, in the same order, with
+    /// their thread, line, kind and description.
+    pub fn options_detailed(&self) -> Vec<OptionInfo> {
+        self.get_instructions_to_exec()
+            .iter()
+            .map(OptionInfo::from)
+            .collect()
+    }
+
+    /// Executes the first option until the instruction at the given thread
+    /// and line is executed, pausing before a step writing one of the
+    /// `breakpoints` addresses. The first step never pauses, so running again
+    /// from a breakpoint makes progress.
+    pub fn run_to(
+        &mut self,
+        thread_id: usize,
+        line_index: usize,
+        breakpoints: &HashSet<String>,
+        step_limit: usize,
+    ) -> RunToResult {
+        let breakpoints: HashSet<SymbolId> = breakpoints
+            .iter()
+            .map(|addr| addr.as_str().into())
+            .collect();
+        for steps in 0..step_limit {
+            let options = self.get_instructions_to_exec();
+            let target = options
+                .iter()
+                .find(|option| option.thread_id == thread_id && option.line_index == line_index);
+            let option = match target.or(options.first()) {
+                Some(option) => option.clone(),
+                None => return RunToResult::Unreachable(steps),
+            };
+            if let Some(Reference::Memory(addr)) = option.instruction.write_location() {
+                if steps > 0 && breakpoints.contains(addr) {
+                    return RunToResult::Breakpoint(steps, addr.to_string());
+                }
+            }
+            if self.exec_instruction(option).is_err() {
+                return RunToResult::Unreachable(steps);
+            }
+            if target.is_some() {
+                return RunToResult::Reached(steps + 1);
+            }
+        }
+        RunToResult::Unreachable(step_limit)
+    }
+
+    /// Sets an initial value before execution starts.
+    pub fn initialize(&mut self, location: &Location, value: i64) {
+        let value = Value(value);
+        match location {
+            Location::Memory(addr) => self.memory_subsystem.memory.store(addr.as_str(), value),
+            Location::Register(Some(thread_id), reg) => {
+                self.registers.store(reg.as_str(), value, *thread_id)
+            }
+            Location::Register(None, reg) => {
+                for memory in self.registers.registers.values_mut() {
+                    memory.store(reg.as_str(), value);
                 }
             }
         }
-        panic!("Label not found");
     }
 
-    pub fn exec_instruction(&mut self, instruction: LabeledInstruction) {
+    pub fn final_state(&self) -> FinalState {
+        FinalState::new(&self.memory_subsystem.memory, &self.registers)
+    }
+
+    /// Explores every interleaving of the threads and collects the distinct
+    /// final states. Executions longer than `step_limit` steps are abandoned
+    /// and contribute no outcome. Like `TSO::explore_all`, states reached by
+    /// several interleavings are explored once.
+    pub fn explore_all(&self, step_limit: usize) -> Exploration<HashSet<FinalState>> {
+        exploration::explore_all(self, step_limit)
+    }
+
+    /// Like `explore_all`, but also counts how many interleavings reach
+    /// each final state, see `TSO::explore_counts`.
+    pub fn explore_counts(&self, step_limit: usize) -> Exploration<HashMap<FinalState, usize>> {
+        exploration::explore_counts(self, step_limit)
+    }
+
+    /// Like `explore_counts`, but explores on `jobs` worker threads, see
+    /// `TSO::explore_counts_parallel`.
+    pub fn explore_counts_parallel(
+        &self,
+        step_limit: usize,
+        jobs: usize,
+    ) -> Exploration<HashMap<FinalState, usize>> {
+        exploration::explore_counts_parallel(self, step_limit, jobs)
+    }
+
+    /// Explores executions like `explore_all`, skipping the same states, but
+    /// stops at the first final state accepted by `predicate` and returns it.
+    pub fn find_final_state(
+        &self,
+        step_limit: usize,
+        predicate: &dyn Fn(&FinalState) -> bool,
+    ) -> Exploration<Option<FinalState>> {
+        exploration::find_final_state(self, step_limit, predicate)
+    }
+
+    /// Program lines before the instruction pointer of each thread, out of
+    /// all program lines. A jump back moves the progress back.
+    pub fn progress(&self) -> (usize, usize) {
+        let executed = self
+            .instruction_pointers
+            .iter()
+            .zip(&self.programs)
+            .map(|(pointer, program)| (*pointer).min(program.len()))
+            .sum();
+        (executed, self.programs.iter().map(Vec::len).sum())
+    }
+
+    /// Programs of all threads, one instruction per line, with `->` marking
+    /// the instruction each thread executes next.
+    pub fn listing(&self) -> String {
+        self.programs
+            .iter()
+            .flatten()
+            .map(|instruction| {
+                let is_next =
+                    self.instruction_pointers[instruction.thread_id] == instruction.line_index;
+                let marker = if is_next { "->" } else { "  " };
+                format!("{} {}\n", marker, instruction)
+            })
+            .collect()
+    }
+
+    /// Computes what executing `instruction` would change, leaving the engine
+    /// untouched.
+    pub fn preview(&self, instruction: LabeledInstruction) -> Result<StepEffect, ExecutionError> {
+        let mut next = self.clone();
+        next.exec_instruction(instruction)?;
+        Ok(StepEffect::between(
+            &self.final_state(),
+            &next.final_state(),
+        ))
+    }
+
+    /// Explains `instruction` for the `explain` command: what it does, the
+    /// locations it reads and writes, what executing it would change, and
+    /// why it may run now.
+    pub fn explain(&self, instruction: LabeledInstruction) -> String {
+        let allowed = format!(
+            "yes, it is the next instruction of thread {}, and sequential consistency lets any thread take its next step.",
+            instruction.thread_id
+        );
+        explanation(
+            &instruction.to_string(),
+            &instruction.instruction.semantics(),
+            accesses(&instruction.instruction, false),
+            self.preview(instruction.clone()),
+            &allowed,
+        )
+    }
+
+    pub fn exec_instruction(
+        &mut self,
+        instruction: LabeledInstruction,
+    ) -> Result<(), ExecutionError> {
+        self.exec_labeled(instruction.clone())?;
+        self.trace.push(instruction);
+        Ok(())
+    }
+
+    /// The executed instructions, one row each, in the column of their
+    /// thread.
+    pub fn trace_as_swimlane(&self) -> String {
+        let steps = self.trace.iter().map(|instruction| {
+            (
+                instruction.thread_id,
+                format!("{}: {}", instruction.line_index, instruction.instruction),
+            )
+        });
+        swimlane(self.programs.len(), steps)
+    }
+
+    fn exec_labeled(&mut self, instruction: LabeledInstruction) -> Result<(), ExecutionError> {
         let thread_id = instruction.thread_id;
-        match instruction.instruction {
+        match instruction.instruction.clone() {
             Instruction::AssignConst(Reference::Register(reg), value) => {
-                self.registers.store(reg.as_str(), value, thread_id);
+                self.registers.store(reg, Value(value), thread_id);
                 self.instruction_pointers[thread_id] += 1;
             }
             Instruction::AssignOperation(
@@ -337,21 +1943,35 @@ impl SequentialConsistency {
                 operation,
                 Reference::Register(reg2),
             ) => {
-                let value1 = self.registers.load(reg1.as_str(), thread_id);
-                let value2 = self.registers.load(reg2.as_str(), thread_id);
+                let value1 = self.registers.load(reg1, thread_id);
+                let value2 = self.registers.load(reg2, thread_id);
 
-                let result = operation.apply(value1, value2);
-                self.registers.store(reg.as_str(), result, thread_id);
+                let result = operation
+                    .apply(value1, value2, self.arith_mode, self.sub_mode)
+                    .ok_or(ExecutionError::arithmetic(
+                        operation,
+                        self.sub_mode,
+                        thread_id,
+                        instruction.line_index,
+                    ))?;
+                self.registers.store(reg, result, thread_id);
                 self.instruction_pointers[thread_id] += 1;
             }
             Instruction::Load(_, Reference::Memory(mem), Reference::Register(reg)) => {
-                let value = self.memory_subsystem.load(mem.as_str(), thread_id);
-                self.registers.store(reg.as_str(), value, thread_id);
+                let value = self.memory_subsystem.load(mem, thread_id);
+                self.registers.store(reg, value, thread_id);
                 self.instruction_pointers[thread_id] += 1;
             }
             Instruction::Store(_, Reference::Register(reg), Reference::Memory(mem)) => {
-                let value = self.registers.load(reg.as_str(), thread_id);
-                self.memory_subsystem.store(mem.as_str(), value, thread_id);
+                let warning = self.registers.unassigned_store_warning(
+                    reg,
+                    mem,
+                    thread_id,
+                    instruction.line_index,
+                );
+                self.warnings.extend(warning);
+                let value = self.registers.load(reg, thread_id);
+                self.memory_subsystem.store(mem, value, thread_id);
                 self.instruction_pointers[thread_id] += 1;
             }
             Instruction::Cas(
@@ -361,50 +1981,136 @@ impl SequentialConsistency {
                 Reference::Register(reg3),
                 Reference::Register(reg4),
             ) => {
-                let expected = self.registers.load(reg3.as_str(), thread_id);
-                let desired_set = self.registers.load(reg4.as_str(), thread_id);
-                let cur_value = self.memory_subsystem.load(addr.as_str(), thread_id);
+                let expected = self.registers.load(reg3, thread_id);
+                let desired_set = self.registers.load(reg4, thread_id);
+                let cur_value = self.memory_subsystem.load(addr, thread_id);
 
                 if cur_value == expected {
-                    self.memory_subsystem
-                        .store(addr.as_str(), desired_set, thread_id);
-                    self.registers.store(ref1.as_str(), cur_value, thread_id);
+                    self.memory_subsystem.store(addr, desired_set, thread_id);
+                    self.registers.store(ref1, cur_value, thread_id);
                 } else {
-                    self.registers.store(ref1.as_str(), cur_value, thread_id);
+                    self.registers.store(ref1, cur_value, thread_id);
                 }
                 self.instruction_pointers[thread_id] += 1;
             }
+            Instruction::Cas2(dests, _, addrs, expected, desired) => {
+                cas2(
+                    &mut self.registers,
+                    &mut self.memory_subsystem,
+                    [&dests, &addrs, &expected, &desired],
+                    thread_id,
+                    None,
+                )
+                .ok_or_else(|| ExecutionError::unsupported(&instruction))?;
+                self.instruction_pointers[thread_id] += 1;
+            }
             Instruction::Fai(
                 Reference::Register(ref1),
                 _,
                 Reference::Memory(addr),
                 Reference::Register(reg3),
             ) => {
-                let prior_to_increment = self.memory_subsystem.load(addr.as_str(), thread_id);
-                let increment_by = self.registers.load(reg3.as_str(), thread_id);
-                let new_value = prior_to_increment + increment_by;
-
-                self.memory_subsystem
-                    .store(addr.as_str(), new_value, thread_id);
-                self.registers
-                    .store(ref1.as_str(), prior_to_increment, thread_id);
+                let prior_to_increment = self.memory_subsystem.load(addr, thread_id);
+                let increment_by = self.registers.load(reg3, thread_id);
+                let new_value = ArithCommand::Add
+                    .apply(prior_to_increment, increment_by, self.arith_mode, None)
+                    .ok_or(ExecutionError::arithmetic(
+                        ArithCommand::Add,
+                        None,
+                        thread_id,
+                        instruction.line_index,
+                    ))?;
+
+                self.memory_subsystem.store(addr, new_value, thread_id);
+                self.registers.store(ref1, prior_to_increment, thread_id);
                 self.instruction_pointers[thread_id] += 1;
             }
-            Instruction::Fence(_) => {
+            Instruction::FetchMinMax(
+                Reference::Register(dest),
+                op,
+                _,
+                Reference::Memory(addr),
+                Reference::Register(operand),
+            ) => {
+                let old = self.memory_subsystem.load(addr, thread_id);
+                let operand = self.registers.load(operand, thread_id);
+                let new_value = op.apply(old, operand);
+                self.memory_subsystem.store(addr, new_value, thread_id);
+                self.registers.store(dest, old, thread_id);
+                self.instruction_pointers[thread_id] += 1;
+            }
+            Instruction::Print(operand) => {
+                self.output.push(print_output(
+                    &self.registers,
+                    &self.memory_subsystem,
+                    &operand,
+                    thread_id,
+                    instruction.line_index,
+                ));
+                self.instruction_pointers[thread_id] += 1;
+            }
+            Instruction::Fence(_, _) | Instruction::FenceAddr(_, _) | Instruction::Nop => {
                 self.instruction_pointers[thread_id] += 1;
             }
             Instruction::ConditionalJump(Reference::Register(reg), label) => {
-                let value = self.registers.load(reg.as_str(), thread_id);
-                if value != 0 {
-                    let label_index = self.find_label_index(thread_id, label.as_str());
-                    self.instruction_pointers[thread_id] = label_index;
+                let value = self.registers.load(reg, thread_id);
+                if !value.is_zero() {
+                    self.instruction_pointers[thread_id] =
+                        label_index(&self.programs[thread_id], &instruction, &label)?;
                 } else {
                     self.instruction_pointers[thread_id] += 1;
                 }
             }
-            _ => {
-                panic!("Instruction not supported");
+            Instruction::CondJumpCmp(
+                Reference::Register(lhs),
+                cmp,
+                Reference::Register(rhs),
+                label,
+            ) => {
+                let lhs = self.registers.load(lhs, thread_id);
+                let rhs = self.registers.load(rhs, thread_id);
+                if cmp.holds(lhs, rhs) {
+                    self.instruction_pointers[thread_id] =
+                        label_index(&self.programs[thread_id], &instruction, &label)?;
+                } else {
+                    self.instruction_pointers[thread_id] += 1;
+                }
             }
+            _ => return Err(ExecutionError::unsupported(&instruction)),
         }
+        Ok(())
+    }
+}
+
+impl Explore for SequentialConsistency {
+    fn options_count(&self) -> usize {
+        self.get_instructions_to_exec().len()
+    }
+
+    fn exec_option(&mut self, index: usize) -> Result<(), ExecutionError> {
+        let option = self.get_instructions_to_exec().swap_remove(index);
+        self.exec_instruction(option)
+    }
+
+    fn final_state(&self) -> FinalState {
+        self.final_state()
+    }
+
+    fn state_fingerprint(&self) -> String {
+        self.state_fingerprint()
+    }
+
+    fn to_snapshot(&self) -> String {
+        self.to_snapshot()
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings()
+    }
+
+    fn restore(snapshot: &str, settings: Settings) -> Self {
+        SequentialConsistency::from_snapshot(snapshot)
+            .expect("Internal error: unreadable snapshot")
+            .with_settings(settings)
     }
 }
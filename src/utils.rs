@@ -1,33 +1,483 @@
-use crate::instruction::LabeledInstruction;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-
-fn parse_program(file_path: String, thread_id: usize) -> Vec<LabeledInstruction> {
-    let file = File::open(file_path.clone()).unwrap();
-    let reader = BufReader::new(file);
-    let mut program = Vec::new();
-    for line in reader.lines() {
-        if let Ok(instruction) = line {
-            let instruction: String = instruction.trim().to_string();
-            if instruction.is_empty() {
+use crate::instruction::{Command, Error, LabeledInstruction, ParseOptions, Reference};
+use crate::thread_subsystem::Location;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+
+fn try_push_instruction(
+    program: &mut Vec<LabeledInstruction>,
+    instruction: &str,
+    thread_id: usize,
+    file: Option<&str>,
+    line: usize,
+    options: ParseOptions,
+) -> Result<(), Error> {
+    let labeled_instruction =
+        LabeledInstruction::from_line_with(instruction, program.len(), thread_id, options)
+            .map_err(|cause| Error::InvalidLine {
+                file: file.map(str::to_string),
+                line,
+                thread_id,
+                text: instruction.to_string(),
+                cause: Box::new(cause),
+            })?;
+    check_label_unique(program, &labeled_instruction)?;
+    program.push(labeled_instruction);
+    Ok(())
+}
+
+/// A jump goes to the first line with its label, so a label may be defined
+/// only once per thread.
+pub(crate) fn check_label_unique(
+    program: &[LabeledInstruction],
+    instruction: &LabeledInstruction,
+) -> Result<(), Error> {
+    match &instruction.label {
+        Some(label) if label_index(program, label).is_some() => Err(Error::DuplicateLabel {
+            thread_id: instruction.thread_id,
+            label: label.clone(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn push_instruction(
+    program: &mut Vec<LabeledInstruction>,
+    instruction: &str,
+    thread_id: usize,
+    file_path: &str,
+    line: usize,
+    options: ParseOptions,
+) {
+    try_push_instruction(
+        program,
+        instruction,
+        thread_id,
+        Some(file_path),
+        line,
+        options,
+    )
+    .unwrap_or_else(|err| panic!("{}", err));
+}
+
+/// Non-empty trimmed lines with their 1-based line numbers. Lines disabled
+/// with a leading `!` are skipped but keep their line number, so errors
+/// still point at the right line of the file.
+fn numbered_lines(src: &str) -> impl Iterator<Item = (usize, &str)> {
+    src.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('!'))
+}
+
+/// An empty program with room for every instruction of `src`, so that
+/// parsing a long program doesn't grow it step by step.
+fn program_for(src: &str) -> Vec<LabeledInstruction> {
+    Vec::with_capacity(numbered_lines(src).count())
+}
+
+fn parse_program(
+    file_path: String,
+    thread_id: usize,
+    options: ParseOptions,
+) -> Vec<LabeledInstruction> {
+    let src = fs::read_to_string(&file_path).unwrap();
+    let mut program = program_for(&src);
+    for (line, instruction) in numbered_lines(&src) {
+        push_instruction(
+            &mut program,
+            instruction,
+            thread_id,
+            &file_path,
+            line,
+            options,
+        );
+    }
+    program
+}
+
+/// Parses the program of a single thread from a string, one instruction per
+/// line. Blank and disabled lines are skipped and don't count towards line
+/// indices.
+pub fn parse_program_str(src: &str, thread_id: usize) -> Result<Vec<LabeledInstruction>, Error> {
+    ParseOptions::default().parse_program_str(src, thread_id)
+}
+
+/// Parses one program per source, the thread id being the source's index.
+/// The programs must be within the default `Limits`.
+pub fn parse_programs_str(sources: &[&str]) -> Result<Vec<Vec<LabeledInstruction>>, Error> {
+    ParseOptions::default().parse_programs_str(sources)
+}
+
+/// The parsers of this module, with other options than the default ones.
+impl ParseOptions {
+    /// See `parse_program_str`.
+    pub fn parse_program_str(
+        self,
+        src: &str,
+        thread_id: usize,
+    ) -> Result<Vec<LabeledInstruction>, Error> {
+        let mut program = program_for(src);
+        for (line, instruction) in numbered_lines(src) {
+            try_push_instruction(&mut program, instruction, thread_id, None, line, self)?;
+        }
+        Ok(program)
+    }
+
+    /// See `parse_programs_str`.
+    pub fn parse_programs_str(
+        self,
+        sources: &[&str],
+    ) -> Result<Vec<Vec<LabeledInstruction>>, Error> {
+        Limits::default().check_threads(sources.len())?;
+        let programs = sources
+            .iter()
+            .enumerate()
+            .map(|(thread_id, src)| self.parse_program_str(src, thread_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        Limits::default().check(&programs)?;
+        Ok(programs)
+    }
+
+    /// See `programs_to_instructions`.
+    pub fn programs_to_instructions(self, file_paths: Vec<String>) -> Vec<Vec<LabeledInstruction>> {
+        let mut programs: Vec<Vec<LabeledInstruction>> = Vec::new();
+        for (thread_id, file_path) in file_paths.iter().enumerate() {
+            let parsed = file_paths[..thread_id]
+                .iter()
+                .position(|other| other == file_path);
+            let program = match parsed {
+                Some(other_thread) => for_thread(&programs[other_thread], thread_id),
+                None => parse_program(file_path.to_string(), thread_id, self),
+            };
+            programs.push(program);
+        }
+        Limits::default()
+            .check(&programs)
+            .unwrap_or_else(|err| panic!("{}", err));
+        programs
+    }
+
+    /// See `mapped_programs_to_instructions`.
+    pub fn mapped_programs_to_instructions(
+        self,
+        mapping: Vec<(usize, String)>,
+    ) -> Vec<Vec<LabeledInstruction>> {
+        let thread_count = mapping.iter().map(|(thread_id, _)| thread_id + 1).max();
+        let thread_count = thread_count.unwrap_or(0);
+        // Checked before allocating, as the ids come from the input
+        Limits::default()
+            .check_threads(thread_count)
+            .unwrap_or_else(|err| panic!("{}", err));
+        let mut programs = vec![Vec::new(); thread_count];
+        for (index, (thread_id, file_path)) in mapping.iter().enumerate() {
+            let parsed = mapping[..index]
+                .iter()
+                .find(|(_, other)| other == file_path);
+            programs[*thread_id] = match parsed {
+                Some((other_thread, _)) => for_thread(&programs[*other_thread], *thread_id),
+                None => parse_program(file_path.to_string(), *thread_id, self),
+            };
+        }
+        Limits::default()
+            .check(&programs)
+            .unwrap_or_else(|err| panic!("{}", err));
+        programs
+    }
+
+    /// See `single_file_to_instructions`.
+    pub fn single_file_to_instructions(self, file_path: String) -> Vec<Vec<LabeledInstruction>> {
+        let src = fs::read_to_string(&file_path).unwrap();
+        self.parse_single_file_str(&src, Some(&file_path))
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// See `parse_single_file_str`.
+    pub fn parse_single_file_str(
+        self,
+        src: &str,
+        file: Option<&str>,
+    ) -> Result<Vec<Vec<LabeledInstruction>>, Error> {
+        let mut programs: Vec<Vec<LabeledInstruction>> = Vec::new();
+        for (line_number, line) in numbered_lines(src) {
+            if is_thread_separator(line) {
+                programs.push(Vec::new());
                 continue;
             }
-            let parsed: LabeledInstruction = instruction
-                .parse::<LabeledInstruction>()
-                .expect(format!("Invalid instruction found in {}", file_path).as_str());
-            let labeled_instruction =
-                LabeledInstruction::new(parsed.label, parsed.instruction, program.len(), thread_id);
-            program.push(labeled_instruction);
+            if programs.is_empty() {
+                programs.push(Vec::new());
+            }
+            let thread_id = programs.len() - 1;
+            try_push_instruction(
+                programs.last_mut().unwrap(),
+                line,
+                thread_id,
+                file,
+                line_number,
+                self,
+            )?;
         }
+        Limits::default().check(&programs)?;
+        Ok(programs)
     }
+}
+
+/// A line starting a new thread in a single-file program: `---`, `thread:`
+/// or `thread <n>:`.
+fn is_thread_separator(line: &str) -> bool {
+    line == "---"
+        || line
+            .strip_prefix("thread")
+            .and_then(|rest| rest.strip_suffix(':'))
+            .is_some_and(|id| id.trim().chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Errors of every line of the source that fails to parse, in line order,
+/// naming `file` as their source. Thread separator lines are accepted, so
+/// single-file programs can be checked too.
+pub fn syntax_errors(src: &str, file: Option<&str>) -> Vec<Error> {
+    let mut programs: Vec<Vec<LabeledInstruction>> = Vec::new();
+    numbered_lines(src)
+        .filter_map(|(line, instruction)| {
+            if is_thread_separator(instruction) {
+                programs.push(Vec::new());
+                return None;
+            }
+            if programs.is_empty() {
+                programs.push(Vec::new());
+            }
+            let thread_id = programs.len() - 1;
+            let program = programs.last_mut().unwrap();
+            try_push_instruction(
+                program,
+                instruction,
+                thread_id,
+                file,
+                line,
+                ParseOptions::default(),
+            )
+            .err()
+        })
+        .collect()
+}
+
+/// Parses initial values of shared memory, one `addr value` per line, e.g.
+/// `#a0 10` or `a1 0x14`. Blank and disabled lines are skipped like in
+/// programs; any other line that isn't a location and a number is an error
+/// naming `file` and the line.
+pub fn parse_memory_init(src: &str, file: Option<&str>) -> Result<Vec<(Location, i64)>, Error> {
+    numbered_lines(src)
+        .map(|(line, text)| {
+            let value = match text.split_whitespace().collect::<Vec<_>>()[..] {
+                [addr, value] => {
+                    let addr = format!("#{}", addr.strip_prefix('#').unwrap_or(addr));
+                    match (addr.parse::<Reference>(), value.parse::<Command>()) {
+                        (Ok(Reference::Memory(addr)), Ok(Command::Number(value))) => {
+                            Some((Location::Memory(addr.to_string()), value))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            value.ok_or_else(|| Error::InvalidMemoryInit {
+                file: file.map(str::to_string),
+                line,
+                text: text.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The program of another thread, as run by `thread_id`. Labels and line
+/// indices are per thread, so only the thread id changes.
+fn for_thread(program: &[LabeledInstruction], thread_id: usize) -> Vec<LabeledInstruction> {
     program
+        .iter()
+        .map(|instruction| LabeledInstruction {
+            thread_id,
+            ..instruction.clone()
+        })
+        .collect()
 }
 
+/// Parses the program file of each thread. A file listed for several
+/// threads is read once, each thread getting its own copy of the program.
 pub fn programs_to_instructions(file_paths: Vec<String>) -> Vec<Vec<LabeledInstruction>> {
-    let mut programs = Vec::new();
-    for (thread_id, file_path) in file_paths.iter().enumerate() {
-        let program = parse_program(file_path.to_string(), thread_id);
-        programs.push(program);
+    ParseOptions::default().programs_to_instructions(file_paths)
+}
+
+/// Parses a `T0=a.txt, T1=b.txt` thread mapping into `(thread_id, path)`
+/// pairs. Each thread id may be bound only once.
+pub fn parse_thread_mapping(spec: &str) -> Result<Vec<(usize, String)>, Error> {
+    let mut mapping: Vec<(usize, String)> = Vec::new();
+    for entry in spec.split(',').map(str::trim) {
+        let (thread_id, path) = entry
+            .split_once('=')
+            .and_then(|(thread, path)| {
+                let thread_id = thread.trim().strip_prefix('T')?.parse::<usize>().ok()?;
+                Some((thread_id, path.trim().to_string()))
+            })
+            .filter(|(thread_id, path)| {
+                !path.is_empty() && mapping.iter().all(|(bound, _)| bound != thread_id)
+            })
+            .ok_or_else(|| Error::InvalidCommand(entry.to_string()))?;
+        mapping.push((thread_id, path));
     }
+    Ok(mapping)
+}
+
+/// Parses every program of the mapping on its thread id. Threads left out
+/// of the mapping get an empty program.
+pub fn mapped_programs_to_instructions(
+    mapping: Vec<(usize, String)>,
+) -> Vec<Vec<LabeledInstruction>> {
+    ParseOptions::default().mapped_programs_to_instructions(mapping)
+}
+
+/// Parses a file holding the programs of all threads, separated by thread
+/// separator lines. A separator before the first instruction is optional.
+pub fn single_file_to_instructions(file_path: String) -> Vec<Vec<LabeledInstruction>> {
+    ParseOptions::default().single_file_to_instructions(file_path)
+}
+
+/// Parses the programs of all threads from a string in the single-file
+/// format, naming `file` as the source of errors. The programs must be
+/// within the default `Limits`.
+pub fn parse_single_file_str(
+    src: &str,
+    file: Option<&str>,
+) -> Result<Vec<Vec<LabeledInstruction>>, Error> {
+    ParseOptions::default().parse_single_file_str(src, file)
+}
+
+fn label_index(program: &[LabeledInstruction], label: &str) -> Option<usize> {
+    program
+        .iter()
+        .position(|instruction| instruction.label.as_deref() == Some(label))
+}
+
+/// Lines of the program control can reach from line 0, following fallthrough
+/// and jump targets. Jumps to missing labels aren't followed, see
+/// `missing_labels`.
+pub fn reachable_lines(program: &[LabeledInstruction]) -> HashSet<usize> {
+    let mut reachable = HashSet::new();
+    let mut pending = vec![0];
+    while let Some(line) = pending.pop() {
+        if line >= program.len() || !reachable.insert(line) {
+            continue;
+        }
+        let instruction = &program[line].instruction;
+        let taken = instruction.static_jump();
+        if taken != Some(false) {
+            if let Some(target) = instruction
+                .jump_target()
+                .and_then(|label| label_index(program, label))
+            {
+                pending.push(target);
+            }
+        }
+        if taken != Some(true) {
+            pending.push(line + 1);
+        }
+    }
+    reachable
+}
+
+/// Jumps of the program to labels it doesn't define, as `(line, label)`.
+pub fn missing_labels(program: &[LabeledInstruction]) -> Vec<(usize, String)> {
+    program
+        .iter()
+        .enumerate()
+        .filter_map(|(line, instruction)| {
+            let label = instruction.instruction.jump_target()?;
+            label_index(program, label)
+                .is_none()
+                .then(|| (line, label.to_string()))
+        })
+        .collect()
+}
+
+/// Names of the memory locations the programs refer to.
+pub fn referenced_memory(programs: &[Vec<LabeledInstruction>]) -> BTreeSet<String> {
     programs
+        .iter()
+        .flatten()
+        .flat_map(|instruction| instruction.instruction.operands())
+        .filter_map(|operand| match operand {
+            Reference::Memory(name) => Some(name.to_string()),
+            Reference::Register(_) => None,
+        })
+        .collect()
+}
+
+/// Registers the programs refer to, as `(thread_id, name)`.
+pub fn referenced_registers(programs: &[Vec<LabeledInstruction>]) -> BTreeSet<(usize, String)> {
+    programs
+        .iter()
+        .flatten()
+        .flat_map(|instruction| {
+            instruction
+                .instruction
+                .operands()
+                .into_iter()
+                .filter_map(|operand| match operand {
+                    Reference::Register(name) => Some((instruction.thread_id, name.to_string())),
+                    Reference::Memory(_) => None,
+                })
+        })
+        .collect()
+}
+
+/// Caps on the size of programs, for embedders bounding the resources an
+/// untrusted input may take. The parsers enforce the defaults, which are far
+/// above what hand-written programs need; stricter limits are checked with
+/// `check`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_threads: usize,
+    /// Instructions in the program of one thread.
+    pub max_program_len: usize,
+    /// Distinct memory locations over all threads.
+    pub max_addresses: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_threads: 256,
+            max_program_len: 100_000,
+            max_addresses: 65_536,
+        }
+    }
+}
+
+impl Limits {
+    /// Fails with the first limit the programs exceed.
+    pub fn check(&self, programs: &[Vec<LabeledInstruction>]) -> Result<(), Error> {
+        self.check_threads(programs.len())?;
+        if let Some(thread_id) = programs
+            .iter()
+            .position(|program| program.len() > self.max_program_len)
+        {
+            return Err(Error::ProgramTooLong {
+                thread_id,
+                max: self.max_program_len,
+            });
+        }
+        if referenced_memory(programs).len() > self.max_addresses {
+            return Err(Error::TooManyAddresses {
+                max: self.max_addresses,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_threads(&self, thread_count: usize) -> Result<(), Error> {
+        match thread_count > self.max_threads {
+            true => Err(Error::TooManyThreads {
+                max: self.max_threads,
+            }),
+            false => Ok(()),
+        }
+    }
 }
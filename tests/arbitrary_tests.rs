@@ -0,0 +1,38 @@
+#![cfg(feature = "arbitrary")]
+
+use arbitrary::{Arbitrary, Unstructured};
+use isa_interpreter::{Instruction, LabeledInstruction};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::collections::HashSet;
+use std::mem::discriminant;
+
+use pretty_assertions::assert_eq;
+
+#[test]
+fn test_arbitrary_instructions_round_trip() {
+    let mut bytes = vec![0; 1 << 16];
+    StdRng::seed_from_u64(0).fill_bytes(&mut bytes);
+    let mut u = Unstructured::new(&bytes);
+    let mut variants = HashSet::new();
+    for _ in 0..1000 {
+        let instruction = Instruction::arbitrary(&mut u).unwrap();
+        variants.insert(discriminant(&instruction));
+        let source = instruction.to_source();
+        assert_eq!(
+            instruction,
+            source.parse::<Instruction>().unwrap(),
+            "{}",
+            source
+        );
+
+        let labeled = LabeledInstruction::arbitrary(&mut u).unwrap();
+        let line = match &labeled.label {
+            Some(label) => format!("{}: {}", label, labeled.instruction.to_source()),
+            None => labeled.instruction.to_source(),
+        };
+        assert_eq!(labeled, LabeledInstruction::from_line(&line, 0, 0).unwrap());
+    }
+    // Every variant is generated, not just the ones of exhausted input
+    assert_eq!(14, variants.len());
+}
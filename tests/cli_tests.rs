@@ -0,0 +1,562 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use pretty_assertions::assert_eq;
+
+const STORE_BUFFERING: &str = "etc/program_samples/sb_0.txt, etc/program_samples/sb_1.txt";
+
+fn interpreter(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_isa_interpreter"))
+        .args(args)
+        .output()
+        .expect("Unable to run the interpreter")
+}
+
+#[test]
+fn test_check_histogram() {
+    let output = interpreter(&["check", "-m", "SC", "-p", STORE_BUFFERING]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        "Explored 20 interleavings, 3 distinct final states\n\
+         \x20   12 | #x=1, #y=1, 0:r1=1, 0:r2=1, 1:r1=1, 1:r2=1\n\
+         \x20    4 | #x=1, #y=1, 0:r1=1, 0:r2=0, 1:r1=1, 1:r2=1\n\
+         \x20    4 | #x=1, #y=1, 0:r1=1, 0:r2=1, 1:r1=1, 1:r2=0\n",
+        stdout
+    );
+}
+
+#[test]
+fn test_check_jobs() {
+    let sequential = interpreter(&["check", "-m", "TSO", "-p", STORE_BUFFERING]);
+    let parallel = interpreter(&["check", "-m", "TSO", "-p", STORE_BUFFERING, "--jobs", "4"]);
+    assert!(parallel.status.success());
+    assert_eq!(
+        String::from_utf8(sequential.stdout).unwrap(),
+        String::from_utf8(parallel.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_compare_models() {
+    let output = interpreter(&["compare", "-p", STORE_BUFFERING]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(" SC | TSO | PSO | final state", lines[1]);
+    // Both loads miss the other thread's store only with store buffers
+    assert!(lines.contains(
+        &"    |  ✓  |  ✓  | #x=1, #y=1, 0:r1=1, 0:r2=0, 1:r1=1, 1:r2=0  <- weaker models only"
+    ));
+    // Every state a model reaches, the weaker models reach too
+    for line in &lines[2..] {
+        let reached = line
+            .split('|')
+            .take(3)
+            .map(|column| column.contains('✓'))
+            .collect::<Vec<_>>();
+        assert!(reached.is_sorted(), "{}", line);
+    }
+}
+
+#[test]
+fn test_check_assert_unreachable() {
+    let args = [
+        "-p",
+        STORE_BUFFERING,
+        "--assert-unreachable",
+        "0:r2=0,1:r2=0",
+    ];
+    let sc = interpreter(&[&["check", "-m", "SC"], &args[..]].concat());
+    assert!(sc.status.success());
+
+    let tso = interpreter(&[&["check", "-m", "TSO"], &args[..]].concat());
+    assert!(!tso.status.success());
+    assert!(String::from_utf8(tso.stdout)
+        .unwrap()
+        .contains("Assertion failed, reachable state:"));
+}
+
+fn run_with_input(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_isa_interpreter"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Unable to run the interpreter");
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_run_pointers() {
+    let output = run_with_input(
+        &["run", "-m", "SC", "-p", STORE_BUFFERING],
+        b"0\n0\n0\npointers\nexit\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.ends_with(
+        "[3/6] Please select an option and input the index: \n\
+         Thread 0, finished\n\
+         Thread 1, line 0: r1 := 1\n\
+         0 | Thread 1, line 0: r1 := 1\n\
+         [3/6] Please select an option and input the index: \n"
+    ));
+}
+
+#[test]
+fn test_run_why_rejects_invalid_targets() {
+    let output = run_with_input(
+        &["run", "-m", "TSO", "-p", STORE_BUFFERING],
+        b"why x 1\nwhy 0 -1\nwhy 0\nwhy 0 1\nexit\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    let answers = stdout
+        .lines()
+        .filter(|line| !line.contains('|') && !line.starts_with('['))
+        .skip_while(|line| !line.starts_with("Usage"))
+        .take(4)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "Usage: why <thread> <line>",
+            "Usage: why <thread> <line>",
+            "Usage: why <thread> <line>",
+            "Thread 0, line 1 is not waiting for anything",
+        ],
+        answers
+    );
+}
+
+#[test]
+fn test_run_reset() {
+    let output = run_with_input(
+        &["run", "-m", "SC", "-p", STORE_BUFFERING],
+        b"0\n0\nreset\npointers\nexit\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.ends_with(
+        "Reset to the initial state\n\
+         0 | Thread 0, line 0: r1 := 1\n\
+         1 | Thread 1, line 0: r1 := 1\n\
+         [0/6] Please select an option and input the index: \n\
+         Thread 0, line 0: r1 := 1\n\
+         Thread 1, line 0: r1 := 1\n\
+         0 | Thread 0, line 0: r1 := 1\n\
+         1 | Thread 1, line 0: r1 := 1\n\
+         [0/6] Please select an option and input the index: \n"
+    ));
+}
+
+#[test]
+fn test_run_stops_at_max_steps() {
+    let output = run_with_input(
+        &[
+            "run",
+            "-m",
+            "SC",
+            "-p",
+            "etc/program_samples/infinite_loop.txt",
+            "--max-steps",
+            "5",
+        ],
+        "0\n".repeat(10).as_bytes(),
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.ends_with("Step limit reached after 5 steps\n0:r1=1, 0:r2=1\n"));
+}
+
+#[test]
+fn test_invalid_line_reports_location() {
+    let output = interpreter(&[
+        "check",
+        "-m",
+        "SC",
+        "-p",
+        "etc/program_samples/invalid_line.txt",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("etc/program_samples/invalid_line.txt:4: invalid instruction 'foo bar'"));
+}
+
+#[test]
+fn test_run_by_mnemonics() {
+    let output = run_with_input(
+        &["run", "-m", "SC", "-p", STORE_BUFFERING],
+        b"t1:0\nt1:1\nt9:9\npointers\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.contains("No matching option\n"));
+    assert!(stdout.ends_with(
+        "Thread 0, line 0: r1 := 1\n\
+         Thread 1, line 2: r2 := load RLX #x\n\
+         0 | Thread 0, line 0: r1 := 1\n\
+         1 | Thread 1, line 2: r2 := load RLX #x\n\
+         [2/6] Please select an option and input the index: \n"
+    ));
+}
+
+#[test]
+fn test_check_thread_mapping() {
+    let output = interpreter(&[
+        "check",
+        "-m",
+        "SC",
+        "--threads",
+        "T1=etc/program_samples/sb_0.txt",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        "Explored 1 interleavings, 1 distinct final states\n\
+         \x20    1 | #x=1, 1:r1=1, 1:r2=0\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_run_watch_prints_changes() {
+    let output = run_with_input(
+        &[
+            "run",
+            "-m",
+            "SC",
+            "-p",
+            "etc/program_samples/write_twice.txt",
+        ],
+        b"watch #x\n0\n0\nunwatch #y\n0\n0\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.contains("No watch on #y\n"));
+    let changes = stdout
+        .lines()
+        .filter(|line| line.starts_with("#x: "))
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["#x: 0 -> 1", "#x: 1 -> 2"], changes);
+}
+
+#[test]
+fn test_unreachable_lines_are_warned_about() {
+    let output = interpreter(&[
+        "check",
+        "-m",
+        "SC",
+        "-p",
+        "etc/program_samples/dead_block.txt",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        "Warning: thread 0, line 2 is unreachable: r2 = 5\n\
+         Warning: thread 0, line 3 is unreachable: r3 = 6\n",
+        String::from_utf8(output.stderr).unwrap()
+    );
+}
+
+#[test]
+fn test_check_mem_default() {
+    let output = interpreter(&[
+        "check",
+        "-m",
+        "SC",
+        "-p",
+        "etc/program_samples/sb_0.txt",
+        "--mem-default",
+        "4",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        "Explored 1 interleavings, 1 distinct final states\n\
+         \x20    1 | #x=1, 0:r1=1, 0:r2=4\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_assert_unreachable_with_mem_default() {
+    // The final state leaves out #y, never written, so it holds the default
+    let check = |cond: &str| {
+        interpreter(&[
+            "check",
+            "-m",
+            "SC",
+            "-p",
+            "etc/program_samples/sb_0.txt",
+            "--mem-default",
+            "4",
+            "--assert-unreachable",
+            cond,
+        ])
+    };
+    assert!(check("#y=0").status.success());
+    let reachable = check("#y=4,0:r2=4");
+    assert!(!reachable.status.success());
+    assert!(String::from_utf8(reachable.stdout)
+        .unwrap()
+        .contains("Assertion failed, reachable state: #x=1, 0:r1=1, 0:r2=4"));
+}
+
+#[test]
+fn test_check_syntax() {
+    let valid = interpreter(&["check-syntax", "etc/program_samples/sb.txt"]);
+    assert!(valid.status.success());
+    assert_eq!(
+        "etc/program_samples/sb.txt: ok\n",
+        String::from_utf8(valid.stdout).unwrap()
+    );
+
+    let invalid = interpreter(&[
+        "check-syntax",
+        "etc/program_samples/sb_0.txt",
+        "etc/program_samples/invalid_line.txt",
+    ]);
+    assert!(!invalid.status.success());
+    assert_eq!(
+        "etc/program_samples/sb_0.txt: ok\n",
+        String::from_utf8(invalid.stdout).unwrap()
+    );
+    assert_eq!(
+        "etc/program_samples/invalid_line.txt:4: invalid instruction 'foo bar'\n",
+        String::from_utf8(invalid.stderr).unwrap()
+    );
+}
+
+#[test]
+fn test_batch() {
+    let sc = interpreter(&["batch", "etc/batch", "-m", "SC"]);
+    assert!(sc.status.success());
+    assert_eq!(
+        "sb.txt        | pass | 3 final states\n\
+         sb_fenced.txt | pass | 3 final states\n\
+         2 passed, 0 failed\n",
+        String::from_utf8(sc.stdout).unwrap()
+    );
+
+    let tso = interpreter(&["batch", "etc/batch", "-m", "TSO"]);
+    assert!(!tso.status.success());
+    assert_eq!(
+        "sb.txt        | FAIL | unexpected final state #x=0, #y=0, 0:r1=1, 0:r2=0, 1:r1=1, 1:r2=0\n\
+         sb_fenced.txt | pass | 3 final states\n\
+         1 passed, 1 failed\n",
+        String::from_utf8(tso.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_run_verbosity() {
+    let run = |verbosity: &str| {
+        let output = run_with_input(
+            &[
+                "run",
+                "-m",
+                "SC",
+                "-p",
+                "etc/program_samples/write_twice.txt",
+                verbosity,
+            ],
+            b"watch #x\n0\n0\n0\n0\n",
+        );
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let quiet = run("-q");
+    assert!(!quiet.contains("#x: 0 -> 1"));
+    assert!(!quiet.contains("0:r1 := 1"));
+    assert!(quiet.ends_with("No more instructions to execute\n#x=2, 0:r1=2\n"));
+
+    let verbose = run("-v");
+    assert!(verbose.contains("0:r1 := 1\n"));
+    assert!(verbose.contains("#x := 1\n#x: 0 -> 1\n"));
+    assert!(verbose.ends_with("No more instructions to execute\n"));
+}
+
+#[test]
+fn test_check_program_from_stdin() {
+    let output = run_with_input(
+        &["check", "-m", "SC", "-p", "-"],
+        b"r1 = 1\nstore RLX r1 #x\n---\nload RLX #x r2\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        "Explored 3 interleavings, 2 distinct final states\n\
+         \x20    2 | #x=1, 0:r1=1, 1:r2=0\n\
+         \x20    1 | #x=1, 0:r1=1, 1:r2=1\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_check_reports_executions_failing_at_runtime() {
+    let output = run_with_input(
+        &["check", "-m", "SC", "-p", "-"],
+        b"r1 = 1\nstore RLX r1 #x\n---\nload RLX #x r2\nr3 = r1 / r2\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        "Explored 1 interleavings, 1 distinct final states\n\
+         \x20    1 | #x=1, 0:r1=1, 1:r2=1, 1:r3=0\n\
+         5 executions failed at runtime\n\
+         \x20    5 | Thread 1, line 1: division by zero\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_run_register_ops_prints_the_failing_step() {
+    let output = run_with_input(
+        &[
+            "run",
+            "-m",
+            "TSO",
+            "-p",
+            "etc/program_samples/division_by_zero.txt",
+        ],
+        b"run-rlx\nexit\n",
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains(
+        "Executed 1 register instructions\n\
+         Thread 0, line 1: division by zero\n\
+         0 | Thread 0, line 1: r2 := r1 / r0\n"
+    ));
+}
+
+#[test]
+fn test_run_ends_cleanly_without_exit() {
+    let output = run_with_input(&["run", "-m", "TSO", "-p", STORE_BUFFERING], b"0\n");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with("Please select an option and input the index: \nStore buffers:\n  Thread 0: empty\n  Thread 1: empty\n"));
+
+    let output = run_with_input(
+        &["run", "-m", "TSO", "-p", STORE_BUFFERING],
+        b"graph\nmermaid /nonexistent/graph.mmd\n\xff\n0\n",
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Usage: graph <path> [legend]\n"));
+    assert!(stdout.contains("Unable to save /nonexistent/graph.mmd: "));
+    assert!(stdout.contains("Unable to read input: "));
+    assert!(stdout.ends_with("Store buffers:\n  Thread 0: empty\n  Thread 1: empty\n"));
+}
+
+#[test]
+fn test_run_two_phase_annotates_buffered_values() {
+    let output = run_with_input(
+        &[
+            "run",
+            "-m",
+            "TSO",
+            "-p",
+            "etc/program_samples/sb_0.txt",
+            "--two-phase",
+        ],
+        b"t0:0\nt0:1\nmemory\np0:1\nmemory\nexit\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    let lines = stdout
+        .lines()
+        .filter(|line| {
+            line.starts_with('#') || line.starts_with("Buffered") || line.starts_with("Propagated")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "Buffered #x := 1 in thread 0, not visible to other threads until propagated",
+            "#x: 0 (buffered, not yet visible: 1 from thread 0)",
+            "Propagated #x := 1 of thread 0, now visible to every thread",
+            "#x: 1",
+        ],
+        lines
+    );
+}
+
+#[test]
+fn test_run_fixed_schedule() {
+    // Thread 1 runs between the store and the load of thread 0
+    let output = run_with_input(
+        &[
+            "run",
+            "-m",
+            "SC",
+            "-p",
+            STORE_BUFFERING,
+            "-q",
+            "--schedule",
+            "0, 0, 1, 1, 1, 0",
+        ],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        "No more instructions to execute\n#x=1, #y=1, 0:r1=1, 0:r2=1, 1:r1=1, 1:r2=1\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+
+    let output = run_with_input(
+        &[
+            "run",
+            "-m",
+            "SC",
+            "-p",
+            STORE_BUFFERING,
+            "--schedule",
+            "0,2",
+        ],
+        b"",
+    );
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        "invalid schedule: step 1 chooses option 2, but there are 2 options\n",
+        String::from_utf8(output.stderr).unwrap()
+    );
+
+    let output = run_with_input(
+        &["run", "-m", "TSO", "-p", STORE_BUFFERING, "--schedule", "0"],
+        b"",
+    );
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        "--schedule is supported only with -m SC\n",
+        String::from_utf8(output.stderr).unwrap()
+    );
+}
+
+#[test]
+fn test_init_mem_preloads_shared_memory() {
+    let args = |model| {
+        [
+            "-m",
+            model,
+            "-p",
+            "etc/program_samples/array_read.txt",
+            "--init-mem",
+            "etc/program_samples/array_init.txt",
+        ]
+    };
+    let output = run_with_input(
+        &[&["run"][..], &args("SC"), &["-q", "--schedule", "0,0,0"]].concat(),
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        "No more instructions to execute\n#a0=10, #a1=20, #a2=30, 0:r1=10, 0:r2=20, 0:r3=30\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+
+    let output = interpreter(&[&["check"][..], &args("TSO")].concat());
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("| #a0=10, #a1=20, #a2=30, 0:r1=10, 0:r2=20, 0:r3=30\n"));
+}
@@ -0,0 +1,11 @@
+use isa_interpreter::{programs_to_instructions, LabeledInstruction};
+
+/// The programs of `etc/program_samples` with the given file names.
+pub fn sample_programs(names: &[&str]) -> Vec<Vec<LabeledInstruction>> {
+    programs_to_instructions(
+        names
+            .iter()
+            .map(|name| format!("etc/program_samples/{}.txt", name))
+            .collect(),
+    )
+}
@@ -1 +1,2327 @@
+mod common;
 
+use isa_interpreter::{
+    compare_outcomes, is_reachable, parse_programs_str, single_file_to_instructions, ArithCommand,
+    ArithMode, DependencyGraph, ExecutionError, Exploration, FinalState, GraphError, Instruction,
+    InstructionNode, InterpretorSC, InterpretorTSO, LabeledInstruction, Location, Memory,
+    MemoryAccessMode, MemoryModel, MpReport, NodeId, NodeInfo, NodeType, OldestFirst, OptionInfo,
+    ParseOptions, PropagationPolicy, Random, Reference, ReplFormatter, RunToResult,
+    SequentialConsistency, StepEffect, SubMode, Value, TSO,
+};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+use common::sample_programs;
+use pretty_assertions::assert_eq;
+
+fn both_read_zero(state: &FinalState) -> bool {
+    state.registers[&0]["r2"] == 0 && state.registers[&1]["r2"] == 0
+}
+
+#[test]
+fn test_tso_store_buffering_relaxed_outcome() {
+    let tso = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    let outcomes = tso.explore_all(100).found;
+    assert!(outcomes.iter().any(both_read_zero));
+}
+
+#[test]
+fn test_tso_store_buffering_fenced() {
+    let tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    let outcomes = tso.explore_all(100).found;
+    assert!(!outcomes.is_empty());
+    assert!(!outcomes.iter().any(both_read_zero));
+}
+
+#[test]
+fn test_explore_does_not_mutate_engine() {
+    let tso = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    let options_before = tso.get_instructions_to_exec().len();
+    tso.explore_all(100);
+    assert_eq!(options_before, tso.get_instructions_to_exec().len());
+}
+
+#[test]
+fn test_sc_store_buffering() {
+    let sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    let outcomes = sc.explore_all(100).found;
+    assert_eq!(3, outcomes.len());
+    assert!(!outcomes.iter().any(both_read_zero));
+}
+
+fn y_visible_before_x(state: &FinalState) -> bool {
+    state.registers[&1]["r3"] == 1 && state.registers[&1]["r4"] == 0
+}
+
+#[test]
+fn test_tso_stores_visible_in_program_order() {
+    let tso = TSO::new(sample_programs(&["fifo_writer", "fifo_reader"]), false);
+    let outcomes = tso.explore_all(100).found;
+    assert!(outcomes.iter().any(|state| state.registers[&1]["r3"] == 1));
+    assert!(!outcomes.iter().any(y_visible_before_x));
+}
+
+#[test]
+fn test_pso_stores_to_different_addresses_reorder() {
+    let pso = TSO::new(sample_programs(&["fifo_writer", "fifo_reader"]), true);
+    assert!(pso.explore_all(100).found.iter().any(y_visible_before_x));
+}
+
+#[test]
+fn test_compare_outcomes_marks_the_models_reaching_each_state() {
+    let outcomes = compare_outcomes(
+        sample_programs(&["fifo_writer", "fifo_reader"]),
+        &[],
+        100,
+        0,
+        false,
+        1,
+    )
+    .found;
+    let y_first = outcomes
+        .iter()
+        .filter(|(state, _)| state.contains("1:r3=1, 1:r4=0"))
+        .map(|(_, reached)| *reached)
+        .collect::<Vec<_>>();
+    assert!(!y_first.is_empty());
+    assert!(y_first
+        .iter()
+        .all(|reached| *reached == [false, false, true]));
+    // Here every weaker model reaches what a stronger one does
+    assert!(outcomes
+        .values()
+        .all(|[sc, tso, pso]| (!sc || *tso) && (!tso || *pso)));
+}
+
+#[test]
+fn test_blocked_by_reports_dependencies() {
+    let tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    let dependencies = tso
+        .blocked_by(0, 4)
+        .unwrap()
+        .iter()
+        .map(|node| node.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "Thread 0, line 1: fence SEQ_CST",
+            "Thread 0, line 3: fence SEQ_CST"
+        ],
+        dependencies
+    );
+    assert_eq!(Some(vec![]), tso.blocked_by(1, 0));
+    assert_eq!(None, tso.blocked_by(2, 0));
+}
+
+fn dependency_lines(tso: &TSO, thread_id: usize, line_index: usize) -> Vec<usize> {
+    let mut lines = tso
+        .blocked_by(thread_id, line_index)
+        .unwrap()
+        .iter()
+        .map(|node| match node {
+            NodeType::Instruction(instruction) => instruction.line_index,
+            NodeType::Propagate(_) => panic!("Unexpected propagate"),
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn test_fence_edges() {
+    let programs = single_file_to_instructions("etc/program_samples/fences.txt".to_string());
+    let tso = TSO::new(programs, false);
+    let expected: [[Vec<usize>; 3]; 4] = [
+        [vec![0, 1], vec![], vec![2]],
+        [vec![1], vec![2], vec![2]],
+        [vec![0, 1], vec![2], vec![2]],
+        [vec![], vec![], vec![]],
+    ];
+    for (thread_id, expected) in expected.iter().enumerate() {
+        for (offset, expected) in expected.iter().enumerate() {
+            assert_eq!(*expected, dependency_lines(&tso, thread_id, 2 + offset));
+        }
+    }
+}
+
+#[test]
+fn test_seq_cst_fence_orders_like_rel_acq() {
+    let edges = |mode: &str| {
+        let src = format!(
+            "store RLX r0 #w\nload RLX #x r1\nfence {}\nload RLX #y r2\nstore RLX r1 #z",
+            mode
+        );
+        let tso = TSO::new(parse_programs_str(&[&src]).unwrap(), false);
+        (2..5)
+            .map(|line_index| dependency_lines(&tso, 0, line_index))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![vec![0, 1], vec![2], vec![2]], edges("SEQ_CST"));
+    assert_eq!(edges("REL_ACQ"), edges("SEQ_CST"));
+}
+
+fn message_passing_outcomes(name: &str) -> HashSet<FinalState> {
+    let path = format!("etc/program_samples/{}.txt", name);
+    let mut pso = TSO::new(single_file_to_instructions(path), true);
+    pso.initialize(&Location::Register(Some(0), "r1".to_string()), 1);
+    pso.explore_all(100).found
+}
+
+fn stale_data(state: &FinalState) -> bool {
+    state.registers[&1]["r2"] == 1 && state.registers[&1]["r3"] == 0
+}
+
+#[test]
+fn test_relaxed_fences_allow_stale_data() {
+    assert!(message_passing_outcomes("mp_fence_rlx")
+        .iter()
+        .any(stale_data));
+}
+
+#[test]
+fn test_release_acquire_fences_forbid_stale_data() {
+    let outcomes = message_passing_outcomes("mp_fence_rel_acq");
+    assert!(outcomes.iter().any(|state| state.registers[&1]["r2"] == 1));
+    assert!(!outcomes.iter().any(stale_data));
+}
+
+#[test]
+fn test_exec_non_leaf_returns_error() {
+    let mut tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    let options_before = tso.get_instructions_to_exec().len();
+    let node = tso.dependency_graph.find_instruction(0, 4).unwrap();
+    assert!(matches!(
+        tso.exec_instruction(node),
+        Err(ExecutionError::Graph(GraphError::NodeHasDependencies(_)))
+    ));
+    assert_eq!(options_before, tso.get_instructions_to_exec().len());
+    assert!(tso.dependency_graph.find_instruction(0, 4).is_some());
+}
+
+#[test]
+fn test_register_and_address_with_same_name() {
+    let mut sc = SequentialConsistency::new(sample_programs(&["shared_name"]));
+    let instructions = sc
+        .get_instructions_to_exec()
+        .iter()
+        .map(|instruction| instruction.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Thread 0, line 0: x := 5"], instructions);
+    while let Some(instruction) = sc.get_instructions_to_exec().pop() {
+        if instruction.line_index == 1 {
+            assert_eq!("store SEQ_CST x #x", instruction.instruction.to_string());
+        }
+        if instruction.line_index == 3 {
+            assert_eq!("y := load SEQ_CST #x", instruction.instruction.to_string());
+        }
+        sc.exec_instruction(instruction).unwrap();
+    }
+    assert_eq!("#x=5, 0:x=7, 0:y=5", sc.final_state().to_string());
+    assert_eq!("#x: 5\n", sc.memory_subsystem.memory.to_shared_string());
+}
+
+fn apply(state: &FinalState, effect: &StepEffect) -> FinalState {
+    let mut state = state.clone();
+    for (thread_id, reg, value) in &effect.registers {
+        state
+            .registers
+            .entry(*thread_id)
+            .or_default()
+            .insert(reg.clone(), *value);
+    }
+    for (addr, value) in &effect.memory {
+        state.memory.insert(addr.clone(), *value);
+    }
+    state
+}
+
+#[test]
+fn test_preview_matches_execution() {
+    let mut tso = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    tso.initialize(&Location::Register(None, "r1".to_string()), 1);
+    let mut buffered = Vec::new();
+    while let Some(option) = tso.get_instructions_to_exec().pop() {
+        let before = tso.final_state();
+        let effect = tso.preview(option.clone()).unwrap();
+        assert_eq!(before, tso.final_state());
+        tso.exec_instruction(option).unwrap();
+        assert_eq!(apply(&before, &effect), tso.final_state());
+        buffered.extend(effect.buffered);
+    }
+    buffered.sort();
+    assert_eq!(
+        vec![(0, "x".to_string(), 1), (1, "y".to_string(), 1)],
+        buffered
+    );
+
+    let mut sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    while let Some(option) = sc.get_instructions_to_exec().pop() {
+        let before = sc.final_state();
+        let effect = sc.preview(option.clone()).unwrap();
+        assert_eq!(before, sc.final_state());
+        sc.exec_instruction(option).unwrap();
+        assert_eq!(apply(&before, &effect), sc.final_state());
+    }
+}
+
+#[test]
+fn test_jump_to_label_only_line() {
+    let sc = SequentialConsistency::new(sample_programs(&["label_only"]));
+    let outcomes = sc.explore_all(100).found;
+    assert_eq!(1, outcomes.len());
+    assert_eq!(
+        "0:r1=1, 0:r3=7",
+        outcomes.iter().next().unwrap().to_string()
+    );
+}
+
+#[test]
+fn test_detect_cycle() {
+    let tso = TSO::try_new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false).unwrap();
+    assert_eq!(None, tso.dependency_graph.detect_cycle());
+
+    let fence = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    let load = tso.dependency_graph.find_instruction(0, 4).unwrap();
+    fence.borrow_mut().depends_on.push(load.clone());
+    load.borrow_mut().depends_on_me.push(fence.clone());
+    let mut cycle = tso.dependency_graph.detect_cycle().unwrap();
+    cycle.sort();
+    assert_eq!(vec!["0-1", "0-4"], cycle);
+    assert_eq!(
+        "Cyclic dependency graph: 0-1 -> 0-4",
+        GraphError::Cycle(cycle).to_string()
+    );
+}
+
+#[test]
+fn test_dot_node_ids_are_unique() {
+    let mut tso = TSO::new(sample_programs(&["sb_0"]), false);
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    let write = match store.borrow().instruction.clone() {
+        NodeType::Instruction(write) => write,
+        NodeType::Propagate(_) => unreachable!(),
+    };
+    tso.exec_instruction(store).unwrap();
+    tso.dependency_graph
+        .add_propagate(write, Reference::Memory("x".into()));
+
+    let dot = tso.dependency_graph.to_dot(false);
+    let ids = dot
+        .split(';')
+        .filter(|statement| statement.contains("[label="))
+        .map(|statement| statement.split('[').next().unwrap().trim().to_string())
+        .collect::<HashSet<_>>();
+    assert_eq!(tso.dependency_graph.nodes().len(), ids.len());
+}
+
+#[test]
+fn test_adjacency_lists_dependencies_of_every_node() {
+    let programs =
+        parse_programs_str(&["r1 = 1\nstore RLX r1 #x\nfence SEQ_CST\nload RLX #y r2"]).unwrap();
+    let mut tso = TSO::new(programs, false);
+    let adjacency = |tso: &TSO| {
+        tso.dependency_graph
+            .to_adjacency()
+            .into_iter()
+            .map(|(info, depends_on)| (info.id, depends_on))
+            .collect::<Vec<_>>()
+    };
+    let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("0-0".to_string(), ids(&[])),
+            ("0-1".to_string(), ids(&[])),
+            ("0-2".to_string(), ids(&["0-0", "0-1"])),
+            ("0-3".to_string(), ids(&["0-2"])),
+        ],
+        adjacency(&tso)
+    );
+
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    tso.exec_instruction(store).unwrap();
+    assert_eq!(
+        vec![
+            ("0-0".to_string(), ids(&[])),
+            ("0-2".to_string(), ids(&["0-0", "prop_0-1"])),
+            ("0-3".to_string(), ids(&["0-2"])),
+            ("prop_0-1".to_string(), ids(&[])),
+        ],
+        adjacency(&tso)
+    );
+    let (propagate, _) = tso.dependency_graph.to_adjacency().pop().unwrap();
+    assert_eq!(0, propagate.instance);
+    assert!(matches!(propagate.instruction, NodeType::Propagate(_)));
+}
+
+#[test]
+fn test_dfs_filter_follows_long_chains() {
+    // Line 0 waits for line 1, which waits for line 2, and so on, far deeper
+    // than a recursive search could go
+    let mut nodes: Vec<Rc<RefCell<InstructionNode>>> = Vec::new();
+    for line_index in 0..100_000 {
+        let node = Rc::new(RefCell::new(InstructionNode {
+            instruction: NodeType::Instruction(LabeledInstruction::new(
+                None,
+                Instruction::Nop,
+                line_index,
+                0,
+            )),
+            depends_on: Vec::new(),
+            depends_on_me: Vec::new(),
+            instance: 0,
+        }));
+        if let Some(previous) = nodes.last() {
+            InstructionNode::add_dependency(previous.clone(), node.clone());
+        }
+        nodes.push(node);
+    }
+    let graph = DependencyGraph::from_nodes(nodes);
+    let even = graph.dfs_filter(|node| match node {
+        NodeType::Instruction(instruction) => instruction.line_index % 2 == 0,
+        NodeType::Propagate(_) => false,
+    });
+    assert_eq!(50_000, even.len());
+    assert!(even
+        .iter()
+        .enumerate()
+        .all(|(index, node)| node.borrow().id() == format!("0-{}", 2 * index)));
+}
+
+/// Edges of every node, with the dependencies of each in a fixed order.
+fn sorted_adjacency(adjacency: Vec<(NodeInfo, Vec<NodeId>)>) -> Vec<(NodeId, Vec<NodeId>)> {
+    adjacency
+        .into_iter()
+        .map(|(info, mut depends_on)| {
+            depends_on.sort();
+            (info.id, depends_on)
+        })
+        .collect()
+}
+
+/// Two threads of `len` lines cycling through instructions of every kind
+/// that orders others, shifted against each other.
+fn ordering_programs(len: usize) -> Vec<Vec<LabeledInstruction>> {
+    let lines = [
+        "r1 = 1",
+        "store RLX r1 #x",
+        "load ACQ #y r2",
+        "r3 := cas REL #z r1 r2",
+        "fence REL",
+        "store REL r2 #y",
+        "load RLX #x r4",
+        "fence ACQ",
+        "r5 := fai SEQ_CST #x r1",
+        "store SEQ_CST r5 #z",
+        "fence REL_ACQ #y",
+        "load SEQ_CST #z r6",
+        "r7 := fetch_max ACQ #y r6",
+        "fence SEQ_CST",
+        "load RLX #y r8",
+        "fence RLX",
+    ];
+    let program = |shift: usize| {
+        (0..len)
+            .map(|index| lines[(index + shift) % lines.len()])
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    parse_programs_str(&[&program(0), &program(5)]).unwrap()
+}
+
+#[test]
+fn test_built_dependencies_match_adding_them_per_node() {
+    let programs = ordering_programs(100);
+
+    let built = TSO::new(programs.clone(), false).dependency_graph;
+    let mut naive = TSO::new(Vec::new(), false).dependency_graph;
+    for instruction in programs.into_iter().flatten() {
+        naive.add_node(instruction);
+    }
+    for index in 0..naive.nodes().len() {
+        naive.add_dependencies(index);
+    }
+
+    assert_eq!(
+        sorted_adjacency(naive.to_adjacency()),
+        sorted_adjacency(built.to_adjacency())
+    );
+}
+
+#[test]
+fn test_nodes_added_with_their_dependencies_match_built_ones() {
+    let programs = ordering_programs(40);
+    let built = TSO::new(programs.clone(), false).dependency_graph;
+    let mut built = sorted_adjacency(built.to_adjacency());
+    built.sort();
+
+    let instructions = programs.into_iter().flatten().collect::<Vec<_>>();
+    let interleaved = (0..40).flat_map(|line| [line, line + 40]);
+    // In program order, backwards as a jump re-adds them, and interleaved
+    let orders: [Vec<usize>; 3] = [
+        (0..80).collect(),
+        (0..80).rev().collect(),
+        interleaved.rev().collect(),
+    ];
+    for order in orders {
+        let mut graph = TSO::new(Vec::new(), false).dependency_graph;
+        for index in order {
+            graph.add_node_with_deps(instructions[index].clone());
+        }
+        let mut incremental = sorted_adjacency(graph.to_adjacency());
+        incremental.sort();
+        assert_eq!(built, incremental);
+    }
+}
+
+#[test]
+fn test_dependencies_of_long_programs_build_quickly() {
+    // Adding each node's edges by searching the whole graph took close to a
+    // minute for this program
+    let program = (0..1667)
+        .map(|index| format!("r1 = {index}\nstore RLX r1 #x{index}\nload RLX #x{index} r2"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let tso = TSO::new(parse_programs_str(&[&program]).unwrap(), false);
+    assert_eq!(5001, tso.dependency_graph.nodes().len());
+    let load = tso.dependency_graph.find_instruction(0, 2).unwrap();
+    let depends_on = load.borrow().depends_on.clone();
+    assert_eq!(1, depends_on.len());
+    assert_eq!("0-1", depends_on[0].borrow().id());
+}
+
+#[test]
+fn test_dot_labels_show_instruction_text() {
+    let mut tso = TSO::new(sample_programs(&["sb_0"]), false);
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    tso.exec_instruction(store).unwrap();
+
+    let dot = tso.dependency_graph.to_dot(false);
+    assert!(dot.contains("[label=\"Thread 0, line 2: r2 := load RLX #y\"]"));
+    assert!(dot.contains(
+        "[label=\"Propagate for write (Thread 0, line 1: store RLX r1 #x)\",shape=rectangle]"
+    ));
+    assert!(dot.contains("T0Xinstr2N"));
+    assert!(!dot.contains("label=\"T0X"));
+}
+
+#[test]
+fn test_mermaid_mirrors_dot_clusters_and_edges() {
+    let tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    let mermaid = tso.dependency_graph.to_mermaid();
+    assert!(mermaid.starts_with("flowchart TD\n"));
+    assert!(mermaid.contains("subgraph T0[\"Thread #0\"]"));
+    assert!(mermaid.contains("subgraph T1[\"Thread #1\"]"));
+
+    let mermaid_edges = mermaid
+        .lines()
+        .filter_map(|line| line.trim().split_once(" --> "))
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect::<HashSet<_>>();
+    let dot_edges = tso
+        .dependency_graph
+        .to_dot(false)
+        .split(';')
+        .filter_map(|statement| statement.split_once("->"))
+        .map(|(from, to)| (from.trim().to_string(), to.trim().to_string()))
+        .collect::<HashSet<_>>();
+    assert!(!mermaid_edges.is_empty());
+    assert_eq!(dot_edges, mermaid_edges);
+}
+
+#[test]
+fn test_oldest_first_policy_is_deterministic_and_sc_like() {
+    let programs = sample_programs(&["sb_fenced_0", "sb_fenced_1"]);
+    let run = || {
+        TSO::new(programs.clone(), false)
+            .with_policy(Box::new(OldestFirst))
+            .run(100)
+    };
+    let state = run().unwrap();
+    assert_eq!(state, run().unwrap());
+    assert_eq!(
+        "#x=1, #y=1, 0:r1=1, 0:r2=0, 1:r1=1, 1:r2=1",
+        state.to_string()
+    );
+    assert!(SequentialConsistency::new(programs)
+        .explore_all(100)
+        .found
+        .contains(&state));
+}
+
+#[test]
+fn test_random_policy_is_reproducible() {
+    let programs = sample_programs(&["sb_0", "sb_1"]);
+    let run = |seed| {
+        TSO::new(programs.clone(), true)
+            .with_policy(Box::new(Random::new(seed)))
+            .run(100)
+    };
+    assert_eq!(run(7), run(7));
+    assert!(run(7).is_some());
+}
+
+fn store_buffering_rmw_outcomes(name: &str) -> HashSet<FinalState> {
+    let path = format!("etc/program_samples/{}.txt", name);
+    let mut tso = TSO::new(single_file_to_instructions(path), false);
+    tso.initialize(&Location::Register(None, "r1".to_string()), 1);
+    tso.explore_all(100).found
+}
+
+#[test]
+fn test_relaxed_rmw_allows_store_buffering() {
+    assert!(store_buffering_rmw_outcomes("sb_fai_rlx")
+        .iter()
+        .any(both_read_zero));
+}
+
+#[test]
+fn test_seq_cst_rmw_forbids_store_buffering() {
+    let outcomes = store_buffering_rmw_outcomes("sb_fai_seq_cst");
+    assert!(!outcomes.is_empty());
+    assert!(!outcomes.iter().any(both_read_zero));
+}
+
+#[test]
+fn test_address_fence_orders_only_its_address() {
+    let mut pso = TSO::new(sample_programs(&["fence_addr"]), true);
+    assert_eq!(vec![0], dependency_lines(&pso, 0, 2));
+    // The loads also follow the stores to their address, see store forwarding
+    assert_eq!(vec![0, 2], dependency_lines(&pso, 0, 3));
+    assert_eq!(vec![1], dependency_lines(&pso, 0, 4));
+
+    for line_index in [0, 1] {
+        let store = pso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        pso.exec_instruction(store).unwrap();
+    }
+    let dependencies = pso
+        .blocked_by(0, 2)
+        .unwrap()
+        .iter()
+        .map(|node| node.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["Propagate for write (Thread 0, line 0: store RLX r1 #x)"],
+        dependencies
+    );
+}
+
+#[test]
+fn test_listing() {
+    let mut sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    let first = sc.get_instructions_to_exec()[0].clone();
+    sc.exec_instruction(first).unwrap();
+    assert_eq!(
+        "   Thread 0, line 0: r1 := 1\n\
+         -> Thread 0, line 1: store RLX r1 #x\n\
+         \x20  Thread 0, line 2: r2 := load RLX #y\n\
+         -> Thread 1, line 0: r1 := 1\n\
+         \x20  Thread 1, line 1: store RLX r1 #y\n\
+         \x20  Thread 1, line 2: r2 := load RLX #x\n",
+        sc.listing()
+    );
+
+    let mut tso = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    let store = tso.dependency_graph.find_instruction(1, 1).unwrap();
+    tso.exec_instruction(store).unwrap();
+    assert_eq!(
+        "[ ] Thread 0, line 0: r1 := 1\n\
+         [ ] Thread 0, line 1: store RLX r1 #x\n\
+         [ ] Thread 0, line 2: r2 := load RLX #y\n\
+         [ ] Thread 1, line 0: r1 := 1\n\
+         [x] Thread 1, line 1: store RLX r1 #y\n\
+         [ ] Thread 1, line 2: r2 := load RLX #x\n",
+        tso.listing()
+    );
+}
+
+#[test]
+fn test_arith_modes_on_overflow() {
+    let init = [
+        (Location::Register(None, "r1".to_string()), i64::MAX),
+        (Location::Register(None, "r2".to_string()), 1),
+    ];
+    for (mode, expected) in [
+        (ArithMode::Wrapping, Some(i64::MIN)),
+        (ArithMode::Saturating, Some(i64::MAX)),
+        (ArithMode::Checked, None),
+    ] {
+        let mut sc =
+            SequentialConsistency::new(sample_programs(&["overflow"])).with_arith_mode(mode);
+        for (location, value) in &init {
+            sc.initialize(location, *value);
+        }
+        let outcomes = sc.explore_all(10).found;
+        let r3 = outcomes
+            .iter()
+            .map(|state| state.registers[&0]["r3"])
+            .collect::<Vec<_>>();
+        assert_eq!(expected.into_iter().collect::<Vec<_>>(), r3);
+
+        let mut tso = TSO::new(sample_programs(&["overflow"]), false).with_arith_mode(mode);
+        for (location, value) in &init {
+            tso.initialize(location, *value);
+        }
+        assert_eq!(outcomes, tso.explore_all(10).found);
+    }
+
+    let mut sc = SequentialConsistency::new(sample_programs(&["overflow"]))
+        .with_arith_mode(ArithMode::Checked);
+    for (location, value) in &init {
+        sc.initialize(location, *value);
+    }
+    let option = sc.get_instructions_to_exec()[0].clone();
+    let err = sc.exec_instruction(option).unwrap_err();
+    assert_eq!(
+        ExecutionError::Arithmetic {
+            thread_id: 0,
+            line_index: 0
+        },
+        err
+    );
+    assert_eq!("Thread 0, line 0: arithmetic overflow", err.to_string());
+}
+
+#[test]
+fn test_buffer_summary_lists_unpropagated_store() {
+    let mut tso = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    tso.initialize(&Location::Register(None, "r1".to_string()), 1);
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    tso.exec_instruction(store).unwrap();
+    let pending = tso
+        .memory_subsystem
+        .pending(0)
+        .iter()
+        .map(|write| write.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["#x := 1"], pending);
+    assert!(tso.memory_subsystem.pending(1).is_empty());
+    assert_eq!(
+        "Store buffers:\n  \
+         Thread 0: #x := 1\n  \
+         Thread 1: empty\n\
+         Warning: some writes were never propagated and are invisible to other threads\n",
+        tso.buffer_summary()
+    );
+}
+
+#[test]
+fn test_progress_increases() {
+    let mut tso = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    let mut last = tso.progress();
+    assert_eq!((0, 6), last);
+    while let Some(option) = tso.get_instructions_to_exec().pop() {
+        tso.exec_instruction(option).unwrap();
+        let progress = tso.progress();
+        assert!(progress.0 >= last.0);
+        assert_eq!(6, progress.1);
+        last = progress;
+    }
+    assert_eq!((6, 6), last);
+
+    let mut sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    assert_eq!((0, 6), sc.progress());
+    let mut executed = 0;
+    while let Some(option) = sc.get_instructions_to_exec().pop() {
+        sc.exec_instruction(option).unwrap();
+        executed += 1;
+        assert_eq!((executed, 6), sc.progress());
+    }
+}
+
+#[test]
+fn test_conditional_jump_cmp() {
+    for (cmp, expected_taken) in [
+        ("<", [true, false, false]),
+        ("<=", [true, true, false]),
+        (">", [false, false, true]),
+        (">=", [false, true, true]),
+        ("==", [false, true, false]),
+        ("!=", [true, false, true]),
+    ] {
+        let source = format!("if r1 {} r2 goto L\nr3 = 1\nL: nop", cmp);
+        let programs = parse_programs_str(&[&source]).unwrap();
+        for (r1, taken) in [1, 2, 3].into_iter().zip(expected_taken) {
+            let mut sc = SequentialConsistency::new(programs.clone());
+            sc.initialize(&Location::Register(None, "r1".to_string()), r1);
+            sc.initialize(&Location::Register(None, "r2".to_string()), 2);
+            let outcomes = sc.explore_all(10).found;
+            let r3 = outcomes
+                .iter()
+                .map(|state| state.registers[&0].get("r3").copied())
+                .collect::<Vec<_>>();
+            let expected = if taken { None } else { Some(1) };
+            assert_eq!(vec![expected], r3, "{} {} 2", r1, cmp);
+        }
+    }
+}
+
+#[test]
+fn test_conditional_jump_under_tso_and_pso() {
+    let programs = parse_programs_str(&["L: r3 = 1\nif r1 goto L"]).unwrap();
+    for (r1, taken) in [(0, false), (1, true), (-1, true)] {
+        for is_pso in [false, true] {
+            let mut tso = TSO::new(programs.clone(), is_pso);
+            tso.initialize(&Location::Register(None, "r1".to_string()), r1);
+            for line_index in [0, 1] {
+                let node = tso
+                    .dependency_graph
+                    .find_instruction(0, line_index)
+                    .unwrap();
+                tso.exec_instruction(node).unwrap();
+            }
+            // Like under SC, a nonzero register jumps back
+            let pending = tso.dependency_graph.find_instruction(0, 0).is_some();
+            assert_eq!(taken, pending, "r1 = {}, pso: {}", r1, is_pso);
+        }
+    }
+}
+
+#[test]
+fn test_conditional_jump_cmp_under_tso_and_pso() {
+    for (cmp, expected_taken) in [
+        ("<", [true, false, false]),
+        ("<=", [true, true, false]),
+        (">", [false, false, true]),
+        (">=", [false, true, true]),
+        ("==", [false, true, false]),
+        ("!=", [true, false, true]),
+    ] {
+        let source = format!("L: r3 = 1\nif r1 {} r2 goto L", cmp);
+        let programs = parse_programs_str(&[&source]).unwrap();
+        for (r1, taken) in [1, 2, 3].into_iter().zip(expected_taken) {
+            for is_pso in [false, true] {
+                let mut tso = TSO::new(programs.clone(), is_pso);
+                tso.initialize(&Location::Register(None, "r1".to_string()), r1);
+                tso.initialize(&Location::Register(None, "r2".to_string()), 2);
+                for line_index in [0, 1] {
+                    let node = tso
+                        .dependency_graph
+                        .find_instruction(0, line_index)
+                        .unwrap();
+                    tso.exec_instruction(node).unwrap();
+                }
+                // Jumping back makes the labeled line pending again
+                let pending = tso.dependency_graph.find_instruction(0, 0).is_some();
+                assert_eq!(taken, pending, "{} {} 2, pso: {}", r1, cmp, is_pso);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_forward_jump_under_tso_and_pso_as_under_sc() {
+    // The fence keeps the skipped line from running before the jump decides
+    for source in [
+        "if r1 goto END\nfence SEQ_CST\nr3 = 5\nEND: nop",
+        "if r1 < r2 goto END\nfence SEQ_CST\nr3 = 5\nEND: nop",
+    ] {
+        let programs = parse_programs_str(&[source]).unwrap();
+        for r1 in [0, 1] {
+            let registers = [("r1", r1), ("r2", 1)];
+            let mut sc = SequentialConsistency::new(programs.clone());
+            for (register, value) in registers {
+                sc.initialize(&Location::Register(None, register.to_string()), value);
+            }
+            let expected = sc.explore_all(10).found;
+            assert_eq!(1, expected.len());
+            for is_pso in [false, true] {
+                let mut tso = TSO::new(programs.clone(), is_pso);
+                for (register, value) in registers {
+                    tso.initialize(&Location::Register(None, register.to_string()), value);
+                }
+                let explored = tso.explore_all(10);
+                assert_eq!(expected, explored.found, "{}, r1 = {}", source, r1);
+                assert_eq!(0, explored.failed());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_potential_races() {
+    let racy = TSO::new(sample_programs(&["sb_0", "sb_1"]), false);
+    assert_eq!(
+        vec![
+            ("0-1".to_string(), "1-2".to_string()),
+            ("0-2".to_string(), "1-1".to_string()),
+        ],
+        racy.dependency_graph.potential_races()
+    );
+
+    let fenced = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    assert!(fenced.dependency_graph.potential_races().is_empty());
+}
+
+#[test]
+fn test_run_to() {
+    let none = HashSet::new();
+    let mut sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    assert_eq!(RunToResult::Reached(5), sc.run_to(1, 1, &none, 100));
+    assert_eq!(vec![3, 2], sc.instruction_pointers);
+    assert_eq!(RunToResult::Unreachable(1), sc.run_to(0, 0, &none, 100));
+
+    let mut tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    assert!(matches!(
+        tso.run_to(1, 2, &none, 100),
+        RunToResult::Reached(_)
+    ));
+    assert!(tso.dependency_graph.find_instruction(1, 2).is_none());
+    assert!(tso.dependency_graph.find_instruction(1, 4).is_some());
+    assert_eq!(RunToResult::Unreachable(0), tso.run_to(1, 2, &none, 0));
+}
+
+#[test]
+fn test_run_to_halts_at_breakpoint() {
+    let breakpoints = HashSet::from(["y".to_string()]);
+    let mut sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    assert_eq!(
+        RunToResult::Breakpoint(4, "y".to_string()),
+        sc.run_to(1, 2, &breakpoints, 100)
+    );
+    assert_eq!(vec![3, 1], sc.instruction_pointers);
+    assert_eq!(RunToResult::Reached(2), sc.run_to(1, 2, &breakpoints, 100));
+
+    let mut tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    let breakpoints = HashSet::from(["x".to_string()]);
+    match tso.run_to(1, 4, &breakpoints, 100) {
+        RunToResult::Breakpoint(_, addr) => assert_eq!("x", addr),
+        other => panic!("Expected a breakpoint, got {:?}", other),
+    }
+    assert!(tso.dependency_graph.find_instruction(0, 2).is_some());
+}
+
+#[test]
+fn test_watch_records_propagated_changes() {
+    let mut tso = TSO::new(sample_programs(&["write_twice"]), false);
+    tso.memory_subsystem.memory.watch("x");
+    for line_index in 0..4 {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    assert!(tso.memory_subsystem.memory.take_changes().is_empty());
+    tso.run(10).unwrap();
+    assert_eq!(
+        vec![
+            ("x".to_string(), Value(0), Value(1)),
+            ("x".to_string(), Value(1), Value(2))
+        ],
+        tso.memory_subsystem.memory.take_changes()
+    );
+}
+
+#[test]
+fn test_values() {
+    let wrapping = ArithMode::Wrapping;
+    assert_eq!(
+        Some(Value(5)),
+        ArithCommand::Add.apply(Value(2), Value(3), wrapping, None)
+    );
+    assert_eq!(
+        Some(Value(-1)),
+        ArithCommand::Sub.apply(Value(0), Value(1), wrapping, None)
+    );
+    assert_eq!(
+        None,
+        ArithCommand::Div.apply(Value(1), Value(0), wrapping, None)
+    );
+
+    let mut memory = Memory::new();
+    assert_eq!(Value(0), memory.load("x"));
+    memory.store("x", Value(7));
+    assert_eq!(Value(7), memory.load("x"));
+    assert_eq!("x: 7\n", memory.to_string());
+
+    let sc = SequentialConsistency::new(sample_programs(&["program_1"]));
+    let outcomes = sc.explore_all(100).found;
+    assert_eq!(
+        vec!["#r1=1488, #r5=1497, 0:r2=1488, 0:r3=1497, 0:r4=9"],
+        outcomes
+            .iter()
+            .map(|state| state.to_string())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_interpreters_from_generated_instructions() {
+    let r1 = || Reference::Register("r1".into());
+    let x = || Reference::Memory("x".into());
+    let program = vec![vec![
+        LabeledInstruction::new(None, Instruction::AssignConst(r1(), 7), 0, 0),
+        LabeledInstruction::new(
+            None,
+            Instruction::Store(MemoryAccessMode::SeqCst, r1(), x()),
+            1,
+            0,
+        ),
+    ]];
+
+    let mut sc = InterpretorSC::from_instructions(program.clone());
+    while let Some(instruction) = sc.system().get_instructions_to_exec().pop() {
+        sc.system().exec_instruction(instruction).unwrap();
+    }
+    assert_eq!("#x=7, 0:r1=7", sc.system().final_state().to_string());
+
+    let mut tso = InterpretorTSO::from_instructions(program, false);
+    while let Some(node) = tso.system().get_instructions_to_exec().pop() {
+        tso.system().exec_instruction(node).unwrap();
+    }
+    // Nothing orders the store after the assignment of its register
+    assert_eq!("#x=0, 0:r1=7", tso.system().final_state().to_string());
+}
+
+struct Mnemonics;
+
+impl ReplFormatter for Mnemonics {
+    fn option(&self, _: usize, option: &OptionInfo) -> Option<String> {
+        match option.thread_id {
+            0 => Some(format!("* {}", option.mnemonic())),
+            _ => None,
+        }
+    }
+
+    fn prompt(&self, executed: usize, total: usize) -> String {
+        format!("{} of {} done>", executed, total)
+    }
+}
+
+#[test]
+fn test_custom_formatter_changes_menu() {
+    let programs = parse_programs_str(&["r1 = 1", "r2 = 2"]).unwrap();
+    let sc = InterpretorSC::from_instructions(programs.clone());
+    assert_eq!(
+        "0 | Thread 0, line 0: r1 := 1\n1 | Thread 1, line 0: r2 := 2\n\
+         [0/2] Please select an option and input the index: \n",
+        sc.menu()
+    );
+
+    let sc = InterpretorSC::from_instructions(programs.clone()).with_formatter(Mnemonics);
+    assert_eq!("* t0:0\n0 of 2 done>\n", sc.menu());
+    let tso = InterpretorTSO::from_instructions(programs, false).with_formatter(Mnemonics);
+    assert_eq!("* t0:0\n0 of 2 done>\n", tso.menu());
+}
+
+const FORWARDING: &str = "store RLX r1 #x\nstore RLX r2 #x\nload RLX #x r3";
+
+fn store_forwarding(is_pso: bool) -> TSO {
+    let programs = parse_programs_str(&[FORWARDING, "load RLX #x r1"]).unwrap();
+    let mut tso = TSO::new(programs, is_pso);
+    tso.initialize(&Location::Register(Some(0), "r1".to_string()), 5);
+    tso.initialize(&Location::Register(Some(0), "r2".to_string()), 7);
+    tso
+}
+
+#[test]
+fn test_load_forwards_latest_buffered_store() {
+    for is_pso in [false, true] {
+        let mut tso = store_forwarding(is_pso);
+        for line_index in [0, 1] {
+            assert!(tso.dependency_graph.find_instruction(0, 2).is_some());
+            let store = tso
+                .dependency_graph
+                .find_instruction(0, line_index)
+                .unwrap();
+            tso.exec_instruction(store).unwrap();
+        }
+        assert_eq!(
+            vec![("x".to_string(), 5), ("x".to_string(), 7)],
+            tso.memory_subsystem.pending_writes(0)
+        );
+
+        let own_load = tso.dependency_graph.find_instruction(0, 2).unwrap();
+        tso.exec_instruction(own_load).unwrap();
+        let other_load = tso.dependency_graph.find_instruction(1, 0).unwrap();
+        tso.exec_instruction(other_load).unwrap();
+        assert_eq!(Value(7), tso.registers.load("r3", 0));
+        assert_eq!(Value(0), tso.registers.load("r1", 1));
+    }
+}
+
+#[test]
+fn test_same_address_accesses_keep_program_order() {
+    for is_pso in [false, true] {
+        let tso = store_forwarding(is_pso);
+        assert_eq!(vec![0], dependency_lines(&tso, 0, 1));
+        assert_eq!(vec![0, 1], dependency_lines(&tso, 0, 2));
+        assert_eq!(
+            vec![
+                ("0-0".to_string(), "1-0".to_string()),
+                ("0-1".to_string(), "1-0".to_string()),
+            ],
+            tso.dependency_graph.potential_races()
+        );
+        for state in tso.explore_all(100).found {
+            assert_eq!(7, state.registers[&0]["r3"]);
+            assert_eq!(7, state.memory["x"]);
+        }
+    }
+}
+
+#[test]
+fn test_reset_restores_initial_options() {
+    let programs = sample_programs(&["sb_0", "sb_1"]);
+
+    let mut sc = SequentialConsistency::new(programs.clone());
+    let initial = sc.get_instructions_to_exec();
+    for _ in 0..3 {
+        let option = sc.get_instructions_to_exec()[0].clone();
+        sc.exec_instruction(option).unwrap();
+    }
+    assert_ne!(initial, sc.get_instructions_to_exec());
+    sc.reset();
+    assert_eq!(initial, sc.get_instructions_to_exec());
+    assert_eq!((0, 6), sc.progress());
+
+    let option_ids = |tso: &TSO| {
+        let mut ids = tso
+            .get_instructions_to_exec()
+            .iter()
+            .map(|node| node.borrow().instruction.id())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+    let mut tso = TSO::new(programs, true);
+    let initial = option_ids(&tso);
+    for _ in 0..3 {
+        let option = tso.get_instructions_to_exec()[0].clone();
+        tso.exec_instruction(option).unwrap();
+    }
+    assert_ne!(initial, option_ids(&tso));
+    tso.reset();
+    assert_eq!(initial, option_ids(&tso));
+    assert!(tso.memory_subsystem.pending(0).is_empty());
+    assert_eq!((0, 6), tso.progress());
+}
+
+fn fenced_store_buffering_outcomes(scope: &str) -> HashSet<FinalState> {
+    let thread = |store: &str, load: &str| {
+        format!(
+            "store RLX r1 #{}\nfence SEQ_CST {}\nload RLX #{} r2",
+            store, scope, load
+        )
+    };
+    let programs = parse_programs_str(&[&thread("x", "y"), &thread("y", "x")]).unwrap();
+    let mut tso = TSO::new(programs, false);
+    tso.initialize(&Location::Register(None, "r1".to_string()), 1);
+    tso.explore_all(100).found
+}
+
+#[test]
+fn test_local_fence_allows_store_buffering() {
+    assert!(fenced_store_buffering_outcomes("local")
+        .iter()
+        .any(both_read_zero));
+
+    let outcomes = fenced_store_buffering_outcomes("global");
+    assert!(!outcomes.is_empty());
+    assert!(!outcomes.iter().any(both_read_zero));
+}
+
+#[test]
+fn test_local_fence_orders_instructions() {
+    let programs = parse_programs_str(&["r1 = 1\nfence SEQ_CST local\nr2 = 2"]).unwrap();
+    let tso = TSO::new(programs, false);
+    assert_eq!(vec![0], dependency_lines(&tso, 0, 1));
+    assert_eq!(vec![1], dependency_lines(&tso, 0, 2));
+}
+
+#[test]
+fn test_trace_as_swimlane() {
+    let programs = sample_programs(&["sb_0", "sb_1"]);
+    let mut tso = TSO::new(programs.clone(), false);
+    for (thread_id, line_index) in [(0, 0), (1, 0), (0, 1)] {
+        let node = tso
+            .dependency_graph
+            .find_instruction(thread_id, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    let propagate = tso
+        .get_instructions_to_exec()
+        .into_iter()
+        .find(|node| matches!(node.borrow().instruction, NodeType::Propagate(_)))
+        .unwrap();
+    tso.exec_instruction(propagate).unwrap();
+    assert_eq!(
+        "step | Thread 0           | Thread 1\n\
+         \x20  1 | 0: r1 := 1         |\n\
+         \x20  2 |                    | 0: r1 := 1\n\
+         \x20  3 | 1: store RLX r1 #x |\n\
+         \x20  4 | 1: propagate #x    |\n",
+        tso.trace_as_swimlane()
+    );
+
+    let mut sc = SequentialConsistency::new(programs);
+    for _ in 0..2 {
+        let last = sc.get_instructions_to_exec().pop().unwrap();
+        sc.exec_instruction(last).unwrap();
+    }
+    let swimlane = sc.trace_as_swimlane();
+    assert_eq!(3, swimlane.lines().count());
+    for row in swimlane.lines().skip(1) {
+        let columns = row.split(" | ").collect::<Vec<_>>();
+        assert_eq!(3, columns.len());
+        assert!(columns[1].trim().is_empty());
+    }
+}
+
+#[test]
+fn test_sub_modes() {
+    let sub = |mode| ArithCommand::Sub.apply(Value(2), Value(5), ArithMode::Checked, Some(mode));
+    assert_eq!(Some(Value(-3)), sub(SubMode::SignedWrap));
+    assert_eq!(Some(Value(0)), sub(SubMode::SaturateZero));
+    assert_eq!(None, sub(SubMode::ErrorOnUnderflow));
+    assert_eq!(
+        None,
+        ArithCommand::Add.apply(
+            Value(i64::MAX),
+            Value(1),
+            ArithMode::Checked,
+            Some(SubMode::SaturateZero)
+        )
+    );
+
+    let program = || parse_programs_str(&["r1 = 2\nr2 = 5\nr3 = r1 - r2"]).unwrap();
+    for (mode, expected) in [
+        (SubMode::SignedWrap, Some(-3)),
+        (SubMode::SaturateZero, Some(0)),
+        (SubMode::ErrorOnUnderflow, None),
+    ] {
+        let sc = SequentialConsistency::new(program()).with_sub_mode(mode);
+        let r3 = sc
+            .explore_all(10)
+            .found
+            .iter()
+            .map(|state| state.registers[&0]["r3"])
+            .collect::<Vec<_>>();
+        assert_eq!(expected.into_iter().collect::<Vec<_>>(), r3);
+    }
+
+    let mut sc = SequentialConsistency::new(program()).with_sub_mode(SubMode::ErrorOnUnderflow);
+    for _ in 0..2 {
+        let option = sc.get_instructions_to_exec()[0].clone();
+        sc.exec_instruction(option).unwrap();
+    }
+    let option = sc.get_instructions_to_exec()[0].clone();
+    let err = sc.exec_instruction(option).unwrap_err();
+    assert_eq!(
+        ExecutionError::Underflow {
+            thread_id: 0,
+            line_index: 2
+        },
+        err
+    );
+    assert_eq!("Thread 0, line 2: subtraction below zero", err.to_string());
+}
+
+#[test]
+fn test_mem_default_for_unwritten_locations() {
+    let programs = || parse_programs_str(&["load RLX #x r1\nr3 = r2 + r1"]).unwrap();
+    let sc = SequentialConsistency::new(programs()).with_mem_default(9);
+    let states = sc.explore_all(10).found;
+    assert_eq!(1, states.len());
+    for state in &states {
+        assert_eq!(9, state.registers[&0]["r1"]);
+        assert_eq!(18, state.registers[&0]["r3"]);
+    }
+
+    let mut tso = TSO::new(programs(), true).with_mem_default(9);
+    tso.reset();
+    assert_eq!(states, tso.explore_all(10).found);
+
+    let mut memory = Memory::new();
+    memory.set_default(Value(3));
+    assert_eq!(Value(3), memory.load("x"));
+    memory.watch("x");
+    memory.store("x", Value(3));
+    assert!(memory.take_changes().is_empty());
+}
+
+#[test]
+fn test_flush_thread_makes_buffered_writes_visible() {
+    for is_pso in [false, true] {
+        let programs = parse_programs_str(&[
+            "store RLX r1 #x\nstore RLX r2 #y\nstore RLX r3 #x",
+            "store RLX r1 #z",
+        ])
+        .unwrap();
+        let mut tso = TSO::new(programs, is_pso);
+        for (register, value) in [("r1", 1), ("r2", 2), ("r3", 3)] {
+            tso.initialize(&Location::Register(None, register.to_string()), value);
+        }
+        for (thread_id, line_index) in [(0, 0), (0, 1), (0, 2), (1, 0)] {
+            let store = tso
+                .dependency_graph
+                .find_instruction(thread_id, line_index)
+                .unwrap();
+            tso.exec_instruction(store).unwrap();
+        }
+
+        assert_eq!(3, tso.flush_thread(0));
+        assert!(tso.memory_subsystem.pending(0).is_empty());
+        let memory = &tso.memory_subsystem.memory;
+        assert_eq!(Value(3), memory.load("x"));
+        assert_eq!(Value(2), memory.load("y"));
+        assert_eq!(Value(0), memory.load("z"));
+        assert_eq!(1, tso.memory_subsystem.pending(1).len());
+        assert_eq!(0, tso.flush_thread(0));
+    }
+}
+
+#[test]
+fn test_run_register_ops_leaves_memory_operations_pending() {
+    for is_pso in [false, true] {
+        let programs = parse_programs_str(&[
+            "r1 = 1\nstore RLX r1 #x\nr2 = 2\nr3 = r2 * r2\nload RLX #y r4\nr5 = 7",
+            "r1 = 3\nr2 = 4\nstore RLX r1 #y",
+            "r1 = 1\nA: r2 = 2\nif r3 goto A",
+        ])
+        .unwrap();
+        let mut tso = TSO::new(programs, is_pso);
+        let options = |tso: &TSO| {
+            tso.options_detailed()
+                .iter()
+                .map(OptionInfo::mnemonic)
+                .collect::<HashSet<_>>()
+        };
+        let before = options(&tso);
+
+        // Assignments sharing a register with another pending instruction
+        // of their thread, or in a thread with a pending jump, are kept
+        assert_eq!((2, Ok(())), tso.run_register_ops());
+        assert_eq!(Value(7), tso.registers.load("r5", 0));
+        assert_eq!(Value(4), tso.registers.load("r2", 1));
+        let after = options(&tso);
+        assert_eq!(
+            HashSet::from(["t0:5".to_string(), "t1:1".to_string()]),
+            &before - &after
+        );
+        assert!(after.contains("t0:1") && after.contains("t0:4") && after.contains("t1:2"));
+        assert!(tso.memory_subsystem.pending(0).is_empty());
+        assert_eq!((0, Ok(())), tso.run_register_ops());
+
+        let assign = tso.dependency_graph.find_instruction(0, 2).unwrap();
+        tso.exec_instruction(assign).unwrap();
+        assert_eq!((1, Ok(())), tso.run_register_ops());
+        assert_eq!(Value(4), tso.registers.load("r3", 0));
+    }
+}
+
+#[test]
+fn test_run_register_ops_returns_the_error_stopping_it() {
+    let programs = parse_programs_str(&["r3 = 3\nr2 = r1 / r0\nr5 = 5"]).unwrap();
+    let mut tso = TSO::new(programs, false);
+    assert_eq!(
+        (
+            1,
+            Err(ExecutionError::DivisionByZero {
+                thread_id: 0,
+                line_index: 1
+            })
+        ),
+        tso.run_register_ops()
+    );
+    // Stops at the failing one, though the last is independent too
+    assert_eq!(Value(3), tso.registers.load("r3", 0));
+    assert!(tso.dependency_graph.find_instruction(0, 2).is_some());
+}
+
+#[test]
+fn test_observed_value_of_message_passing_loads() {
+    for is_pso in [false, true] {
+        let programs = parse_programs_str(&[
+            "r1 = 1\nstore RLX r1 #x\nr2 = 2\nstore REL r2 #f\nload RLX #f r3",
+            "load ACQ #f r1\nload RLX #x r2\nload RLX #y r3",
+        ])
+        .unwrap();
+        let mut tso = TSO::new(programs, is_pso);
+        let exec = |tso: &mut TSO, thread_id, line_index| {
+            let node = tso
+                .dependency_graph
+                .find_instruction(thread_id, line_index)
+                .unwrap();
+            tso.exec_instruction(node).unwrap();
+        };
+        let source = |id: &str| Some(id.to_string());
+
+        // Reads the flag from its own buffer before it propagates
+        for line_index in 0..5 {
+            exec(&mut tso, 0, line_index);
+        }
+        assert_eq!(Some((Value(2), source("0-3"))), tso.observed_value("0-4"));
+        assert_eq!(None, tso.observed_value("1-0"));
+
+        assert_eq!(2, tso.flush_thread(0));
+        for line_index in 0..3 {
+            exec(&mut tso, 1, line_index);
+        }
+        assert_eq!(Some((Value(2), source("0-3"))), tso.observed_value("1-0"));
+        assert_eq!(Some((Value(1), source("0-1"))), tso.observed_value("1-1"));
+        assert_eq!(Some((Value(0), None)), tso.observed_value("1-2"));
+        assert_eq!(None, tso.observed_value("0-1"));
+    }
+}
+
+#[test]
+fn test_observed_values_of_each_execution_and_read_modify_writes() {
+    let programs = parse_programs_str(&["r1 = 1\n\
+         L: r2 := fai RLX #x r1\n\
+         load RLX #x r3\n\
+         r4 := cas RLX #x r3 r1\n\
+         r5 := fetch_max RLX #x r3\n\
+         r6 r7 := cas2 RLX #x #y r5 r0 r1 r1"])
+    .unwrap();
+    let mut tso = TSO::new(programs, false);
+    let exec = |tso: &mut TSO, line_index| {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    };
+    let source = |id: &str| Some(id.to_string());
+
+    // Runs the fai and the load twice, as a jump back to `L` would
+    for line_index in 0..3 {
+        exec(&mut tso, line_index);
+    }
+    tso.revert_to_label("L".to_string());
+    for line_index in 1..6 {
+        exec(&mut tso, line_index);
+    }
+    assert_eq!(
+        &[(Value(0), None), (Value(1), source("0-1"))],
+        tso.observed_values("0-1")
+    );
+    assert_eq!(
+        &[(Value(1), source("0-1")), (Value(2), source("0-1"))],
+        tso.observed_values("0-2")
+    );
+    assert_eq!(Some((Value(2), source("0-1"))), tso.observed_value("0-2"));
+    assert_eq!(&[(Value(2), source("0-1"))], tso.observed_values("0-3"));
+    assert_eq!(&[(Value(1), source("0-3"))], tso.observed_values("0-4"));
+    // One read of each address of the cas2
+    assert_eq!(
+        &[(Value(2), source("0-4")), (Value(0), None)],
+        tso.observed_values("0-5")
+    );
+    assert!(tso.observed_values("0-0").is_empty());
+}
+
+#[test]
+fn test_store_of_unassigned_register_warns() {
+    let programs = || parse_programs_str(&["r1 = 1\nstore RLX r1 #x\nstore RLX r2 #y"]).unwrap();
+    let warning = "Warning: thread 0, line 2 stores r2 to #y, but r2 was never assigned";
+
+    let mut sc = SequentialConsistency::new(programs());
+    for expected in [vec![], vec![], vec![warning.to_string()]] {
+        let option = sc.get_instructions_to_exec()[0].clone();
+        sc.exec_instruction(option).unwrap();
+        assert_eq!(expected, sc.take_warnings());
+    }
+
+    let mut tso = TSO::new(programs(), false);
+    for line_index in 0..3 {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    assert_eq!(vec![warning.to_string()], tso.take_warnings());
+    assert!(tso.take_warnings().is_empty());
+
+    let mut initialized = SequentialConsistency::new(programs());
+    initialized.initialize(&Location::Register(Some(0), "r2".to_string()), 0);
+    for _ in 0..3 {
+        let option = initialized.get_instructions_to_exec()[0].clone();
+        initialized.exec_instruction(option).unwrap();
+    }
+    assert!(initialized.take_warnings().is_empty());
+}
+
+#[test]
+fn test_options_detailed_reports_thread_line_and_kind() {
+    let programs = parse_programs_str(&["store RLX r1 #x\nr2 = 1", "r1 = 2"]).unwrap();
+    let mut tso = TSO::new(programs.clone(), false);
+    let store = tso.dependency_graph.find_instruction(0, 0).unwrap();
+    tso.exec_instruction(store).unwrap();
+
+    let options = tso.options_detailed();
+    assert_eq!(tso.get_instructions_to_exec().len(), options.len());
+    let kinds = options
+        .iter()
+        .map(|option| (option.thread_id, option.line_index, option.is_propagate))
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        HashSet::from([(0, 0, true), (0, 1, false), (1, 0, false)]),
+        kinds
+    );
+    let propagate = options.iter().find(|option| option.is_propagate).unwrap();
+    assert_eq!("p0:0", propagate.mnemonic());
+    assert_eq!(
+        "Propagate for write (Thread 0, line 0: store RLX r1 #x)",
+        propagate.description
+    );
+
+    let mut sc = SequentialConsistency::new(programs);
+    let store = sc.get_instructions_to_exec()[0].clone();
+    sc.exec_instruction(store).unwrap();
+    let options = sc.options_detailed();
+    assert_eq!(
+        vec![(0, 1, false), (1, 0, false)],
+        options
+            .iter()
+            .map(|option| (option.thread_id, option.line_index, option.is_propagate))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!("Thread 1, line 0: r1 := 2", options[1].description);
+}
+
+#[test]
+fn test_registers_dump_lists_threads_in_order() {
+    let programs = parse_programs_str(&["r1 = 1", "r2 = 2", "r3 = 3"]).unwrap();
+    let mut sc = SequentialConsistency::new(programs);
+    for thread_id in [2, 0, 1] {
+        let option = sc
+            .get_instructions_to_exec()
+            .into_iter()
+            .find(|option| option.thread_id == thread_id)
+            .unwrap();
+        sc.exec_instruction(option).unwrap();
+    }
+    sc.registers
+        .registers
+        .insert(3, sc.registers.registers[&0].clone());
+    let threads = sc
+        .registers
+        .to_string()
+        .lines()
+        .filter(|line| line.starts_with("Thread"))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["Thread 0", "Thread 1", "Thread 2", "Thread 3"],
+        threads
+    );
+    assert!(sc
+        .registers
+        .to_string()
+        .starts_with("Thread 0\nr1: 1\n\nThread 1\nr2: 2\n"));
+}
+
+#[test]
+fn test_check_message_passing() {
+    let mp = |store_mode: &str, load_mode: &str| {
+        let programs = parse_programs_str(&[
+            &format!("store RLX r1 #data\nstore {} r1 #flag", store_mode),
+            &format!("load {} #flag r2\nload RLX #data r3", load_mode),
+        ])
+        .unwrap();
+        TSO::new(programs, false).check_message_passing()
+    };
+    let synchronized = MpReport {
+        store: "0-1".to_string(),
+        load: "1-0".to_string(),
+        flag: "#flag".to_string(),
+        data: vec!["#data".to_string()],
+        synchronized: true,
+    };
+    let unsynchronized = MpReport {
+        synchronized: false,
+        ..synchronized
+    };
+    assert_eq!(vec![unsynchronized.clone()], mp("REL", "ACQ"));
+    assert_eq!(vec![unsynchronized.clone()], mp("RLX", "RLX"));
+    assert_eq!(vec![unsynchronized], mp("REL", "RLX"));
+
+    let fenced = TSO::new(
+        single_file_to_instructions("etc/program_samples/mp_fence_rel_acq.txt".to_string()),
+        false,
+    );
+    let reports = fenced.check_message_passing();
+    assert_eq!(1, reports.len());
+    assert!(reports[0].synchronized);
+}
+
+#[test]
+fn test_is_reachable() {
+    let sb = sample_programs(&["sb_0", "sb_1"]);
+    assert!(is_reachable(
+        sb.clone(),
+        MemoryModel::TSO,
+        both_read_zero,
+        100
+    ));
+    assert!(!is_reachable(
+        sb.clone(),
+        MemoryModel::SC,
+        both_read_zero,
+        100
+    ));
+    assert!(is_reachable(
+        sb,
+        MemoryModel::SC,
+        |state| state.memory["x"] == 1 && state.memory["y"] == 1,
+        100
+    ));
+}
+
+#[test]
+fn test_loop_iterations_are_distinct_nodes() {
+    let programs = parse_programs_str(&["r0 = 1\nL: store RLX r1 #x\nif r0 goto L"]).unwrap();
+    let mut tso = TSO::new(programs, false);
+    // Store, jump back and store again while the first write is buffered
+    for line_index in [0, 1, 2, 1] {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+
+    let propagates = tso
+        .dependency_graph
+        .dfs_filter(|node| matches!(node, NodeType::Propagate(_)))
+        .iter()
+        .map(|node| node.borrow().id())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["prop_0-1", "prop_0-1@1"], propagates);
+}
+
+#[test]
+fn test_fingerprint_ignores_write_order() {
+    let run = |source: &str| {
+        let mut sc = SequentialConsistency::new(parse_programs_str(&[source, "r2 = 5"]).unwrap());
+        while let Some(option) = sc.get_instructions_to_exec().pop() {
+            sc.exec_instruction(option).unwrap();
+        }
+        sc.final_state()
+    };
+    let first = run("r1 = 1\nstore RLX r1 #x\nstore RLX r1 #y");
+    let second = run("r1 = 1\nstore RLX r1 #y\nstore RLX r1 #x");
+    assert_eq!(first, second);
+    assert_eq!("#x=1, #y=1, 0:r1=1, 1:r2=5", first.fingerprint());
+    assert_eq!(first.fingerprint(), second.fingerprint());
+    assert_ne!(
+        first.fingerprint(),
+        run("r1 = 1\nstore RLX r1 #x").fingerprint()
+    );
+}
+
+#[test]
+fn test_print_outputs_current_value() {
+    let programs = parse_programs_str(&["r1 = 7\nprint r1\nstore RLX r1 #x\nprint #x"]).unwrap();
+    let mut sc = SequentialConsistency::new(programs);
+    while let Some(option) = sc.get_instructions_to_exec().pop() {
+        sc.exec_instruction(option).unwrap();
+    }
+    assert_eq!(
+        vec!["Thread 0, line 1: r1 = 7", "Thread 0, line 3: #x = 7"],
+        sc.take_output()
+    );
+    assert!(sc.take_output().is_empty());
+
+    // Under TSO printing waits for nothing, and the thread sees its own
+    // buffered write before it is propagated
+    let programs = parse_programs_str(&["r1 = 7\nstore RLX r1 #x\nprint #x\nprint #x"]).unwrap();
+    let mut tso = TSO::new(programs, false);
+    for line_index in [3, 0, 1, 2] {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    assert_eq!(
+        vec!["Thread 0, line 3: #x = 0", "Thread 0, line 2: #x = 7"],
+        tso.take_output()
+    );
+    assert_eq!(Value(0), tso.memory_subsystem.memory.load("x"));
+}
+
+#[test]
+fn test_happens_before_relation() {
+    // r1 = 1; fence; store #x; fence; load #y in each thread
+    let mut tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    let relation = tso.dependency_graph.happens_before_relation();
+    assert!(relation.happens_before("0-1", "0-2"));
+    assert!(relation.happens_before("0-3", "0-4"));
+    // Transitively through both fences
+    assert!(relation.happens_before("0-0", "0-4"));
+    assert!(relation.happens_before("0-2", "0-4"));
+    assert!(!relation.happens_before("0-4", "0-0"));
+    assert!(!relation.happens_before("0-0", "0-0"));
+    assert!(!relation.happens_before("0-0", "1-4"));
+    assert!(!relation.happens_before("0-0", "9-9"));
+
+    // Executing the store adds its propagate, which the second fence waits for
+    let store = tso.dependency_graph.find_instruction(0, 0).unwrap();
+    tso.exec_instruction(store).unwrap();
+    let fence = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    tso.exec_instruction(fence).unwrap();
+    let store = tso.dependency_graph.find_instruction(0, 2).unwrap();
+    tso.exec_instruction(store).unwrap();
+    let relation = tso.dependency_graph.happens_before_relation();
+    assert!(relation.happens_before("prop_0-2", "0-4"));
+    assert!(!relation.happens_before("0-0", "0-4"));
+}
+
+const CAS2_PROGRAM: &str = "r1 = 1\nr2 = 2\nstore RLX r1 #x\nstore RLX r2 #y\nr5 = 5\nr6 = 6";
+
+#[test]
+fn test_cas2_writes_both_addresses_when_both_match() {
+    let program = format!("{}\no1 o2 := cas2 RLX #x #y r1 r2 r5 r6", CAS2_PROGRAM);
+    let programs = parse_programs_str(&[program.as_str()]).unwrap();
+    let mut sc = SequentialConsistency::new(programs.clone());
+    while let Some(option) = sc.get_instructions_to_exec().pop() {
+        sc.exec_instruction(option).unwrap();
+    }
+    assert_eq!(Value(5), sc.memory_subsystem.memory.load("x"));
+    assert_eq!(Value(6), sc.memory_subsystem.memory.load("y"));
+    assert_eq!(Value(1), sc.registers.load("o1", 0));
+    assert_eq!(Value(2), sc.registers.load("o2", 0));
+
+    // Under TSO both writes get a propagate, in buffer order
+    let mut tso = TSO::new(programs, false);
+    for line_index in 0..7 {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    let relation = tso.dependency_graph.happens_before_relation();
+    assert!(relation.happens_before("prop_0-6", "prop_0-6@1"));
+    while let Some(option) = tso.get_instructions_to_exec().pop() {
+        tso.exec_instruction(option).unwrap();
+    }
+    assert_eq!(Value(5), tso.memory_subsystem.memory.load("x"));
+    assert_eq!(Value(6), tso.memory_subsystem.memory.load("y"));
+    assert_eq!(Value(2), tso.registers.load("o2", 0));
+}
+
+#[test]
+fn test_cas2_writes_nothing_on_partial_mismatch() {
+    let program = format!("{}\no1 o2 := cas2 RLX #x #y r1 r1 r5 r6", CAS2_PROGRAM);
+    let programs = parse_programs_str(&[program.as_str()]).unwrap();
+    let mut sc = SequentialConsistency::new(programs.clone());
+    while let Some(option) = sc.get_instructions_to_exec().pop() {
+        sc.exec_instruction(option).unwrap();
+    }
+    assert_eq!(Value(1), sc.memory_subsystem.memory.load("x"));
+    assert_eq!(Value(2), sc.memory_subsystem.memory.load("y"));
+    assert_eq!(Value(1), sc.registers.load("o1", 0));
+    assert_eq!(Value(2), sc.registers.load("o2", 0));
+
+    // The graph doesn't order registers, so run the lines in program order
+    let mut tso = TSO::new(programs, false);
+    for line_index in 0..7 {
+        let node = tso
+            .dependency_graph
+            .find_instruction(0, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    while let Some(option) = tso.get_instructions_to_exec().pop() {
+        tso.exec_instruction(option).unwrap();
+    }
+    assert_eq!(Value(1), tso.memory_subsystem.memory.load("x"));
+    assert_eq!(Value(2), tso.memory_subsystem.memory.load("y"));
+    assert!(tso.memory_subsystem.pending(0).is_empty());
+}
+
+#[test]
+fn test_dot_legend_only_when_enabled() {
+    let mut tso = TSO::new(sample_programs(&["sb_0"]), false);
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    tso.exec_instruction(store).unwrap();
+
+    let plain = tso.dependency_graph.to_dot(false);
+    assert!(!plain.contains("Legend"));
+    assert!(!plain.contains("legend_"));
+
+    let with_legend = tso.dependency_graph.to_dot(true);
+    assert!(with_legend.contains("label=\"Legend\""));
+    assert!(with_legend.contains("legend_propagate[label=\"propagate\",shape=rectangle]"));
+    assert!(with_legend.contains("waits for the write to reach memory"));
+    // A cluster of its own next to the one of the thread
+    assert_eq!(1, plain.matches("subgraph cluster").count());
+    assert_eq!(2, with_legend.matches("subgraph cluster").count());
+}
+
+#[test]
+fn test_mode_less_accesses_order_like_their_default_mode() {
+    let src = "r1 = 1\nstore r1 #x\nload #y r2\nr3 = 1";
+    let relaxed = TSO::new(parse_programs_str(&[src]).unwrap(), false);
+    assert_eq!(Vec::<usize>::new(), dependency_lines(&relaxed, 0, 1));
+    assert_eq!(Vec::<usize>::new(), dependency_lines(&relaxed, 0, 3));
+
+    let options = ParseOptions {
+        default_mode: MemoryAccessMode::SeqCst,
+    };
+    let seq_cst = TSO::new(options.parse_programs_str(&[src]).unwrap(), false);
+    // The later instructions wait for a sequentially consistent store, a
+    // load waits for the earlier ones
+    assert_eq!(vec![0, 1], dependency_lines(&seq_cst, 0, 2));
+    assert_eq!(vec![1], dependency_lines(&seq_cst, 0, 3));
+}
+
+#[test]
+fn test_eager_propagate_hides_store_buffering() {
+    // Local fences keep each thread in program order without waiting for
+    // propagates, so only the store buffer lets both loads read zero
+    let programs = parse_programs_str(&[
+        "r1 = 1\nfence REL_ACQ local\nstore RLX r1 #x\nfence REL_ACQ local\nload RLX #y r2",
+        "r1 = 1\nfence REL_ACQ local\nstore RLX r1 #y\nfence REL_ACQ local\nload RLX #x r2",
+    ])
+    .unwrap();
+    let lazy = TSO::new(programs.clone(), false).explore_all(100).found;
+    let eager = TSO::new(programs, false)
+        .with_eager_propagate(true)
+        .explore_all(100)
+        .found;
+    assert!(lazy.iter().any(both_read_zero));
+    assert!(!eager.iter().any(both_read_zero));
+    // Eager propagation only leaves out interleavings
+    assert!(!eager.is_empty());
+    assert!(eager.is_subset(&lazy));
+}
+
+#[test]
+fn test_coherence_order_records_committed_writes() {
+    let programs = parse_programs_str(&[
+        "r1 = 1\nr2 = 3\nstore RLX r1 #x\nstore RLX r2 #x",
+        "r1 = 2\nstore RLX r1 #x",
+    ])
+    .unwrap();
+    let expected = [(0, Value(1)), (1, Value(2)), (0, Value(3))];
+
+    let mut sc = SequentialConsistency::new(programs.clone());
+    for thread_id in [0, 0, 0, 1, 1, 0] {
+        let option = sc
+            .get_instructions_to_exec()
+            .into_iter()
+            .find(|option| option.thread_id == thread_id)
+            .unwrap();
+        sc.exec_instruction(option).unwrap();
+    }
+    assert_eq!(&expected, sc.memory_subsystem.coherence_order("x"));
+    assert!(sc.memory_subsystem.coherence_order("y").is_empty());
+
+    // Under TSO writes join the order when they propagate, not when issued
+    let mut tso = TSO::new(programs, false);
+    for (thread_id, line_index) in [(0, 0), (0, 1), (0, 2), (0, 3), (1, 0), (1, 1)] {
+        let node = tso
+            .dependency_graph
+            .find_instruction(thread_id, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    assert!(tso.memory_subsystem.coherence_order("x").is_empty());
+    let propagate = |tso: &mut TSO, thread_id: usize| {
+        let node = tso
+            .get_instructions_to_exec()
+            .into_iter()
+            .find(|node| {
+                matches!(&node.borrow().instruction,
+                    NodeType::Propagate(propagate) if propagate.associated_write.thread_id == thread_id)
+            })
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    };
+    propagate(&mut tso, 0);
+    propagate(&mut tso, 1);
+    propagate(&mut tso, 0);
+    assert_eq!(&expected, tso.memory_subsystem.coherence_order("x"));
+}
+
+/// Runs the single-threaded program in program order under both engines and
+/// returns the first error of each.
+fn first_runtime_errors(src: &str) -> [ExecutionError; 2] {
+    let programs = parse_programs_str(&[src]).unwrap();
+    let mut sc = SequentialConsistency::new(programs.clone());
+    let sc_err = loop {
+        let option = sc.get_instructions_to_exec()[0].clone();
+        if let Err(err) = sc.exec_instruction(option) {
+            break err;
+        }
+    };
+    let mut tso = TSO::new(programs, false);
+    let tso_err = loop {
+        let option = tso
+            .get_instructions_to_exec()
+            .into_iter()
+            .min_by_key(|node| match &node.borrow().instruction {
+                NodeType::Instruction(instruction) => instruction.line_index,
+                NodeType::Propagate(_) => usize::MAX,
+            })
+            .unwrap();
+        if let Err(err) = tso.exec_instruction(option) {
+            break err;
+        }
+    };
+    [sc_err, tso_err]
+}
+
+#[test]
+fn test_division_by_zero_is_an_error() {
+    let expected = ExecutionError::DivisionByZero {
+        thread_id: 0,
+        line_index: 1,
+    };
+    for err in first_runtime_errors("r1 = 1\nr3 = r1 / r2") {
+        assert_eq!(expected, err);
+        assert_eq!("Thread 0, line 1: division by zero", err.to_string());
+    }
+}
+
+#[test]
+fn test_exploration_counts_executions_failing_at_runtime() {
+    // Divides by zero unless the load sees the store
+    let programs =
+        parse_programs_str(&["r1 = 1\nstore RLX r1 #x", "load RLX #x r2\nr3 = r1 / r2"]).unwrap();
+    let division_by_zero = "Thread 1, line 1: division by zero".to_string();
+    let sc = SequentialConsistency::new(programs.clone());
+    let tso = TSO::new(programs, false);
+    for (outcomes, failed) in [
+        (sc.explore_counts(10), 5),
+        (sc.explore_counts_parallel(10, 4), 5),
+        (tso.explore_counts(10), 34),
+        (tso.explore_counts_parallel(10, 4), 34),
+    ] {
+        assert!(outcomes
+            .found
+            .keys()
+            .all(|state| state.registers[&1]["r2"] == 1));
+        assert_eq!(
+            BTreeMap::from([(division_by_zero.clone(), failed)]),
+            outcomes.errors
+        );
+        assert_eq!(failed, outcomes.failed());
+    }
+    // Once per distinct state failing, the load before none, one or both
+    // instructions of thread 0
+    assert_eq!(3, sc.explore_all(10).errors[&division_by_zero]);
+
+    let found = sc.find_final_state(10, &|state| state.registers[&1]["r2"] == 0);
+    assert_eq!(None, found.found);
+    assert_eq!(3, found.failed());
+}
+
+#[test]
+fn test_jump_to_missing_label_is_an_error() {
+    // Both a non-zero and a zero condition, as the engines jump on different
+    // ones
+    for err in first_runtime_errors("r1 = 1\nif r1 goto L9\nif r2 goto L9") {
+        match err {
+            ExecutionError::MissingLabel {
+                thread_id: 0,
+                ref label,
+                ..
+            } if label == "L9" => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert!(err.to_string().ends_with("no label 'L9' to jump to"));
+    }
+}
+
+#[test]
+fn test_unsupported_instruction_is_an_error() {
+    let expected = ExecutionError::UnsupportedInstruction {
+        thread_id: 0,
+        line_index: 0,
+        instruction: Box::new(Instruction::AssignConst(Reference::Memory("x".into()), 1)),
+    };
+    for err in first_runtime_errors("#x = 1") {
+        assert_eq!(expected, err);
+        assert_eq!(
+            "Thread 0, line 0: unsupported instruction '#x := 1'",
+            err.to_string()
+        );
+    }
+}
+
+/// Runs the program of one thread in program order from `#x = 5` under
+/// both engines, returning `#x` and `r3` of each, or the first error.
+fn fai_results(src: &str, mode: ArithMode) -> [Result<(Value, Value), ExecutionError>; 2] {
+    let programs = parse_programs_str(&[src]).unwrap();
+    let x = Location::Memory("x".to_string());
+    let mut sc = SequentialConsistency::new(programs.clone()).with_arith_mode(mode);
+    let mut tso = TSO::new(programs, false).with_arith_mode(mode);
+    sc.initialize(&x, 5);
+    let sc_result = (|| {
+        while let Some(option) = sc.get_instructions_to_exec().first().cloned() {
+            sc.exec_instruction(option)?;
+        }
+        Ok((
+            sc.memory_subsystem.memory.load("x"),
+            sc.registers.load("r3", 0),
+        ))
+    })();
+    tso.initialize(&x, 5);
+    let tso_result = (|| {
+        for line_index in 0..tso.programs[0].len() {
+            let node = tso
+                .dependency_graph
+                .find_instruction(0, line_index)
+                .unwrap();
+            tso.exec_instruction(node)?;
+        }
+        tso.flush_thread(0);
+        Ok((
+            tso.memory_subsystem.memory.load("x"),
+            tso.registers.load("r3", 0),
+        ))
+    })();
+    [sc_result, tso_result]
+}
+
+#[test]
+fn test_fai_with_negative_increment_decrements() {
+    // r2 = 0 - 2 is -2
+    let src = "r1 = 2\nr2 = r0 - r1\nr3 := fai RLX #x r2";
+    for result in fai_results(src, ArithMode::Wrapping) {
+        assert_eq!(Ok((Value(3), Value(5))), result);
+    }
+    for result in fai_results("r3 := fai RLX #x r0", ArithMode::Wrapping) {
+        assert_eq!(Ok((Value(5), Value(5))), result);
+    }
+}
+
+#[test]
+fn test_fai_overflow_follows_arith_mode() {
+    let src = "r1 = 9223372036854775807\nr3 := fai RLX #x r1";
+    for result in fai_results(src, ArithMode::Wrapping) {
+        assert_eq!(Ok((Value(i64::MIN + 4), Value(5))), result);
+    }
+    for result in fai_results(src, ArithMode::Saturating) {
+        assert_eq!(Ok((Value(i64::MAX), Value(5))), result);
+    }
+    for result in fai_results(src, ArithMode::Checked) {
+        assert_eq!(
+            Err(ExecutionError::Arithmetic {
+                thread_id: 0,
+                line_index: 1
+            }),
+            result
+        );
+    }
+}
+
+#[test]
+fn test_fetch_min_max() {
+    let cases = [
+        ("r1 = 9\nr3 := fetch_max RLX #x r1", 9),
+        ("r1 = 3\nr3 := fetch_max SEQ_CST #x r1", 5),
+        ("r1 = 3\nr3 := fetch_min RLX #x r1", 3),
+        ("r1 = 9\nr3 := fetch_min REL #x r1", 5),
+    ];
+    for (src, x) in cases {
+        for result in fai_results(src, ArithMode::Wrapping) {
+            assert_eq!(Ok((Value(x), Value(5))), result, "{}", src);
+        }
+    }
+}
+
+#[test]
+fn test_fetch_min_max_compare_signed() {
+    // r2 = 0 - 2 is -2
+    let src = "r1 = 2\nr2 = r0 - r1\nr3 := fetch_min RLX #x r2";
+    for result in fai_results(src, ArithMode::Wrapping) {
+        assert_eq!(Ok((Value(-2), Value(5))), result);
+    }
+    let src = "r1 = 2\nr2 = r0 - r1\nr3 := fetch_max RLX #x r2";
+    for result in fai_results(src, ArithMode::Wrapping) {
+        assert_eq!(Ok((Value(5), Value(5))), result);
+    }
+}
+
+#[test]
+fn test_explain_lists_accessed_locations() {
+    let mut tso = TSO::new(sample_programs(&["sb_0"]), false);
+    let assign = tso.dependency_graph.find_instruction(0, 0).unwrap();
+    tso.exec_instruction(assign).unwrap();
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    let explanation = tso.explain(store.clone());
+    assert!(explanation.starts_with("Thread 0, line 1: store RLX r1 #x\nStores r1 to #x"));
+    assert!(explanation.contains("\nReads: r1\nWrites: #x (into the store buffer)\n"));
+    assert!(explanation.contains("  buffer 0: #x := 1\n"));
+    assert!(explanation.contains("Allowed now: yes"));
+
+    tso.exec_instruction(store).unwrap();
+    let load = tso.dependency_graph.find_instruction(0, 2).unwrap();
+    assert!(tso.explain(load).contains("\nReads: #y\nWrites: r2\n"));
+
+    let sc =
+        SequentialConsistency::new(parse_programs_str(&["r1 := cas SEQ_CST #x r2 r3"]).unwrap());
+    let cas = sc.get_instructions_to_exec()[0].clone();
+    let explanation = sc.explain(cas);
+    assert!(explanation.contains("\nReads: r2, r3, #x\nWrites: r1, #x\n"));
+    assert!(explanation.contains("next instruction of thread 0"));
+}
+
+#[test]
+fn test_explain_reports_pending_dependencies() {
+    let tso = TSO::new(sample_programs(&["sb_fenced_0"]), false);
+    let load = tso
+        .dependency_graph
+        .find_instruction(0, tso.programs[0].len() - 1)
+        .unwrap();
+    let explanation = tso.explain(load);
+    assert!(explanation.contains("Allowed now: no, it waits for: "));
+    assert!(explanation.contains("  Fails: "));
+}
+
+fn propagate_ids(tso: &TSO) -> Vec<String> {
+    tso.dependency_graph
+        .nodes()
+        .iter()
+        .filter(|node| matches!(node.borrow().instruction, NodeType::Propagate(_)))
+        .map(|node| node.borrow().id())
+        .collect()
+}
+
+#[test]
+fn test_store_adds_exactly_one_propagate() {
+    let mut tso = TSO::new(sample_programs(&["sb_0"]), false);
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    tso.exec_instruction(store.clone()).unwrap();
+    assert_eq!(vec!["prop_0-1"], propagate_ids(&tso));
+
+    // A removed store can't be executed, and so propagated, again
+    assert!(matches!(
+        tso.exec_instruction(store),
+        Err(ExecutionError::Graph(GraphError::NodeNotFound(_)))
+    ));
+    assert_eq!(vec!["prop_0-1"], propagate_ids(&tso));
+}
+
+#[test]
+fn test_second_propagate_of_a_write_is_rejected() {
+    let mut tso = TSO::new(sample_programs(&["sb_0"]), false);
+    let store = tso.dependency_graph.find_instruction(0, 1).unwrap();
+    let write = tso.programs[0][1].clone();
+    let x = Reference::Memory("x".into());
+    let y = Reference::Memory("y".into());
+    for propagates in [
+        vec![(write.clone(), x.clone()), (write.clone(), x.clone())],
+        vec![(write.clone(), y)],
+    ] {
+        let err = tso
+            .dependency_graph
+            .remove_node(store.clone(), propagates, false)
+            .unwrap_err();
+        assert!(matches!(err, GraphError::UnexpectedPropagate(_)));
+        // The graph is left untouched
+        assert!(tso.dependency_graph.find_instruction(0, 1).is_some());
+        assert!(propagate_ids(&tso).is_empty());
+    }
+    let err = tso
+        .dependency_graph
+        .remove_node(store, vec![(write.clone(), x.clone()), (write, x)], false)
+        .unwrap_err();
+    assert_eq!("Unexpected propagate prop_0-1 to #x", err.to_string());
+}
+
+/// Thread `thread_id` of a long program using every kind of memory
+/// instruction.
+fn long_program(thread_id: usize) -> String {
+    (0..20)
+        .map(|block| {
+            format!(
+                "r1 = {block}\n\
+                 store RLX r1 #x\n\
+                 load ACQ #y r2\n\
+                 r3 := cas SEQ_CST #z r2 r1\n\
+                 r4 := fai REL #x r1\n\
+                 if r1 == r1 goto S{thread_id}_{block}\n\
+                 r5 := fetch_min RLX #y r4\n\
+                 fence REL_ACQ\n\
+                 S{thread_id}_{block}: store REL r4 #y\n\
+                 r6 = r4 + r1\n\
+                 print r6"
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_long_run_keeps_its_trace() {
+    // Recorded when forward jumps started skipping their lines under TSO
+    let expected = std::fs::read_to_string("etc/traces/long_run.txt").unwrap();
+    let programs = parse_programs_str(&[&long_program(0), &long_program(1)]).unwrap();
+    let mut tso = TSO::new(programs, false).with_policy(Box::new(Random::new(7)));
+    assert!(tso.run(10_000).is_some());
+    assert_eq!(expected, tso.trace_as_swimlane());
+}
+
+#[test]
+fn test_tracked_leaves_match_a_full_scan() {
+    let writer = "r1 = 1\nstore RLX r1 #x\nr2 r3 := cas2 SEQ_CST #x #y r1 r1 r4 r4\n\
+                  store REL r1 #y\nfence REL\nr4 := fai ACQ #x r1\nstore RLX r4 #z";
+    let reader = "load ACQ #y r1\nfence ACQ\nload RLX #x r2\nstore RLX r2 #y\n\
+                  r3 := fetch_max REL #z r2\nload SEQ_CST #z r4";
+    // Jumping back re-adds the loop's nodes and their dependencies
+    let looping = "r2 = 1\nL: r1 := fai RLX #c r2\nstore REL r1 #x\nfence SEQ_CST\n\
+                   if r1 < r2 goto L";
+    let cases = [
+        ([writer, reader], false),
+        ([writer, reader], true),
+        ([looping, reader], false),
+    ];
+    for (seed, (programs, is_pso)) in cases.into_iter().enumerate() {
+        let mut tso = TSO::new(parse_programs_str(&programs).unwrap(), is_pso);
+        let policy = Random::new(seed as u64);
+        for _ in 0..100 {
+            let graph = &tso.dependency_graph;
+            let scanned = graph
+                .nodes()
+                .iter()
+                .filter(|node| node.borrow().depends_on.is_empty())
+                .map(|node| node.borrow().id())
+                .collect::<Vec<_>>();
+            let options = tso.get_instructions_to_exec();
+            let leaves = options
+                .iter()
+                .map(|node| node.borrow().id())
+                .collect::<Vec<_>>();
+            assert_eq!(scanned, leaves);
+            match policy.choose(&options) {
+                Some(index) => tso.exec_instruction(options[index].clone()).unwrap(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parallel_explore_matches_sequential() {
+    let writer = "r1 = 1\nstore RLX r1 #x\nr2 := fai ACQ #z r1";
+    let reader = "load RLX #x r2\nr3 := fetch_max REL #z r2";
+    // Jumping back replays propagates whose writes already left the buffer
+    let looping = "r2 = 1\nL: r1 := fai RLX #c r2\nstore REL r1 #x\nif r2 <= r1 goto L";
+    for (programs, step_limit) in [([writer, reader], 100), ([looping, reader], 6)] {
+        let programs = parse_programs_str(&programs).unwrap();
+        let sc = SequentialConsistency::new(programs.clone()).with_mem_default(1);
+        let tso = TSO::new(programs.clone(), false)
+            .with_mem_default(1)
+            .with_eager_propagate(true);
+        let pso = TSO::new(programs, true);
+        let expected = (
+            sc.explore_counts(step_limit),
+            tso.explore_counts(step_limit),
+            pso.explore_counts(step_limit),
+        );
+        // Each worker skips only the states it counted itself, so the
+        // visited states depend on the jobs
+        let counted =
+            |outcomes: Exploration<HashMap<FinalState, usize>>| (outcomes.found, outcomes.errors);
+        for jobs in [2, 8] {
+            assert_eq!(
+                counted(expected.0.clone()),
+                counted(sc.explore_counts_parallel(step_limit, jobs))
+            );
+            assert_eq!(
+                counted(expected.1.clone()),
+                counted(tso.explore_counts_parallel(step_limit, jobs))
+            );
+            assert_eq!(
+                counted(expected.2.clone()),
+                counted(pso.explore_counts_parallel(step_limit, jobs))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_pruned_explore_keeps_every_outcome() {
+    let unpruned = |counts: HashMap<FinalState, usize>| counts.into_keys().collect::<HashSet<_>>();
+    // Every interleaving of the independent steps reaches one of few states
+    let commuting = [
+        "r1 = 1\nstore RLX r1 #x\nload RLX #y r2",
+        "r1 = 2\nstore RLX r1 #y\nload RLX #x r2",
+    ];
+    let looping = "r2 = 1\nL: r1 := fai RLX #c r2\nstore REL r1 #x\nif r2 <= r1 goto L";
+    for (programs, step_limit) in [(commuting, 100), ([looping, "load RLX #x r2"], 8)] {
+        let programs = parse_programs_str(&programs).unwrap();
+        let sc = SequentialConsistency::new(programs.clone());
+        let tso = TSO::new(programs.clone(), false).with_eager_propagate(true);
+        let pso = TSO::new(programs, true);
+        let outcomes = sc.explore_all(step_limit).found;
+        assert!(!outcomes.is_empty());
+        assert_eq!(unpruned(sc.explore_counts(step_limit).found), outcomes);
+        assert_eq!(
+            unpruned(tso.explore_counts(step_limit).found),
+            tso.explore_all(step_limit).found
+        );
+        assert_eq!(
+            unpruned(pso.explore_counts(step_limit).found),
+            pso.explore_all(step_limit).found
+        );
+    }
+
+    // Pruning skips the states the commuting steps and propagates reach in
+    // another order, so fewer are visited than there are interleavings
+    let programs = parse_programs_str(&commuting).unwrap();
+    let tso = TSO::new(programs.clone(), false);
+    let pso = TSO::new(programs, true);
+    let pruned = |outcomes: Exploration<HashMap<FinalState, usize>>| {
+        outcomes.visited < outcomes.found.values().sum::<usize>()
+    };
+    assert!(pruned(tso.explore_counts(100)));
+    assert!(pruned(pso.explore_counts(100)));
+    // Counting skips a state only when reached with as many steps left
+    assert!(tso.explore_all(100).visited <= tso.explore_counts(100).visited);
+
+    // Depth first, the states before the last step are first reached the
+    // longer way through the first thread, with no steps left
+    let shortcut = parse_programs_str(&[
+        "r5 = 1\nload RLX #f r1\nif r1 == r5 goto A\nr4 = 1\nA: r4 = 1\nr1 = 0\nr5 = 1",
+        "r1 = 1\nstore RLX r1 #f\nr2 = 2\nr3 = 3",
+    ])
+    .unwrap();
+    let sc = SequentialConsistency::new(shortcut);
+    let outcomes = sc.explore_all(10).found;
+    assert_eq!(1, outcomes.len());
+    assert_eq!(unpruned(sc.explore_counts(10).found), outcomes);
+}
+
+/// `to_dot` with every cluster drawn black instead of a random color.
+fn dot_in_black(tso: &TSO) -> String {
+    let dot = tso.dependency_graph.to_dot(false);
+    let mut clusters = dot.split("{color=");
+    let mut black = clusters.next().unwrap().to_string();
+    for cluster in clusters {
+        black.push_str("{color=black");
+        black.push_str(&cluster[cluster.find(';').unwrap()..]);
+    }
+    black
+}
+
+#[test]
+fn test_dot_of_many_threads_keeps_its_output() {
+    let mut tso = TSO::new(
+        sample_programs(&["sb_fenced_0", "sb_fenced_1", "fifo_writer", "fifo_reader"]),
+        false,
+    );
+    // Pending propagates, waited for by later instructions of their thread
+    for (thread_id, line_index) in [(0, 0), (0, 1), (0, 2), (2, 0), (2, 1), (2, 2), (2, 3)] {
+        let node = tso
+            .dependency_graph
+            .find_instruction(thread_id, line_index)
+            .unwrap();
+        tso.exec_instruction(node).unwrap();
+    }
+    let expected = std::fs::read_to_string("etc/graphs/many_threads.dot").unwrap();
+    assert_eq!(expected, dot_in_black(&tso));
+}
@@ -0,0 +1,48 @@
+use isa_interpreter::litmus::LitmusTest;
+use isa_interpreter::{Error, Location, MemoryModel};
+
+use pretty_assertions::assert_eq;
+
+#[test]
+fn test_parse_litmus_file() {
+    let test = LitmusTest::from_file("etc/litmus/sb.litmus").unwrap();
+    assert_eq!("SB", test.name);
+    assert_eq!(2, test.programs.len());
+    assert_eq!(2, test.programs[1].len());
+    assert_eq!(1, test.programs[1][1].thread_id);
+    assert_eq!(1, test.programs[1][1].line_index);
+    assert_eq!(
+        vec![
+            (Location::Memory("x".to_string()), 0),
+            (Location::Memory("y".to_string()), 0),
+            (Location::Register(Some(0), "r1".to_string()), 1),
+            (Location::Register(Some(1), "r1".to_string()), 1),
+        ],
+        test.init
+    );
+    assert_eq!(2, test.condition.constraints.len());
+}
+
+#[test]
+fn test_run_litmus_file() {
+    let test = LitmusTest::from_file("etc/litmus/sb.litmus").unwrap();
+    assert!(test.check(MemoryModel::SC, 100).is_none());
+    assert!(test.check(MemoryModel::TSO, 100).is_some());
+}
+
+#[test]
+fn test_litmus_condition_on_unwritten_location() {
+    let src = "ISA Z\n{ 0:r1=1; }\n P0 ;\n store RLX r1 #x ;\nexists (x=1 /\\ y=0)";
+    let test = src.parse::<LitmusTest>().unwrap();
+    let state = test.check(MemoryModel::SC, 10).unwrap();
+    assert!(!state.memory.contains_key("y"));
+}
+
+#[test]
+fn test_litmus_unsupported_instruction() {
+    let src = "X86 SB\n{ x=0; }\n P0 ;\n MOV [x],$1 ;\nexists (x=1)";
+    match src.parse::<LitmusTest>() {
+        Err(Error::InvalidLitmus(message)) => assert!(message.contains("'MOV [x],$1' in P0")),
+        other => panic!("Expected an unsupported instruction error, got {:?}", other),
+    }
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "serde")]
+
+use isa_interpreter::{ArithCommand, Instruction, LabeledInstruction, MemoryAccessMode, Reference};
+
+use pretty_assertions::assert_eq;
+
+#[test]
+fn test_instruction_round_trip() {
+    let sources = [
+        "x = 1",
+        "x = r1 + r2",
+        "if r1 goto L5",
+        "if r1 <= r2 goto L5",
+        "load SEQ_CST #x r2",
+        "store RLX r1 #x",
+        "r1 := cas REL #x r3 r4",
+        "r1 := fai ACQ #x r3",
+        "r1 := fetch_min REL #x r3",
+        "fence REL_ACQ",
+        "fence REL #x",
+        "nop",
+    ];
+    for source in sources {
+        let instruction = source.parse::<Instruction>().unwrap();
+        let json = serde_json::to_string(&instruction).unwrap();
+        assert_eq!(format!("\"{}\"", source), json);
+        assert_eq!(
+            instruction,
+            serde_json::from_str::<Instruction>(&json).unwrap()
+        );
+    }
+    assert!(serde_json::from_str::<Instruction>("\"x = 1x\"").is_err());
+}
+
+#[test]
+fn test_parts_round_trip() {
+    let reference = Reference::Memory("x".into());
+    assert_eq!("\"#x\"", serde_json::to_string(&reference).unwrap());
+    assert_eq!(
+        reference,
+        serde_json::from_str::<Reference>("\"#x\"").unwrap()
+    );
+    assert_eq!(
+        ArithCommand::Mul,
+        serde_json::from_str::<ArithCommand>(&serde_json::to_string(&ArithCommand::Mul).unwrap())
+            .unwrap()
+    );
+    assert_eq!(
+        "\"REL_ACQ\"",
+        serde_json::to_string(&MemoryAccessMode::RelAcq).unwrap()
+    );
+    assert!(serde_json::from_str::<MemoryAccessMode>("\"+\"").is_err());
+
+    let labeled = LabeledInstruction::from_line("L1: store REL r1 #x", 3, 1).unwrap();
+    let json = serde_json::to_string(&labeled).unwrap();
+    assert_eq!(
+        r##"{"label":"L1","instruction":"store REL r1 #x","line_index":3,"thread_id":1}"##,
+        json
+    );
+    assert_eq!(labeled, serde_json::from_str(&json).unwrap());
+}
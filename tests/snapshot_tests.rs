@@ -0,0 +1,62 @@
+mod common;
+
+use isa_interpreter::{Location, SequentialConsistency, TSO};
+
+use common::sample_programs;
+use pretty_assertions::assert_eq;
+
+fn snapshot_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!(
+            "isa_interpreter_{}_{}.snapshot",
+            name,
+            std::process::id()
+        ))
+        .to_string_lossy()
+        .to_string()
+}
+
+fn tso_options(tso: &TSO) -> Vec<String> {
+    tso.get_instructions_to_exec()
+        .iter()
+        .map(|node| node.borrow().instruction.to_string())
+        .collect()
+}
+
+#[test]
+fn test_tso_snapshot_round_trip() {
+    let mut tso = TSO::new(sample_programs(&["sb_fenced_0", "sb_fenced_1"]), false);
+    tso.initialize(&Location::Memory("y".to_string()), 2);
+    for _ in 0..4 {
+        let option = tso.get_instructions_to_exec()[0].clone();
+        tso.exec_instruction(option).unwrap();
+    }
+    let path = snapshot_path("tso");
+    tso.save_state(&path).unwrap();
+    let restored = TSO::load_state(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(tso_options(&tso), tso_options(&restored));
+    assert_eq!(tso.final_state(), restored.final_state());
+    assert_eq!(tso.explore_counts(100), restored.explore_counts(100));
+}
+
+#[test]
+fn test_sc_snapshot_round_trip() {
+    let mut sc = SequentialConsistency::new(sample_programs(&["sb_0", "sb_1"]));
+    for _ in 0..3 {
+        let option = sc.get_instructions_to_exec()[0].clone();
+        sc.exec_instruction(option).unwrap();
+    }
+    let path = snapshot_path("sc");
+    sc.save_state(&path).unwrap();
+    let restored = SequentialConsistency::load_state(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        sc.get_instructions_to_exec(),
+        restored.get_instructions_to_exec()
+    );
+    assert_eq!(sc.explore_counts(100), restored.explore_counts(100));
+    assert!(TSO::load_state(&path).is_err());
+}
@@ -1,22 +1,44 @@
-use isa_interpreter::{ArithCommand, Instruction, MemoryAccessMode, Reference};
+use isa_interpreter::{
+    grammar, mapped_programs_to_instructions, missing_labels, parse_memory_init, parse_program_str,
+    parse_programs_str, parse_thread_mapping, programs_to_instructions, reachable_lines,
+    referenced_memory, referenced_registers, single_file_to_instructions, syntax_errors,
+    ArithCommand, CmpCommand, Command, Error, FenceScope, Instruction, LabeledInstruction, Limits,
+    Location, Memory, MemoryAccessMode, MinMax, ParseOptions, Reference, SymbolId, TokenKind,
+    Value,
+};
+use std::collections::{BTreeSet, HashSet};
 
 use pretty_assertions::assert_eq;
 
 #[test]
 fn test_assign_const() {
     let instr = "x = 1";
-    let expected = Instruction::AssignConst(Reference::Register("x".to_string()), 1);
+    let expected = Instruction::AssignConst(Reference::Register("x".into()), 1);
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
 
+#[test]
+fn test_hex_and_binary_literals() {
+    let assign = |value| Instruction::AssignConst(Reference::Register("r1".into()), value);
+    assert_eq!(assign(255), "r1 = 0xFF".parse::<Instruction>().unwrap());
+    assert_eq!(assign(5), "r1 = 0b101".parse::<Instruction>().unwrap());
+    assert_eq!(assign(0), "r1 = 0x0".parse::<Instruction>().unwrap());
+    for invalid in ["r1 = 0xZZ", "r1 = 0x", "r1 = 0b102", "r1 = 0x+1"] {
+        assert!(matches!(
+            invalid.parse::<Instruction>(),
+            Err(Error::InvalidCommand(_))
+        ));
+    }
+}
+
 #[test]
 fn test_binary_op() {
     let instr = "x = r1 + r2";
     let expected = Instruction::AssignOperation(
-        Reference::Register("x".to_string()),
-        Reference::Register("r1".to_string()),
+        Reference::Register("x".into()),
+        Reference::Register("r1".into()),
         ArithCommand::Add,
-        Reference::Register("r2".to_string()),
+        Reference::Register("r2".into()),
     );
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
@@ -24,18 +46,40 @@ fn test_binary_op() {
 #[test]
 fn test_conditional_jump() {
     let instr = "if r1 goto L5";
-    let expected =
-        Instruction::ConditionalJump(Reference::Register("r1".to_string()), "L5".to_string());
+    let expected = Instruction::ConditionalJump(Reference::Register("r1".into()), "L5".to_string());
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
 
+#[test]
+fn test_conditional_jump_cmp() {
+    for (source, cmp) in [
+        ("<", CmpCommand::Lt),
+        ("<=", CmpCommand::Le),
+        (">", CmpCommand::Gt),
+        (">=", CmpCommand::Ge),
+        ("==", CmpCommand::Eq),
+        ("!=", CmpCommand::Ne),
+    ] {
+        let instr = format!("if r1 {} r2 goto L5", source);
+        let expected = Instruction::CondJumpCmp(
+            Reference::Register("r1".into()),
+            cmp,
+            Reference::Register("r2".into()),
+            "L5".to_string(),
+        );
+        assert_eq!(expected, instr.parse::<Instruction>().unwrap());
+        assert_eq!(instr, expected.to_source());
+    }
+    assert!("if r1 < goto L5".parse::<Instruction>().is_err());
+}
+
 #[test]
 fn test_load() {
     let instr = "load SEQ_CST #r1 r2";
     let expected = Instruction::Load(
         MemoryAccessMode::SeqCst,
-        Reference::Memory("r1".to_string()),
-        Reference::Register("r2".to_string()),
+        Reference::Memory("r1".into()),
+        Reference::Register("r2".into()),
     );
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
@@ -45,8 +89,8 @@ fn test_store() {
     let instr = "store RLX r1 #r2";
     let expected = Instruction::Store(
         MemoryAccessMode::Rlx,
-        Reference::Register("r1".to_string()),
-        Reference::Memory("r2".to_string()),
+        Reference::Register("r1".into()),
+        Reference::Memory("r2".into()),
     );
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
@@ -55,11 +99,11 @@ fn test_store() {
 fn test_cas() {
     let instr = "r1 := cas REL #r2 r3 r4";
     let expected = Instruction::Cas(
-        Reference::Register("r1".to_string()),
+        Reference::Register("r1".into()),
         MemoryAccessMode::Rel,
-        Reference::Memory("r2".to_string()),
-        Reference::Register("r3".to_string()),
-        Reference::Register("r4".to_string()),
+        Reference::Memory("r2".into()),
+        Reference::Register("r3".into()),
+        Reference::Register("r4".into()),
     );
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
@@ -68,17 +112,737 @@ fn test_cas() {
 fn test_fai() {
     let instr = "r1 := fai ACQ #r2 r3";
     let expected = Instruction::Fai(
-        Reference::Register("r1".to_string()),
+        Reference::Register("r1".into()),
         MemoryAccessMode::Acq,
-        Reference::Memory("r2".to_string()),
-        Reference::Register("r3".to_string()),
+        Reference::Memory("r2".into()),
+        Reference::Register("r3".into()),
     );
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
 }
 
+#[test]
+fn test_fetch_min_max() {
+    let instr = "r1 := fetch_max RLX #x r2";
+    let expected = Instruction::FetchMinMax(
+        Reference::Register("r1".into()),
+        MinMax::Max,
+        MemoryAccessMode::Rlx,
+        Reference::Memory("x".into()),
+        Reference::Register("r2".into()),
+    );
+    assert_eq!(expected, instr.parse::<Instruction>().unwrap());
+    assert_eq!(instr, expected.to_source());
+    let min = "r1:=fetch_min acquire #x r2"
+        .parse::<Instruction>()
+        .unwrap();
+    assert_eq!("r1 := fetch_min ACQ #x r2", min.to_string());
+}
+
+#[test]
+fn test_assignment_without_spaces() {
+    let spaced = "r1 := fai ACQ #x r2".parse::<Instruction>().unwrap();
+    assert_eq!(spaced, "r1:=fai ACQ #x r2".parse::<Instruction>().unwrap());
+    assert_eq!(spaced, "r1 :=fai ACQ #x r2".parse::<Instruction>().unwrap());
+    assert_eq!(
+        "r1 = r2 + r3".parse::<Instruction>().unwrap(),
+        "r1=r2 + r3".parse::<Instruction>().unwrap()
+    );
+    assert_eq!(
+        Instruction::CondJumpCmp(
+            Reference::Register("r1".into()),
+            CmpCommand::Le,
+            Reference::Register("r2".into()),
+            "L".to_string()
+        ),
+        "if r1 <= r2 goto L".parse::<Instruction>().unwrap()
+    );
+    assert_eq!(
+        Some("L".to_string()),
+        LabeledInstruction::from_line("L: r1:=cas REL #x r2 r3", 0, 0)
+            .unwrap()
+            .label
+    );
+}
+
+#[test]
+fn test_mode_less_accesses_use_default_mode() {
+    let x = || Reference::Memory("x".into());
+    let r1 = || Reference::Register("r1".into());
+    assert_eq!(
+        Instruction::Load(MemoryAccessMode::Rlx, x(), r1()),
+        "load #x r1".parse::<Instruction>().unwrap()
+    );
+    let options = ParseOptions {
+        default_mode: MemoryAccessMode::SeqCst,
+    };
+    let program = options
+        .parse_program_str("load #x r1\nstore r1 #x\nload ACQ #x r1", 0)
+        .unwrap();
+    let instructions = program
+        .into_iter()
+        .map(|instruction| instruction.instruction)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            Instruction::Load(MemoryAccessMode::SeqCst, x(), r1()),
+            Instruction::Store(MemoryAccessMode::SeqCst, r1(), x()),
+            Instruction::Load(MemoryAccessMode::Acq, x(), r1()),
+        ],
+        instructions
+    );
+    assert_eq!(
+        Instruction::Store(MemoryAccessMode::SeqCst, r1(), x()),
+        Instruction::parse_with("store r1 #x", options).unwrap()
+    );
+    assert_eq!(
+        Instruction::Store(MemoryAccessMode::Rlx, r1(), x()),
+        "store r1 #x".parse::<Instruction>().unwrap()
+    );
+    assert_eq!(
+        Ok(MemoryAccessMode::SeqCst),
+        "seq_cst".parse().map_err(|_| ())
+    );
+}
+
+#[test]
+fn test_cas2() {
+    let reg = |name: &str| Reference::Register(name.into());
+    let mem = |name: &str| Reference::Memory(name.into());
+    let instr = "r1 r2 := cas2 SEQ_CST #x #y r3 r4 r5 r6";
+    let expected = Instruction::Cas2(
+        [reg("r1"), reg("r2")],
+        MemoryAccessMode::SeqCst,
+        [mem("x"), mem("y")],
+        [reg("r3"), reg("r4")],
+        [reg("r5"), reg("r6")],
+    );
+    let parsed = instr.parse::<Instruction>().unwrap();
+    assert_eq!(expected, parsed);
+    assert_eq!(instr, parsed.to_source());
+    assert_eq!(vec![&mem("x"), &mem("y")], parsed.write_locations());
+}
+
+#[test]
+fn test_print() {
+    assert_eq!(
+        Instruction::Print(Reference::Register("r1".into())),
+        "print r1".parse::<Instruction>().unwrap()
+    );
+    let print = "print #x".parse::<Instruction>().unwrap();
+    assert_eq!(Instruction::Print(Reference::Memory("x".into())), print);
+    assert_eq!("print #x", print.to_source());
+    assert!("print".parse::<Instruction>().is_err());
+}
+
 #[test]
 fn test_fence() {
     let instr = "fence REL_ACQ";
-    let expected = Instruction::Fence(MemoryAccessMode::RelAcq);
+    let expected = Instruction::Fence(MemoryAccessMode::RelAcq, FenceScope::Global);
+    assert_eq!(expected, instr.parse::<Instruction>().unwrap());
+}
+
+#[test]
+fn test_fence_scope() {
+    let local = "fence REL local".parse::<Instruction>().unwrap();
+    assert_eq!(
+        Instruction::Fence(MemoryAccessMode::Rel, FenceScope::Local),
+        local
+    );
+    assert_eq!("fence REL local", local.to_source());
+
+    let global = "fence SEQ_CST global".parse::<Instruction>().unwrap();
+    assert_eq!(
+        Instruction::Fence(MemoryAccessMode::SeqCst, FenceScope::Global),
+        global
+    );
+    assert_eq!("fence SEQ_CST", global.to_source());
+
+    assert!(matches!(
+        "fence REL nearby".parse::<Instruction>(),
+        Err(Error::InvalidCommand(_))
+    ));
+}
+
+#[test]
+fn test_single_file_programs() {
+    let programs = single_file_to_instructions("etc/program_samples/sb.txt".to_string());
+    let expected = programs_to_instructions(vec![
+        "etc/program_samples/sb_0.txt".to_string(),
+        "etc/program_samples/sb_1.txt".to_string(),
+    ]);
+    assert_eq!(expected, programs);
+    assert_eq!(1, programs[1][2].thread_id);
+    assert_eq!(2, programs[1][2].line_index);
+}
+
+#[test]
+fn test_label_only_line() {
+    let instr = "END:".parse::<LabeledInstruction>().unwrap();
+    assert_eq!(Some("END".to_string()), instr.label);
+    assert_eq!(Instruction::Nop, instr.instruction);
+    assert_eq!(Instruction::Nop, "nop".parse::<Instruction>().unwrap());
+}
+
+#[test]
+fn test_parse_programs_str() {
+    let programs = parse_programs_str(&[
+        "r1 = 1\nstore RLX r1 #x\n\nload RLX #y r2\n",
+        "  r1 = 1\n  store RLX r1 #y\n  load RLX #x r2",
+    ])
+    .unwrap();
+    let expected = programs_to_instructions(vec![
+        "etc/program_samples/sb_0.txt".to_string(),
+        "etc/program_samples/sb_1.txt".to_string(),
+    ]);
+    assert_eq!(expected, programs);
+    assert_eq!(2, programs[0][2].line_index);
+}
+
+#[test]
+fn test_parse_program_str_error() {
+    match parse_program_str("r1 = 1\n\nr2 = 1 1", 0) {
+        Err(Error::InvalidLine { line, cause, .. }) => {
+            assert_eq!(3, line);
+            assert!(matches!(*cause, Error::InvalidInstruction(_)));
+        }
+        other => panic!("Expected an invalid line error, got {:?}", other),
+    }
+    match parse_program_str("r1 = 1x", 0) {
+        Err(err @ Error::InvalidLine { .. }) => {
+            assert_eq!("line 1: invalid instruction 'r1 = 1x'", err.to_string())
+        }
+        other => panic!("Expected an invalid line error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_references() {
+    assert!(matches!(
+        "#".parse::<Reference>(),
+        Err(Error::InvalidReference(_))
+    ));
+    assert!(matches!(
+        "".parse::<Reference>(),
+        Err(Error::InvalidReference(_))
+    ));
+    assert!(matches!(
+        "r1 = #".parse::<Instruction>(),
+        Err(Error::InvalidReference(_))
+    ));
+    assert_eq!(
+        Reference::Memory("x_1".into()),
+        "#x_1".parse::<Reference>().unwrap()
+    );
+}
+
+#[test]
+fn test_interned_references_behave_like_names() {
+    let names = ["r1", "#x", "counter_2", "#r1", "a", "#b"];
+    let parsed = names
+        .iter()
+        .map(|name| name.parse::<Reference>().unwrap())
+        .collect::<Vec<_>>();
+    for (name, reference) in names.iter().zip(&parsed) {
+        assert_eq!(*name, reference.to_string());
+        assert_eq!(reference, &name.parse::<Reference>().unwrap());
+    }
+    for (index, reference) in parsed.iter().enumerate() {
+        for (other_index, other) in parsed.iter().enumerate() {
+            assert_eq!(index == other_index, reference == other);
+        }
+    }
+    assert_eq!(6, parsed.iter().collect::<HashSet<_>>().len());
+
+    assert_eq!(r#"Register("r1")"#, format!("{:?}", parsed[0]));
+    assert_eq!(r#"Memory("x")"#, format!("{:?}", parsed[1]));
+    assert_eq!(SymbolId::intern("r1"), "r1".into());
+    assert_eq!("counter_2", SymbolId::intern("counter_2").as_str());
+
+    // Symbols sort by name, whatever order they were interned in
+    let mut symbols = ["zeta", "mid", "alpha"].map(SymbolId::intern);
+    symbols.sort();
+    assert_eq!(["alpha", "mid", "zeta"], symbols.map(SymbolId::as_str));
+
+    // Reading a name never written, or jumping to a label, interns nothing
+    assert_eq!(Value(0), Memory::new().load("never_written"));
+    assert!(SymbolId::find("never_written").is_none());
+    let jump = "if r1 goto jump_target".parse::<Instruction>().unwrap();
+    assert_eq!(
+        Instruction::ConditionalJump(Reference::Register("r1".into()), "jump_target".to_string()),
+        jump
+    );
+    assert!(SymbolId::find("jump_target").is_none());
+}
+
+#[test]
+fn test_malformed_whitespace() {
+    assert_eq!(
+        Instruction::AssignConst(Reference::Register("x".into()), 1),
+        "  x \t=   1  ".parse::<Instruction>().unwrap()
+    );
+    assert!(matches!(
+        "".parse::<Command>(),
+        Err(Error::InvalidCommand(_))
+    ));
+    assert!(matches!(
+        "   ".parse::<LabeledInstruction>(),
+        Err(Error::InvalidInstruction(_))
+    ));
+    assert!(matches!(
+        " x =\t\t1x ".parse::<Instruction>(),
+        Err(Error::InvalidCommand(_))
+    ));
+}
+
+#[test]
+fn test_fence_addr() {
+    let instr = "fence REL #x";
+    let expected = Instruction::FenceAddr(MemoryAccessMode::Rel, Reference::Memory("x".into()));
     assert_eq!(expected, instr.parse::<Instruction>().unwrap());
+    assert!("fence REL x".parse::<Instruction>().is_err());
+}
+
+#[test]
+fn test_thread_mapping() {
+    let mapping =
+        parse_thread_mapping("T2=etc/program_samples/sb_0.txt, T0=etc/program_samples/sb_1.txt")
+            .unwrap();
+    assert_eq!(
+        vec![
+            (2, "etc/program_samples/sb_0.txt".to_string()),
+            (0, "etc/program_samples/sb_1.txt".to_string()),
+        ],
+        mapping
+    );
+    let programs = mapped_programs_to_instructions(mapping);
+    let expected = programs_to_instructions(vec!["etc/program_samples/sb_0.txt".to_string()]);
+    assert_eq!(3, programs.len());
+    assert!(programs[1].is_empty());
+    assert_eq!(expected[0].len(), programs[2].len());
+    assert!(programs[2]
+        .iter()
+        .all(|instruction| instruction.thread_id == 2));
+    assert_eq!(expected[0][1].instruction, programs[2][1].instruction);
+
+    assert!(parse_thread_mapping("T0=a.txt, T0=b.txt").is_err());
+    assert!(parse_thread_mapping("0=a.txt").is_err());
+}
+
+#[test]
+fn test_file_listed_for_several_threads() {
+    let path = "etc/program_samples/program_simple_label.txt".to_string();
+    let programs = programs_to_instructions(vec![path.clone(), path.clone()]);
+    assert_eq!(2, programs.len());
+    for (thread_id, program) in programs.iter().enumerate() {
+        assert_eq!(3, program.len());
+        assert!(program
+            .iter()
+            .enumerate()
+            .all(|(line, instruction)| instruction.thread_id == thread_id
+                && instruction.line_index == line));
+        assert!(missing_labels(program).is_empty());
+        assert_eq!(HashSet::from([0, 1, 2]), reachable_lines(program));
+    }
+    let instructions = |program: &[LabeledInstruction]| {
+        program
+            .iter()
+            .map(|instruction| (instruction.label.clone(), instruction.instruction.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(instructions(&programs[0]), instructions(&programs[1]));
+
+    let programs = mapped_programs_to_instructions(vec![(1, path.clone()), (0, path)]);
+    assert_eq!(0, programs[0][0].thread_id);
+    assert_eq!(1, programs[1][0].thread_id);
+}
+
+#[test]
+fn test_memory_init() {
+    let init = parse_memory_init("#a0 10\n\na1 0x14\n!a2 5\n", None).unwrap();
+    assert_eq!(
+        vec![
+            (Location::Memory("a0".to_string()), 10),
+            (Location::Memory("a1".to_string()), 20),
+        ],
+        init
+    );
+    for (src, line) in [("a0 10\na1 x", 2), ("\na0", 2), ("1a 5", 1), ("a0 1 2", 1)] {
+        let err = parse_memory_init(src, Some("init.txt")).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidMemoryInit { line: l, .. } if l == line),
+            "{}",
+            src
+        );
+    }
+    assert_eq!(
+        "init.txt:2: invalid memory value 'a1 x'",
+        parse_memory_init("a0 10\na1 x", Some("init.txt"))
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+fn test_reachable_lines_skip_block_after_unconditional_jump() {
+    let program =
+        programs_to_instructions(vec!["etc/program_samples/dead_block.txt".to_string()]).remove(0);
+    assert_eq!(HashSet::from([0, 1, 4, 5]), reachable_lines(&program));
+    assert!(missing_labels(&program).is_empty());
+
+    let program =
+        parse_program_str("r1 = 1\nif r1 goto L\nif r1 != r1 goto L\nL: r2 = 1", 0).unwrap();
+    assert_eq!(HashSet::from([0, 1, 2, 3]), reachable_lines(&program));
+}
+
+#[test]
+fn test_missing_labels_are_reported() {
+    let program =
+        parse_program_str("if r1 goto NOWHERE\nif r1 == r1 goto GONE\nr2 = 1", 0).unwrap();
+    assert_eq!(
+        vec![(0, "NOWHERE".to_string()), (1, "GONE".to_string())],
+        missing_labels(&program)
+    );
+    assert_eq!(HashSet::from([0, 1]), reachable_lines(&program));
+}
+
+#[test]
+fn test_memory_access_mode_aliases() {
+    let cases = [
+        ("SEQ_CST", MemoryAccessMode::SeqCst),
+        ("seq_cst", MemoryAccessMode::SeqCst),
+        ("REL", MemoryAccessMode::Rel),
+        ("release", MemoryAccessMode::Rel),
+        ("Release", MemoryAccessMode::Rel),
+        ("ACQ", MemoryAccessMode::Acq),
+        ("acquire", MemoryAccessMode::Acq),
+        ("REL_ACQ", MemoryAccessMode::RelAcq),
+        ("acq_rel", MemoryAccessMode::RelAcq),
+        ("rel_acq", MemoryAccessMode::RelAcq),
+        ("RLX", MemoryAccessMode::Rlx),
+        ("relaxed", MemoryAccessMode::Rlx),
+        ("rlx", MemoryAccessMode::Rlx),
+    ];
+    for (alias, mode) in cases {
+        assert_eq!(
+            Command::MemoryAccess(mode),
+            alias.parse::<Command>().unwrap()
+        );
+    }
+
+    let load = "load acquire #x r1".parse::<Instruction>().unwrap();
+    assert_eq!("load ACQ #x r1", load.to_source());
+}
+
+#[test]
+fn test_referenced_locations() {
+    let programs = parse_programs_str(&[
+        "r1 = 1\nstore RLX r1 #x\nload ACQ #y r2\nr3 = r1 + r2",
+        "r1 := cas SEQ_CST #x r2 r3\nif r1 goto END\nfence REL #z\nEND: r4 := fai RLX #w r5",
+    ])
+    .unwrap();
+    assert_eq!(
+        BTreeSet::from(["w", "x", "y", "z"].map(String::from)),
+        referenced_memory(&programs)
+    );
+    assert_eq!(
+        BTreeSet::from(
+            [
+                (0, "r1"),
+                (0, "r2"),
+                (0, "r3"),
+                (1, "r1"),
+                (1, "r2"),
+                (1, "r3"),
+                (1, "r4"),
+                (1, "r5")
+            ]
+            .map(|(thread_id, name)| (thread_id, name.to_string()))
+        ),
+        referenced_registers(&programs)
+    );
+}
+
+/// Name of the variant, matched exhaustively so that a new variant doesn't
+/// compile until it is listed here and in the grammar.
+fn variant_name(instruction: &Instruction) -> &'static str {
+    match instruction {
+        Instruction::AssignConst(..) => "AssignConst",
+        Instruction::AssignOperation(..) => "AssignOperation",
+        Instruction::ConditionalJump(..) => "ConditionalJump",
+        Instruction::CondJumpCmp(..) => "CondJumpCmp",
+        Instruction::Load(..) => "Load",
+        Instruction::Store(..) => "Store",
+        Instruction::Cas(..) => "Cas",
+        Instruction::Cas2(..) => "Cas2",
+        Instruction::Fai(..) => "Fai",
+        Instruction::FetchMinMax(..) => "FetchMinMax",
+        Instruction::Fence(..) => "Fence",
+        Instruction::FenceAddr(..) => "FenceAddr",
+        Instruction::Print(..) => "Print",
+        Instruction::Nop => "Nop",
+    }
+}
+
+#[test]
+fn test_grammar_matches_parser() {
+    let sample = |token: &TokenKind| match token {
+        TokenKind::Keyword(keyword) => keyword.to_string(),
+        TokenKind::Reference => "r1".to_string(),
+        TokenKind::Memory => "#x".to_string(),
+        TokenKind::Number => "0x1".to_string(),
+        TokenKind::ArithOp => "*".to_string(),
+        TokenKind::CmpOp => "<=".to_string(),
+        TokenKind::AccessMode => "acquire".to_string(),
+        TokenKind::Label => "L".to_string(),
+        TokenKind::FenceScope => "local".to_string(),
+    };
+    let mut covered = HashSet::new();
+    for form in grammar() {
+        let line = form.tokens.iter().map(sample).collect::<Vec<_>>().join(" ");
+        let instruction = line.parse::<Instruction>().unwrap();
+        assert_eq!(form.name, variant_name(&instruction), "{}", line);
+        covered.insert(form.name);
+    }
+
+    let variants = [
+        "x = 1",
+        "x = y + z",
+        "if x goto L",
+        "if x < y goto L",
+        "load RLX #x r1",
+        "store RLX r1 #x",
+        "r1 := cas RLX #x r2 r3",
+        "r1 r2 := cas2 RLX #x #y r3 r4 r5 r6",
+        "r1 := fai RLX #x r2",
+        "r1 := fetch_max RLX #x r2",
+        "fence RLX",
+        "fence RLX #x",
+        "print #x",
+        "nop",
+    ]
+    .map(|line| variant_name(&line.parse::<Instruction>().unwrap()));
+    assert_eq!(HashSet::from(variants), covered);
+}
+
+#[test]
+fn test_syntax_errors_reports_every_invalid_line() {
+    let errors = syntax_errors(
+        "r1 = 1\nfoo\n---\n\nstore RLX r1 #x\nload #x",
+        Some("a.txt"),
+    );
+    assert_eq!(
+        vec![
+            "a.txt:2: invalid instruction 'foo'",
+            "a.txt:6: invalid instruction 'load #x'"
+        ],
+        errors.iter().map(|err| err.to_string()).collect::<Vec<_>>()
+    );
+    assert!(syntax_errors("thread 0:\nnop", None).is_empty());
+}
+
+#[test]
+fn test_duplicate_label_is_an_error() {
+    let err = parse_program_str("L: r1 = 1\nM: r2 = 2\nL: nop\nif r1 goto L", 3).unwrap_err();
+    assert!(matches!(
+        &err,
+        Error::DuplicateLabel { thread_id: 3, label } if label == "L"
+    ));
+    assert_eq!("label 'L' defined twice in thread 3", err.to_string());
+    let errors = syntax_errors("L: nop\nL:\n", None);
+    assert_eq!(1, errors.len());
+}
+
+#[test]
+fn test_distinct_labels_parse() {
+    let program = parse_program_str("L: r1 = 1\nM: r2 = 2\nif r1 goto M", 0).unwrap();
+    assert_eq!(3, program.len());
+    // Labels only need to be unique within a thread
+    let src = "L: nop\nif r1 goto L\n---\nL: nop\nif r1 goto L";
+    assert!(syntax_errors(src, None).is_empty());
+    let programs = isa_interpreter::parse_single_file_str(src, None).unwrap();
+    assert_eq!(Some("L".to_string()), programs[1][0].label);
+}
+
+#[test]
+fn test_disabled_lines_are_skipped() {
+    let program =
+        parse_program_str("r1 = 1\n!r2 = 2\nL: r3 = 3\n!M: nop\nif r1 goto L", 0).unwrap();
+    assert_eq!(
+        vec!["r1 = 1", "r3 = 3", "if r1 goto L"],
+        program
+            .iter()
+            .map(|instruction| instruction.instruction.to_source())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(Some("L".to_string()), program[1].label);
+    assert_eq!(2, program[2].line_index);
+    assert!(missing_labels(&program).is_empty());
+    assert_eq!(HashSet::from([0, 1, 2]), reachable_lines(&program));
+
+    let program = parse_program_str("!M: nop\nif r1 goto M", 0).unwrap();
+    assert_eq!(vec![(0, "M".to_string())], missing_labels(&program));
+
+    let errors = syntax_errors("r1 = 1\n!junk\nfoo", None);
+    assert_eq!(
+        vec!["line 3: invalid instruction 'foo'"],
+        errors.iter().map(|err| err.to_string()).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_parsers_enforce_default_limits() {
+    let max_threads = Limits::default().max_threads;
+    let sources = vec!["nop"; max_threads + 1];
+    let err = parse_programs_str(&sources).unwrap_err();
+    assert!(matches!(err, Error::TooManyThreads { max } if max == max_threads));
+    assert_eq!(
+        format!("more than {} threads", max_threads),
+        err.to_string()
+    );
+    assert!(parse_programs_str(&sources[1..]).is_ok());
+
+    let src = "nop\n".repeat(Limits::default().max_program_len + 1);
+    let err =
+        isa_interpreter::parse_single_file_str(&format!("nop\n---\n{}", src), None).unwrap_err();
+    assert!(matches!(err, Error::ProgramTooLong { thread_id: 1, .. }));
+}
+
+#[test]
+fn test_custom_limits() {
+    let programs = parse_programs_str(&["load RLX #x r1", "nop\nstore RLX r1 #y\nnop"]).unwrap();
+    let limits = Limits {
+        max_threads: 2,
+        max_program_len: 3,
+        max_addresses: 2,
+    };
+    assert!(limits.check(&programs).is_ok());
+
+    let fewer_threads = Limits {
+        max_threads: 1,
+        ..limits
+    };
+    assert!(matches!(
+        fewer_threads.check(&programs),
+        Err(Error::TooManyThreads { max: 1 })
+    ));
+
+    let shorter = Limits {
+        max_program_len: 2,
+        ..limits
+    };
+    let err = shorter.check(&programs).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ProgramTooLong {
+            thread_id: 1,
+            max: 2
+        }
+    ));
+    assert_eq!(
+        "program of thread 1 longer than 2 instructions",
+        err.to_string()
+    );
+
+    let fewer_addresses = Limits {
+        max_addresses: 1,
+        ..limits
+    };
+    let err = fewer_addresses.check(&programs).unwrap_err();
+    assert!(matches!(err, Error::TooManyAddresses { max: 1 }));
+    assert_eq!("more than 1 distinct memory addresses", err.to_string());
+}
+
+#[test]
+fn test_long_program_parses_every_line() {
+    let src = (0..25_000)
+        .map(|index| match index % 1000 {
+            0 => format!("L{}: r1 = {}\n\n", index, index),
+            _ => format!("r1 = {}\n! r2 = 2\nstore RLX r1 #x\n", index),
+        })
+        .collect::<String>();
+    let program = parse_program_str(&src, 0).unwrap();
+    assert_eq!(49_975, program.len());
+    let last = program.last().unwrap();
+    assert_eq!(49_974, last.line_index);
+    assert_eq!("store RLX r1 #x", last.instruction.to_source());
+
+    let path =
+        std::env::temp_dir().join(format!("isa_interpreter_long_{}.txt", std::process::id()));
+    std::fs::write(&path, &src).unwrap();
+    let programs = programs_to_instructions(vec![path.to_string_lossy().to_string(); 2]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        vec![program.len(); 2],
+        programs.iter().map(Vec::len).collect::<Vec<_>>()
+    );
+    assert!(program == programs[0]);
+    assert_eq!(1, programs[1].last().unwrap().thread_id);
+}
+
+#[test]
+fn test_tokenizer_keeps_parse_results() {
+    let parsed = [
+        ("r1:=fai ACQ #x r2", "r1 := fai ACQ #x r2"),
+        ("r1:= cas SEQ_CST #x r2 r3", "r1 := cas SEQ_CST #x r2 r3"),
+        (
+            "r1 r2:=cas2 RLX #x #y r3 r4 r5 r6",
+            "r1 r2 := cas2 RLX #x #y r3 r4 r5 r6",
+        ),
+        ("x=1", "x = 1"),
+        ("x= y + z", "x = y + z"),
+        ("\tif x <= y goto L ", "if x <= y goto L"),
+        ("if x == y goto L", "if x == y goto L"),
+    ];
+    for (line, source) in parsed {
+        assert_eq!(source, line.parse::<Instruction>().unwrap().to_source());
+    }
+
+    let too_long = "r1 r2 := cas2 RLX #x #y r3 r4 r5 r6 r7";
+    assert!(matches!(
+        too_long.parse::<Instruction>(),
+        Err(Error::InvalidInstruction(line)) if line == too_long
+    ));
+    assert!(matches!(
+        "r1 r2 := cas2 RLX #x #y r3 r4 r5 r6 1x".parse::<Instruction>(),
+        Err(Error::InvalidCommand(token)) if token == "1x"
+    ));
+    // Comparisons are only split at whitespace
+    assert!(matches!(
+        "if x!=y goto L".parse::<Instruction>(),
+        Err(Error::InvalidReference(token)) if token == "x!=y"
+    ));
+    assert!(matches!(
+        "x == 1".parse::<Instruction>(),
+        Err(Error::InvalidInstruction(_))
+    ));
+    assert!(matches!(
+        "x :== 1".parse::<Instruction>(),
+        Err(Error::InvalidReference(token)) if token == ":=="
+    ));
+}
+
+#[test]
+fn test_parse_many_lines() {
+    let lines = [
+        "r1 = 0x2a",
+        "r3 = r1 * r2",
+        "if r1 < r2 goto L",
+        "load ACQ #x r1",
+        "store REL r1 #x",
+        "r1 := cas SEQ_CST #x r2 r3",
+        "r1 r2 := cas2 RLX #x #y r3 r4 r5 r6",
+        "r1 := fai ACQ_REL #x r2",
+        "r1 := fetch_min RLX #x r2",
+        "fence SEQ_CST local",
+        "print #x",
+        "nop",
+    ];
+    let expected = lines.map(|line| line.parse::<Instruction>().unwrap());
+    for round in 0..20_000 {
+        let index = round % lines.len();
+        assert_eq!(
+            expected[index],
+            lines[index].parse::<Instruction>().unwrap()
+        );
+    }
 }